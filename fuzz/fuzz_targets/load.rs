@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pdbparser::{msf, pdb};
+use std::io::Cursor;
+
+// Every parse path must return an error on malformed input rather than panic.
+fuzz_target!(|data: &[u8]| {
+    let mut reader = Cursor::new(data);
+    let Ok(msf) = msf::MSF::load(&mut reader) else {
+        return;
+    };
+    if let Ok(pdb) = pdb::PDB::pdb_stream(&mut reader, &msf) {
+        let _ = pdb.string_table(&mut reader, &msf);
+        let _ = pdb.link_info(&mut reader, &msf);
+        for source in pdb.injected_sources(&mut reader, &msf).unwrap_or_default() {
+            let _ = pdb.read_injected_source(&mut reader, &msf, &source);
+        }
+    }
+    for stream in 0..msf.num_streams().min(64) {
+        let _ = msf.read_stream(&mut reader, stream);
+    }
+});
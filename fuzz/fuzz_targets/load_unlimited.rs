@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pdbparser::msf::{self, ParseLimits};
+use pdbparser::pdb;
+use std::io::Cursor;
+
+// Same as `load`, but with every ParseLimits check disabled, so that the parsers' own bounds
+// checks are what stands between hostile sizes and a panic.
+fuzz_target!(|data: &[u8]| {
+    let _ = msf::check(&mut Cursor::new(data), &ParseLimits::unlimited());
+    let mut reader = Cursor::new(data);
+    let Ok(msf) = msf::MSF::load_with_limits(&mut reader, ParseLimits::unlimited()) else {
+        return;
+    };
+    if let Ok(pdb) = pdb::PDB::pdb_stream(&mut reader, &msf) {
+        let _ = pdb.string_table(&mut reader, &msf);
+        let _ = pdb.link_info(&mut reader, &msf);
+        for source in pdb.injected_sources(&mut reader, &msf).unwrap_or_default() {
+            let _ = pdb.read_injected_source(&mut reader, &msf, &source);
+        }
+    }
+    for stream in 0..msf.num_streams().min(64) {
+        let _ = msf.read_stream(&mut reader, stream);
+    }
+});
@@ -0,0 +1,416 @@
+//! LZX decompression, as used by CAB folders.
+//!
+//! An LZX stream is a sequence of blocks (verbatim, aligned offset or uncompressed) whose output
+//! is cut into 32KB frames. The bitstream is read as little-endian 16-bit words, most significant
+//! bit first, and is realigned to a word boundary at the end of every frame. Matches refer back
+//! into a sliding window that is never reset within a folder. Calls to `e8` instructions are
+//! stored with absolute targets and translated back to relative ones frame by frame.
+
+use crate::cab::{Error, Result};
+
+/// Uncompressed size of every frame but the last.
+const FRAME_SIZE: usize = 32768;
+const MIN_MATCH: usize = 2;
+const NUM_CHARS: usize = 256;
+/// Match lengths below this are stored in the main element itself.
+const NUM_PRIMARY_LENGTHS: usize = 7;
+const NUM_SECONDARY_LENGTHS: usize = 249;
+const PRETREE_NUM_ELEMENTS: usize = 20;
+const ALIGNED_NUM_ELEMENTS: usize = 8;
+const MAX_CODE_LENGTH: usize = 16;
+
+const BLOCKTYPE_VERBATIM: u32 = 1;
+const BLOCKTYPE_ALIGNED: u32 = 2;
+const BLOCKTYPE_UNCOMPRESSED: u32 = 3;
+
+/// Reads the LZX bitstream: 16-bit little-endian words, most significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Byte offset of the next word.
+    pos: usize,
+    /// Unread bits, aligned to the top of the buffer.
+    buffer: u64,
+    bits_left: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buffer: 0,
+            bits_left: 0,
+        }
+    }
+    /// Make sure at least `n` (at most 32) bits are buffered. Reading past the end of the input
+    /// yields zero bits, which the block decoder rejects as an invalid block type soon enough.
+    fn ensure(&mut self, n: u32) {
+        while self.bits_left < n {
+            let lo = self.data.get(self.pos).copied().unwrap_or(0);
+            let hi = self.data.get(self.pos + 1).copied().unwrap_or(0);
+            self.pos += 2;
+            self.buffer |= (u16::from_le_bytes([lo, hi]) as u64) << (48 - self.bits_left);
+            self.bits_left += 16;
+        }
+    }
+    fn peek(&mut self, n: u32) -> u32 {
+        self.ensure(n);
+        (self.buffer >> (64 - n)) as u32
+    }
+    fn remove(&mut self, n: u32) {
+        self.buffer <<= n;
+        self.bits_left -= n;
+    }
+    fn read(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        let ret = self.peek(n);
+        self.remove(n);
+        ret
+    }
+    /// Discard the partially read word at the end of a frame.
+    fn align(&mut self) {
+        if self.bits_left > 0 {
+            self.ensure(16);
+        }
+        let partial = self.bits_left & 15;
+        self.remove(partial);
+    }
+    /// Drop the bit buffer so the input can be read byte by byte, for uncompressed blocks.
+    /// This always skips between 1 and 16 bits.
+    fn align_to_bytes(&mut self) {
+        if self.bits_left == 0 {
+            self.ensure(16);
+        }
+        self.buffer = 0;
+        self.bits_left = 0;
+    }
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let ret = self
+            .pos
+            .checked_add(n)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(Error::Lzx("uncompressed block runs past the end of the input"))?;
+        self.pos += n;
+        Ok(ret)
+    }
+}
+
+/// A canonical Huffman code, decoded a bit at a time.
+#[derive(Default)]
+struct Tree {
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Tree {
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut tree = Tree::default();
+        for &len in lengths {
+            tree.counts[len as usize] += 1;
+        }
+        tree.counts[0] = 0;
+        // Reject over-subscribed codes; incomplete ones are fine as long as unused codes never appear.
+        let mut left = 1_i32;
+        for len in 1..=MAX_CODE_LENGTH {
+            left = (left << 1) - tree.counts[len] as i32;
+            if left < 0 {
+                return Err(Error::Lzx("over-subscribed Huffman code"));
+            }
+        }
+        for len in 1..=MAX_CODE_LENGTH as u8 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, l)| **l == len) {
+                tree.symbols.push(symbol as u16);
+            }
+        }
+        Ok(tree)
+    }
+    fn decode(&self, bits: &mut BitReader) -> Result<usize> {
+        let word = bits.peek(MAX_CODE_LENGTH as u32);
+        let (mut code, mut first, mut index) = (0_i32, 0_i32, 0_i32);
+        for len in 1..=MAX_CODE_LENGTH {
+            code |= ((word >> (MAX_CODE_LENGTH - len)) & 1) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                bits.remove(len as u32);
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Lzx("invalid Huffman code"))
+    }
+}
+
+/// Decompress an LZX folder of `size` bytes. `data` is the folder's compressed data blocks
+/// concatenated.
+pub(super) fn decompress(window_bits: u32, data: &[u8], size: usize) -> Result<Vec<u8>> {
+    if !(15..=21).contains(&window_bits) {
+        return Err(Error::Lzx("window size must be between 2^15 and 2^21 bytes"));
+    }
+    let window_size = 1_usize << window_bits;
+    let (extra_bits, position_base) = position_tables();
+    let num_position_slots = match window_bits {
+        20 => 42,
+        21 => 50,
+        _ => window_bits as usize * 2,
+    };
+    let num_main = NUM_CHARS + num_position_slots * 8;
+
+    let mut bits = BitReader::new(data);
+    let mut main_lengths = vec![0_u8; num_main];
+    let mut length_lengths = vec![0_u8; NUM_SECONDARY_LENGTHS];
+    let mut main_tree = Tree::default();
+    let mut length_tree = Tree::default();
+    let mut aligned_tree = Tree::default();
+    let mut repeats = [1_usize; 3];
+    let mut block_type = 0;
+    let mut block_length = 0_usize;
+    let mut block_remaining = 0_usize;
+    let mut translate_e8 = false;
+
+    // The window holds the untranslated output, which later matches refer back to.
+    // Neither is allocated up front, as `size` comes straight from the cabinet.
+    let mut window: Vec<u8> = Vec::new();
+    let mut out = Vec::new();
+
+    let e8_file_size = match bits.read(1) {
+        0 => 0,
+        _ => (bits.read(16) << 16) | bits.read(16),
+    };
+
+    for (frame, frame_start) in (0..size).step_by(FRAME_SIZE).enumerate() {
+        let frame_end = size.min(frame_start + FRAME_SIZE);
+        while window.len() < frame_end {
+            if block_remaining == 0 {
+                if block_type == BLOCKTYPE_UNCOMPRESSED && block_length & 1 == 1 {
+                    bits.read_bytes(1)?;
+                }
+                block_type = bits.read(3);
+                block_length = ((bits.read(16) << 8) | bits.read(8)) as usize;
+                block_remaining = block_length;
+                match block_type {
+                    BLOCKTYPE_ALIGNED | BLOCKTYPE_VERBATIM => {
+                        if block_type == BLOCKTYPE_ALIGNED {
+                            let lengths: Vec<u8> =
+                                (0..ALIGNED_NUM_ELEMENTS).map(|_| bits.read(3) as u8).collect();
+                            aligned_tree = Tree::new(&lengths)?;
+                        }
+                        read_lengths(&mut bits, &mut main_lengths[..NUM_CHARS])?;
+                        read_lengths(&mut bits, &mut main_lengths[NUM_CHARS..])?;
+                        main_tree = Tree::new(&main_lengths)?;
+                        if main_lengths[0xE8] != 0 {
+                            translate_e8 = true;
+                        }
+                        read_lengths(&mut bits, &mut length_lengths)?;
+                        length_tree = Tree::new(&length_lengths)?;
+                    }
+                    BLOCKTYPE_UNCOMPRESSED => {
+                        translate_e8 = true;
+                        bits.align_to_bytes();
+                        for repeat in repeats.iter_mut() {
+                            let bytes = bits.read_bytes(4)?;
+                            *repeat = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+                        }
+                    }
+                    _ => return Err(Error::Lzx("invalid block type")),
+                }
+            }
+
+            // Matches may run past the end of the frame, but not past the end of the block.
+            let run_start = window.len();
+            let run_end = run_start + block_remaining.min(frame_end - run_start);
+            if block_type == BLOCKTYPE_UNCOMPRESSED {
+                window.extend_from_slice(bits.read_bytes(run_end - run_start)?);
+            }
+            while window.len() < run_end {
+                let main_element = main_tree.decode(&mut bits)?;
+                if main_element < NUM_CHARS {
+                    window.push(main_element as u8);
+                    continue;
+                }
+                let main_element = main_element - NUM_CHARS;
+                let mut match_length = main_element & NUM_PRIMARY_LENGTHS;
+                if match_length == NUM_PRIMARY_LENGTHS {
+                    match_length += length_tree.decode(&mut bits)?;
+                }
+                match_length += MIN_MATCH;
+
+                let position_slot = main_element >> 3;
+                let match_offset = match position_slot {
+                    0 => repeats[0],
+                    1 => {
+                        repeats.swap(0, 1);
+                        repeats[0]
+                    }
+                    2 => {
+                        repeats.swap(0, 2);
+                        repeats[0]
+                    }
+                    _ => {
+                        let extra = extra_bits[position_slot];
+                        let mut offset = position_base[position_slot] as usize - 2;
+                        if block_type == BLOCKTYPE_ALIGNED && extra >= 3 {
+                            offset += (bits.read(extra - 3) as usize) << 3;
+                            offset += aligned_tree.decode(&mut bits)?;
+                        } else {
+                            offset += bits.read(extra) as usize;
+                        }
+                        repeats = [offset, repeats[0], repeats[1]];
+                        offset
+                    }
+                };
+
+                if match_offset == 0 || match_offset > window.len() || match_offset > window_size {
+                    return Err(Error::Lzx("match refers back past the start of the window"));
+                }
+                if window.len() + match_length - run_start > block_remaining {
+                    return Err(Error::Lzx("match runs past the end of the block"));
+                }
+                let start = window.len() - match_offset;
+                for i in 0..match_length {
+                    let byte = window[start + i];
+                    window.push(byte);
+                }
+            }
+            block_remaining -= window.len() - run_start;
+        }
+
+        bits.align();
+        let mut data = window[frame_start..frame_end].to_vec();
+        if translate_e8 && e8_file_size != 0 && frame < 32768 {
+            undo_e8_translation(&mut data, frame_start, e8_file_size);
+        }
+        out.extend_from_slice(&data);
+    }
+    Ok(out)
+}
+
+/// The number of extra offset bits and the base offset of every position slot.
+fn position_tables() -> ([u32; 51], [u32; 51]) {
+    let mut extra_bits = [0_u32; 51];
+    let mut position_base = [0_u32; 51];
+    let mut extra = 0;
+    for i in (0..50).step_by(2) {
+        extra_bits[i] = extra;
+        extra_bits[i + 1] = extra;
+        if i != 0 && extra < 17 {
+            extra += 1;
+        }
+    }
+    extra_bits[50] = extra;
+    let mut base = 0;
+    for i in 0..51 {
+        position_base[i] = base;
+        base += 1 << extra_bits[i];
+    }
+    (extra_bits, position_base)
+}
+
+/// Read a pretree, then use it to update `lengths`, which are stored as deltas from the
+/// lengths of the previous block.
+fn read_lengths(bits: &mut BitReader, lengths: &mut [u8]) -> Result<()> {
+    let pretree_lengths: Vec<u8> = (0..PRETREE_NUM_ELEMENTS).map(|_| bits.read(4) as u8).collect();
+    let pretree = Tree::new(&pretree_lengths)?;
+    let mut i = 0;
+    while i < lengths.len() {
+        let (count, value) = match pretree.decode(bits)? {
+            17 => (bits.read(4) as usize + 4, 0),
+            18 => (bits.read(5) as usize + 20, 0),
+            19 => {
+                let count = bits.read(1) as usize + 4;
+                let z = pretree.decode(bits)?;
+                if z > 16 {
+                    return Err(Error::Lzx("invalid pretree code"));
+                }
+                (count, ((lengths[i] as usize + 17 - z) % 17) as u8)
+            }
+            z => (1, ((lengths[i] as usize + 17 - z) % 17) as u8),
+        };
+        // Runs past the end of the tree are tolerated, as in Microsoft's decoder.
+        let end = lengths.len().min(i + count);
+        lengths[i..end].fill(value);
+        i = end;
+    }
+    Ok(())
+}
+
+/// Turn the absolute `e8` call targets of one frame starting at `position` back into relative ones.
+fn undo_e8_translation(data: &mut [u8], position: usize, file_size: u32) {
+    if data.len() <= 10 {
+        return;
+    }
+    let end = data.len() - 10;
+    let mut i = 0;
+    while i < end {
+        if data[i] != 0xE8 {
+            i += 1;
+            continue;
+        }
+        // Work in i64: the translation size is a full u32, and the result is cut back to 32 bits.
+        let current = (position + i) as i64;
+        let bytes = &mut data[i + 1..i + 5];
+        let absolute = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64;
+        if absolute >= -current && absolute < file_size as i64 {
+            let relative = if absolute >= 0 {
+                absolute - current
+            } else {
+                absolute + file_size as i64
+            };
+            bytes.copy_from_slice(&(relative as i32).to_le_bytes());
+        }
+        i += 5;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uncompressed_block() {
+        // No e8 translation, block type 3 of 11 bytes, padding to the word boundary, then the
+        // three repeat offsets and the data padded to an even length.
+        let mut data = vec![0x00, 0x30, 0xB0, 0x00];
+        for repeat in [1_u32, 1, 1] {
+            data.extend(repeat.to_le_bytes());
+        }
+        data.extend(b"hello world\0");
+        assert_eq!(decompress(16, &data, 11).unwrap(), b"hello world");
+        // The block promises more data than there is.
+        assert!(decompress(16, &data[..20], 11).is_err());
+    }
+
+    #[test]
+    fn reject_bad_input() {
+        assert!(matches!(decompress(14, &[], 0), Err(Error::Lzx(_))));
+        assert!(matches!(decompress(22, &[], 0), Err(Error::Lzx(_))));
+        // Block type 0.
+        assert!(matches!(decompress(16, &[0; 16], 16), Err(Error::Lzx("invalid block type"))));
+    }
+
+    #[test]
+    fn e8_translation() {
+        let file_size = 10000_u32;
+        let mut data = Vec::new();
+        for absolute in [0x100_i32, -5, 20000, -0x100, 9999] {
+            data.push(0xE8);
+            data.extend(absolute.to_le_bytes());
+        }
+        data.extend([0xE8; 10]);
+        undo_e8_translation(&mut data, 100, file_size);
+        let targets: Vec<i32> = data[..25]
+            .chunks(5)
+            .map(|call| i32::from_le_bytes(call[1..].try_into().unwrap()))
+            .collect();
+        // Targets in range become relative to the instruction's position in the folder, others
+        // are left alone.
+        assert_eq!(targets, [0x100 - 100, -5 + 10000, 20000, -0x100, 9999 - 120]);
+        // The last 10 bytes of a frame are never translated.
+        assert_eq!(data[25..], [0xE8; 10]);
+    }
+}
@@ -0,0 +1,349 @@
+//! Extraction of Microsoft cabinet (CAB) files, such as the `.pd_` files found in symbol stores.
+//!
+//! Only single-cabinet archives are supported. Folders may be stored uncompressed or compressed
+//! with MSZIP or LZX; Quantum is not supported.
+
+mod lzx;
+
+use crate::util;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Result type alias for this module
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+/// All of the errors that could possible be returned from this module
+pub enum Error {
+    /// Error consuming from the underlying reader.
+    Consume(std::io::Error),
+    /// A field of the cabinet could not be read.
+    Field(util::FieldError),
+    /// The file does not start with the "MSCF" signature.
+    NotCabinet,
+    /// The cabinet continues in another cabinet file.
+    SpannedCabinet,
+    /// A folder uses a compression method other than none, MSZIP or LZX.
+    UnsupportedCompression(u16),
+    /// A file refers to a folder that does not exist.
+    FolderOutOfBounds(u16),
+    /// A data block's checksum does not match its contents.
+    Checksum,
+    /// An MSZIP block is corrupt.
+    Mszip(&'static str),
+    /// The LZX data of a folder is corrupt.
+    Lzx(&'static str),
+    /// A file lies outside of its folder's data.
+    FileOutOfBounds(String),
+    /// A folder expands to more bytes than the caller allows.
+    FolderTooLarge(u64),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Consume(error)
+    }
+}
+
+impl From<util::FieldError> for Error {
+    fn from(error: util::FieldError) -> Self {
+        Error::Field(error)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Consume(e) => e.fmt(f),
+            Error::Field(e) => e.fmt(f),
+            Error::NotCabinet => write!(f, "not a cabinet file"),
+            Error::SpannedCabinet => write!(f, "cabinets spanning several files are not supported"),
+            Error::UnsupportedCompression(c) => write!(f, "unsupported folder compression {}", c),
+            Error::FolderOutOfBounds(folder) => write!(f, "folder {} does not exist", folder),
+            Error::Checksum => write!(f, "data block checksum mismatch"),
+            Error::Mszip(e) => write!(f, "corrupt MSZIP data: {}", e),
+            Error::Lzx(e) => write!(f, "corrupt LZX data: {}", e),
+            Error::FileOutOfBounds(name) => write!(f, "file {} lies outside of its folder", name),
+            Error::FolderTooLarge(size) => write!(f, "folder expands to {} bytes, more than allowed", size),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Consume(e) => e.source(),
+            Error::Field(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// Signature at the very start of every cabinet.
+const CAB_SIGNATURE: &[u8; 4] = b"MSCF";
+
+const FLAG_PREV_CABINET: u16 = 0x0001;
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+const COMPRESS_NONE: u16 = 0;
+const COMPRESS_MSZIP: u16 = 1;
+const COMPRESS_LZX: u16 = 3;
+
+/// A file extracted from a cabinet.
+#[derive(Debug)]
+pub struct CabFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+struct Folder {
+    /// File offset of the first data block.
+    data_offset: u32,
+    num_blocks: u16,
+    compression: u16,
+}
+
+/// Whether `reader` starts with the cabinet signature. The reader is left where it was.
+pub fn is_cabinet(reader: &mut (impl Read + Seek)) -> std::io::Result<bool> {
+    let start = reader.stream_position()?;
+    let mut signature = [0_u8; 4];
+    let ret = match reader.read_exact(&mut signature) {
+        Ok(()) => &signature == CAB_SIGNATURE,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(e),
+    };
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(ret)
+}
+
+/// Decompress every file of the cabinet into memory.
+pub fn extract(reader: &mut (impl Read + Seek)) -> Result<Vec<CabFile>> {
+    extract_with_limit(reader, u64::MAX)
+}
+
+/// Decompress every file of the cabinet into memory, refusing to expand any folder to more
+/// than `max_folder_size` bytes.
+pub fn extract_with_limit(reader: &mut (impl Read + Seek), max_folder_size: u64) -> Result<Vec<CabFile>> {
+    let signature = util::consume!(reader, 4, "Signature")?;
+    if &signature != CAB_SIGNATURE {
+        return Err(Error::NotCabinet);
+    }
+    let _reserved1 = util::consume!(reader, u32, "Reserved")?;
+    let _cabinet_size = util::consume!(reader, u32, "Cabinet Size")?;
+    let _reserved2 = util::consume!(reader, u32, "Reserved")?;
+    let files_offset = util::consume!(reader, u32, "Files Offset")?;
+    let _reserved3 = util::consume!(reader, u32, "Reserved")?;
+    let _version = util::consume!(reader, u16, "Version")?;
+    let num_folders = util::consume!(reader, u16, "Number of Folders")?;
+    let num_files = util::consume!(reader, u16, "Number of Files")?;
+    let flags = util::consume!(reader, u16, "Flags")?;
+    let _set_id = util::consume!(reader, u16, "Set ID")?;
+    let _cabinet_index = util::consume!(reader, u16, "Cabinet Index")?;
+    if flags & (FLAG_PREV_CABINET | FLAG_NEXT_CABINET) != 0 {
+        return Err(Error::SpannedCabinet);
+    }
+    let (folder_reserve, data_reserve) = if flags & FLAG_RESERVE_PRESENT != 0 {
+        let header_reserve = util::consume!(reader, u16, "Header Reserve Size")?;
+        let folder_reserve = util::consume!(reader, u8, "Folder Reserve Size")?;
+        let data_reserve = util::consume!(reader, u8, "Data Reserve Size")?;
+        reader.seek(SeekFrom::Current(header_reserve as i64))?;
+        (folder_reserve, data_reserve)
+    } else {
+        (0, 0)
+    };
+
+    let mut folders = Vec::new();
+    for _ in 0..num_folders {
+        folders.push(Folder {
+            data_offset: util::consume!(reader, u32, "Folder Data Offset")?,
+            num_blocks: util::consume!(reader, u16, "Folder Data Blocks")?,
+            compression: util::consume!(reader, u16, "Folder Compression")?,
+        });
+        reader.seek(SeekFrom::Current(folder_reserve as i64))?;
+    }
+
+    reader.seek(SeekFrom::Start(files_offset as u64))?;
+    // (name, folder, offset in folder, size)
+    let mut entries = Vec::new();
+    for _ in 0..num_files {
+        let size = util::consume!(reader, u32, "File Size")?;
+        let offset = util::consume!(reader, u32, "File Folder Offset")?;
+        let folder = util::consume!(reader, u16, "File Folder")?;
+        let _date = util::consume!(reader, u16, "File Date")?;
+        let _time = util::consume!(reader, u16, "File Time")?;
+        let _attributes = util::consume!(reader, u16, "File Attributes")?;
+        let name = read_name(reader)?;
+        entries.push((name, folder, offset, size));
+    }
+
+    let mut contents: Vec<Option<Vec<u8>>> = (0..folders.len()).map(|_| None).collect();
+    let mut ret = Vec::new();
+    for (name, folder, offset, size) in entries {
+        let data = match contents.get_mut(folder as usize) {
+            Some(Some(data)) => data,
+            Some(slot) => slot.insert(decompress_folder(
+                reader,
+                &folders[folder as usize],
+                data_reserve,
+                max_folder_size,
+            )?),
+            None => return Err(Error::FolderOutOfBounds(folder)),
+        };
+        let (start, end) = (offset as usize, offset as usize + size as usize);
+        let data = data
+            .get(start..end)
+            .ok_or_else(|| Error::FileOutOfBounds(name.clone()))?
+            .to_vec();
+        ret.push(CabFile { name, data });
+    }
+    Ok(ret)
+}
+
+/// Read a NUL-terminated file name.
+fn read_name(reader: &mut impl Read) -> Result<String> {
+    let mut name = Vec::new();
+    loop {
+        match util::consume!(reader, u8, "File Name")? {
+            0 => return Ok(String::from_utf8_lossy(&name).into_owned()),
+            byte => name.push(byte),
+        }
+    }
+}
+
+/// Read and decompress every data block of `folder`.
+fn decompress_folder(
+    reader: &mut (impl Read + Seek),
+    folder: &Folder,
+    data_reserve: u8,
+    max_size: u64,
+) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(folder.data_offset as u64))?;
+    let mut blocks = Vec::new();
+    for _ in 0..folder.num_blocks {
+        let checksum = util::consume!(reader, u32, "Data Checksum")?;
+        let header = util::consume!(reader, 4, "Data Header")?;
+        let mut reserve = vec![0_u8; data_reserve as usize];
+        reader.read_exact(&mut reserve)?;
+        let compressed_size = u16::from_le_bytes([header[0], header[1]]);
+        let uncompressed_size = u16::from_le_bytes([header[2], header[3]]);
+        let mut data = vec![0_u8; compressed_size as usize];
+        reader.read_exact(&mut data)?;
+        if checksum != 0 {
+            let mut header = header.to_vec();
+            header.extend_from_slice(&reserve);
+            if data_checksum(&header, data_checksum(&data, 0)) != checksum {
+                return Err(Error::Checksum);
+            }
+        }
+        blocks.push((data, uncompressed_size as usize));
+    }
+    let size: u64 = blocks.iter().map(|(_, size)| *size as u64).sum();
+    if size > max_size {
+        return Err(Error::FolderTooLarge(size));
+    }
+
+    match folder.compression & 0x000F {
+        COMPRESS_NONE => Ok(blocks.into_iter().flat_map(|(data, _)| data).collect()),
+        COMPRESS_MSZIP => mszip(&blocks),
+        COMPRESS_LZX => {
+            let window_bits = (folder.compression >> 8 & 0x1F) as u32;
+            let size = blocks.iter().map(|(_, size)| size).sum();
+            let data: Vec<u8> = blocks.into_iter().flat_map(|(data, _)| data).collect();
+            lzx::decompress(window_bits, &data, size)
+        }
+        _ => Err(Error::UnsupportedCompression(folder.compression)),
+    }
+}
+
+/// Decompress MSZIP blocks: each is a "CK" signature followed by a complete deflate stream that
+/// may refer back into the previous block's output.
+fn mszip(blocks: &[(Vec<u8>, usize)]) -> Result<Vec<u8>> {
+    use miniz_oxide::inflate::core::{decompress, inflate_flags, DecompressorOxide};
+    use miniz_oxide::inflate::TINFLStatus;
+
+    let mut out = Vec::new();
+    for (data, size) in blocks {
+        let deflated = data.strip_prefix(b"CK").ok_or(Error::Mszip("missing CK signature"))?;
+        let start = out.len();
+        out.resize(start + size, 0);
+        let (status, _, written) = decompress(
+            &mut DecompressorOxide::new(),
+            deflated,
+            &mut out,
+            start,
+            inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+        );
+        if status != TINFLStatus::Done {
+            return Err(Error::Mszip("corrupt deflate stream"));
+        }
+        if written != *size {
+            return Err(Error::Mszip("block does not decompress to its stated size"));
+        }
+    }
+    Ok(out)
+}
+
+/// The cabinet checksum: the XOR of the data's little-endian u32s, with any trailing bytes taken
+/// in big-endian order.
+fn data_checksum(data: &[u8], seed: u32) -> u32 {
+    let mut chunks = data.chunks_exact(4);
+    let mut sum = seed;
+    for chunk in &mut chunks {
+        sum ^= u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    sum ^ chunks.remainder().iter().fold(0, |acc, byte| acc << 8 | *byte as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // The fixtures are single-file cabinets: one MSZIP folder of three blocks whose deflate
+    // streams refer back into the previous block, one LZX folder with e8 translation enabled,
+    // and one uncompressed folder whose data block was corrupted after its checksum was computed.
+    const MSZIP: &[u8] = include_bytes!("../../testdata/cab/mszip.cab");
+    const LZX_E8: &[u8] = include_bytes!("../../testdata/cab/lzx_e8.cab");
+    const LZX_E8_EXPECTED: &[u8] = include_bytes!("../../testdata/cab/lzx_e8.expected");
+    const BAD_CHECKSUM: &[u8] = include_bytes!("../../testdata/cab/bad_checksum.cab");
+
+    #[test]
+    fn mszip_blocks() {
+        let text: Vec<u8> = (0..1500)
+            .flat_map(|i| format!("{i:05} the quick brown fox jumps over the lazy dog\n").into_bytes())
+            .collect();
+        let files = extract(&mut Cursor::new(MSZIP)).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "text.txt");
+        assert_eq!(files[0].data, text);
+    }
+
+    #[test]
+    fn lzx_e8() {
+        let files = extract(&mut Cursor::new(LZX_E8)).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].name, "e8.bin");
+        assert_eq!(files[0].data, LZX_E8_EXPECTED);
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert!(matches!(extract(&mut Cursor::new(BAD_CHECKSUM)), Err(Error::Checksum)));
+    }
+
+    #[test]
+    fn limits_and_signature() {
+        let mut reader = Cursor::new(MSZIP);
+        assert!(is_cabinet(&mut reader).unwrap());
+        assert!(matches!(extract_with_limit(&mut reader, 1000), Err(Error::FolderTooLarge(75000))));
+        let mut not_cab = Cursor::new(&b"MSCX"[..]);
+        assert!(!is_cabinet(&mut not_cab).unwrap());
+        assert!(matches!(extract(&mut not_cab), Err(Error::NotCabinet)));
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(data_checksum(&[1, 0, 0, 0, 2, 0, 0, 0], 0), 3);
+        assert_eq!(data_checksum(&[0xAA, 0xBB, 0xCC], 0x00FF_0000), 0x0055_BBCC);
+    }
+}
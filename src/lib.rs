@@ -0,0 +1,10 @@
+//! This crate parses a PDB file
+
+pub mod cab;
+pub mod msf;
+pub mod pdb;
+pub mod pe;
+pub mod symstore;
+mod util;
+
+pub use util::FieldError;
@@ -1,13 +1,11 @@
-//! This crate parses a PDB file
+//! Command line front-end for the pdbparser crate
 
+use pdbparser::{msf, pdb, pe, symstore};
 use std::env;
 use std::fs::File;
-use std::io::{BufReader};
+use std::io::{BufWriter, Read, Seek};
 use std::path::{Path, PathBuf};
-
-mod util;
-mod msf;
-mod pdb;
+use std::process::ExitCode;
 
 #[derive(Debug)]
 ///Errors for the entire crate.
@@ -18,24 +16,519 @@ enum ReaderError {
     NotMsfFile(PathBuf, msf::Error),
     /// PDB file could not be parsed
     NotPDBFile(PathBuf, pdb::Error),
+    /// Image file could not be parsed
+    NotPeImage(PathBuf, pe::Error),
+    /// The symbol store could not be read or updated.
+    Store(symstore::Error),
+    /// Requested stream is neither a stream number nor a named stream.
+    UnknownStream(String),
+    /// Could not write an output file.
+    Write(PathBuf, std::io::Error),
+    /// The command line is malformed.
+    Usage(&'static str),
+}
+
+impl std::fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ReaderError::Open(path, _) => write!(f, "could not open {}", path.display()),
+            ReaderError::NotMsfFile(path, _) => write!(f, "{} is not an MSF file", path.display()),
+            ReaderError::NotPDBFile(path, _) => write!(f, "{} is not a PDB file", path.display()),
+            ReaderError::NotPeImage(path, _) => write!(f, "{} is not a PE image", path.display()),
+            ReaderError::Store(e) => e.fmt(f),
+            ReaderError::UnknownStream(name) => write!(f, "no stream named {:?}", name),
+            ReaderError::Write(path, _) => write!(f, "could not write {}", path.display()),
+            ReaderError::Usage(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ReaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReaderError::Open(_, e) | ReaderError::Write(_, e) => Some(e),
+            ReaderError::NotMsfFile(_, e) => Some(e),
+            ReaderError::NotPDBFile(_, e) => Some(e),
+            ReaderError::NotPeImage(_, e) => Some(e),
+            ReaderError::Store(e) => e.source(),
+            ReaderError::UnknownStream(_) | ReaderError::Usage(_) => None,
+        }
+    }
 }
 
-fn main() -> Result<(), ReaderError> {
+fn main() -> ExitCode {
     let args: Vec<String> = env::args().collect();
-    if args.len() <= 1 {
-        println!("Usage: pdb <file.pdb>");
-        return Ok(());
-    }
-    println!("Reading file {:?}", args[1]);
-    let file = &args[1];
-    let mut reader = BufReader::new(
-        File::open(file).map_err(|x| ReaderError::Open(Path::new(file).to_path_buf(), x))?,
-    );
-    let mut msf = msf::MSF::load(&mut reader)
+    match run(&args) {
+        Ok(code) => code,
+        Err(e) => {
+            // Print the whole chain, e.g. "x.pdb is not a PDB file: stream 1 offset 0x1c: ...".
+            let mut message = e.to_string();
+            let mut source = std::error::Error::source(&e);
+            while let Some(e) = source {
+                message = format!("{}: {}", message, e);
+                source = e.source();
+            }
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: &[String]) -> Result<ExitCode, ReaderError> {
+    match args.get(1).map(String::as_str) {
+        Some("fsck") if args.len() == 3 => fsck(&args[2]),
+        Some("streams") if args.len() == 3 => list_streams(&args[2]),
+        Some("extract") if args.len() >= 4 => extract(&args[2], &args[3..]),
+        Some("compact") if args.len() == 4 => compact(&args[2], &args[3]),
+        Some("history") if args.len() == 3 => history(&args[2]),
+        Some("strings") if args.len() == 3 => strings(&args[2]),
+        Some("sources") if args.len() == 3 || (args.len() == 5 && args[3] == "-o") => {
+            sources(&args[2], args.get(4).map(Path::new))
+        }
+        Some("match") if args.len() == 4 => match_image(&args[2], &args[3]),
+        Some("store") if args.len() >= 4 => store(&args[2], &args[3..]),
+        Some("lookup") if args.len() == 5 => lookup(&args[2], &args[3], &args[4]),
+        Some("fetch") if args.len() == 4 => fetch(&args[2], &args[3]),
+        Some(file) if args.len() == 2 => dump(file),
+        _ => {
+            eprintln!("Usage: pdb <file.pdb>");
+            eprintln!("       pdb fsck <file.pdb>");
+            eprintln!("       pdb streams <file.pdb>");
+            eprintln!("       pdb extract <file.pdb> [-o <dir>] [--previous] <stream number or name>...");
+            eprintln!("       pdb compact <in.pdb> <out.pdb>");
+            eprintln!("       pdb history <file.pdb>");
+            eprintln!("       pdb strings <file.pdb>");
+            eprintln!("       pdb sources <file.pdb> [-o <dir>]");
+            eprintln!("       pdb match <image.dll> <file.pdb>");
+            eprintln!("       pdb store <store dir> <file.pdb>...");
+            eprintln!("       pdb lookup <store dir> <name> <key>");
+            eprintln!("       pdb fetch <symbol path> <image.dll>");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Open a PDB, expanding CAB-compressed `.pd_` files into memory.
+fn open(file: &str) -> Result<msf::MsfSource, ReaderError> {
+    msf::MsfSource::open(file).map_err(|x| match x {
+        msf::Error::Map(e) => ReaderError::Open(Path::new(file).to_path_buf(), e),
+        e => ReaderError::NotMsfFile(Path::new(file).to_path_buf(), e),
+    })
+}
+
+fn load(file: &str) -> Result<(msf::MsfSource, msf::MSF), ReaderError> {
+    let mut reader = open(file)?;
+    let msf = msf::MSF::load(&mut reader)
+        .map_err(|x| ReaderError::NotMsfFile(Path::new(file).to_path_buf(), x))?;
+    Ok((reader, msf))
+}
+
+/// Print a summary of the container and the PDB info stream.
+fn dump(file: &str) -> Result<ExitCode, ReaderError> {
+    let (mut reader, msf) = load(file)?;
+    let pdb = pdb::PDB::pdb_stream(&mut reader, &msf)
+        .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+
+    let hdr = pdb.info().header();
+    println!("File:          {}", file);
+    println!("MSF version:   {:?}", msf.version());
+    println!("Block size:    {}", msf.block_size());
+    println!("Streams:       {}", msf.num_streams());
+    println!("PDB version:   {:?} ({})", hdr.version(), hdr.version() as u32);
+    println!("Signature:     0x{:08x}", hdr.signature());
+    println!("Age:           {}", hdr.age());
+    println!("Unique id:     {}", symstore::guid_string(hdr.unique_id()));
+    println!("Symbol key:    {}", hdr.symbol_server_key());
+    let features: Vec<String> = pdb
+        .info()
+        .feature_codes()
+        .codes()
+        .iter()
+        .map(|code| match code {
+            pdb::pdbstream::PDBFeatureCode::Unknown(code) => format!("0x{:08x}", code),
+            code => format!("{:?}", code),
+        })
+        .collect();
+    println!("Features:      {}", features.join(", "));
+    println!("Named streams:");
+    for (name, stream) in pdb.info().named_streams() {
+        println!("  {:>5}  {}", stream, name);
+    }
+    let link_info = pdb
+        .link_info(&mut reader, &msf)
+        .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+    if let Some(link_info) = link_info {
+        println!("Link info:");
+        println!("  Directory:   {}", link_info.cwd());
+        println!("  Command:     {}", link_info.command());
+        println!("  Output file: {}", link_info.output_file());
+        for lib in link_info.libraries() {
+            println!("  Library:     {}", lib);
+        }
+    }
+    if pdb.info().named_stream_map().get("/names").is_some() {
+        let strings = pdb
+            .string_table(&mut reader, &msf)
+            .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+        println!(
+            "String table:  {} strings, hash version {}",
+            strings.len(),
+            strings.hash_version()
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Named streams of the PDB info stream, or nothing if it can't be parsed.
+fn stream_names(reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Vec<(String, u32)> {
+    pdb::PDB::pdb_stream(reader, msf)
+        .map(|pdb| pdb.info().named_streams())
+        .unwrap_or_default()
+}
+
+/// List every stream of the StreamDirectory.
+fn list_streams(file: &str) -> Result<ExitCode, ReaderError> {
+    let (mut reader, msf) = load(file)?;
+    for line in stream_table(&mut reader, &msf) {
+        println!("{}", line);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// One line per stream with its size, blocks and name, after a header line.
+fn stream_table(reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Vec<String> {
+    let names = stream_names(reader, msf);
+    let mut lines = vec![format!(
+        "{:>6} {:>10} {:>7}  {:<20} Blocks",
+        "Stream", "Size", "#Blocks", "Name"
+    )];
+    for stream in 0..msf.num_streams() {
+        let size = match msf.stream_size(stream) {
+            Ok(Some(size)) => size.to_string(),
+            _ => "nil".to_string(),
+        };
+        let blocks = msf.stream_blocks(stream).unwrap_or_default();
+        let name = names
+            .iter()
+            .find(|(_, s)| *s == stream)
+            .map_or("", |(name, _)| name.as_str());
+        lines.push(format!(
+            "{:>6} {:>10} {:>7}  {:<20} {:?}",
+            stream,
+            size,
+            blocks.len(),
+            name,
+            blocks
+        ));
+    }
+    lines
+}
+
+/// Write the requested streams, given by number or by name, to files.
+///
+/// With `--previous` the streams are read as they were before the last commit.
+fn extract(file: &str, args: &[String]) -> Result<ExitCode, ReaderError> {
+    let (mut reader, mut msf) = load(file)?;
+    if args.iter().any(|arg| arg == "--previous") {
+        msf = pdb::PDB::old_directory(&mut reader, &msf)
+            .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+    }
+    let names = stream_names(&mut reader, &msf);
+    let mut out_dir = PathBuf::from(".");
+    let mut requested = args.iter();
+    while let Some(arg) = requested.next() {
+        if arg == "-o" {
+            out_dir = requested
+                .next()
+                .map(PathBuf::from)
+                .ok_or(ReaderError::Usage("extract: -o needs a directory"))?;
+            continue;
+        }
+        if arg == "--previous" {
+            continue;
+        }
+        let (stream, out_name) = match arg.parse::<u32>() {
+            Ok(stream) => (stream, format!("stream{}", stream)),
+            Err(_) => {
+                let stream = names
+                    .iter()
+                    .find(|(name, _)| name == arg)
+                    .map(|(_, s)| *s)
+                    .ok_or_else(|| ReaderError::UnknownStream(arg.clone()))?;
+                (stream, arg.trim_start_matches('/').replace(['/', '\\'], "_"))
+            }
+        };
+        let data = msf
+            .read_stream(&mut reader, stream)
+            .map_err(|x| ReaderError::NotMsfFile(Path::new(file).to_path_buf(), x))?;
+        let out_path = out_dir.join(out_name);
+        std::fs::create_dir_all(&out_dir)
+            .and_then(|_| std::fs::write(&out_path, &data))
+            .map_err(|x| ReaderError::Write(out_path.clone(), x))?;
+        println!("stream {} ({} bytes) -> {}", stream, data.len(), out_path.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Check that a PDB belongs to a `.dll` or `.exe`, using the image's CodeView record.
+fn match_image(image: &str, file: &str) -> Result<ExitCode, ReaderError> {
+    let image_path = Path::new(image).to_path_buf();
+    let mut image_reader = File::open(image).map_err(|x| ReaderError::Open(image_path.clone(), x))?;
+    let pe = pe::PeImage::load(&mut image_reader).map_err(|x| ReaderError::NotPeImage(image_path, x))?;
+    let (mut reader, msf) = load(file)?;
+    let pdb = pdb::PDB::pdb_stream(&mut reader, &msf)
+        .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+    // A compressed `.pd_` is checked by the name of the PDB it expands to.
+    let pdb_name = reader.expanded_name().unwrap_or(file);
+    match pe.check_pdb(pdb.info().header(), Some(pdb_name)) {
+        Ok(()) => {
+            println!("{} matches {}", file, image);
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(mismatch) => {
+            println!("{} does not match {}: {}", file, image, mismatch);
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Add PDBs to a symbol store, creating it if needed, as a single transaction.
+fn store(root: &str, files: &[String]) -> Result<ExitCode, ReaderError> {
+    let mut entries = Vec::new();
+    for file in files {
+        let (mut reader, msf) = load(file)?;
+        let pdb = pdb::PDB::pdb_stream(&mut reader, &msf)
+            .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+        entries.push(symstore::Entry::pdb(file, pdb.info().header()));
+    }
+    let store = symstore::SymStore::create(root, false).map_err(ReaderError::Store)?;
+    let id = store
+        .add(&entries, "pdbparser", "", "")
+        .map_err(ReaderError::Store)?;
+    for entry in &entries {
+        println!("{}\\{}", entry.name, entry.key);
+    }
+    println!("transaction {:010}", id);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Find a file in a symbol store by name and key.
+fn lookup(root: &str, name: &str, key: &str) -> Result<ExitCode, ReaderError> {
+    let store = symstore::SymStore::open(root).map_err(ReaderError::Store)?;
+    match store.lookup(name, key).map_err(ReaderError::Store)? {
+        Some(path) => {
+            println!("{}", path.display());
+            Ok(ExitCode::SUCCESS)
+        }
+        None => {
+            println!("{}\\{} is not in {}", name, key, root);
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Find the PDB of an image along a symbol path, downloading it if needed.
+fn fetch(symbol_path: &str, image: &str) -> Result<ExitCode, ReaderError> {
+    let image_path = Path::new(image).to_path_buf();
+    let mut image_reader = File::open(image).map_err(|x| ReaderError::Open(image_path.clone(), x))?;
+    let pe = pe::PeImage::load(&mut image_reader).map_err(|x| ReaderError::NotPeImage(image_path, x))?;
+    let Some(codeview) = pe.codeview() else {
+        println!("{} names no PDB", image);
+        return Ok(ExitCode::FAILURE);
+    };
+    let path = symstore::SymbolPath::parse(symbol_path).map_err(ReaderError::Store)?;
+    let resolver = symstore::SymbolResolver::new(path);
+    match resolver.find(codeview).map_err(ReaderError::Store)? {
+        Some(found) => {
+            println!("{}", found.display());
+            Ok(ExitCode::SUCCESS)
+        }
+        None => {
+            println!("{}\\{} not found", codeview.file_name(), codeview.symbol_server_key());
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Validate the whole container and report every problem found.
+fn fsck(file: &str) -> Result<ExitCode, ReaderError> {
+    let mut reader = open(file)?;
+    let (msf, mut problems) = msf::check(&mut reader, &msf::ParseLimits::default());
+    if let Some(msf) = msf {
+        problems.extend(pdb::PDB::check_info_stream(&mut reader, &msf));
+    }
+    for problem in &problems {
+        println!("{}: {}", file, problem);
+    }
+    if problems.is_empty() {
+        println!("{}: no problems found", file);
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("{}: {} problem(s) found", file, problems.len());
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Rewrite a PDB with contiguous streams and no free blocks.
+fn compact(file: &str, out_file: &str) -> Result<ExitCode, ReaderError> {
+    let (mut reader, msf) = load(file)?;
+    let out = File::create(out_file).map_err(|x| ReaderError::Write(PathBuf::from(out_file), x))?;
+    let mut writer = BufWriter::new(out);
+    msf::compact(&mut reader, &msf, &mut writer)
         .map_err(|x| ReaderError::NotMsfFile(Path::new(file).to_path_buf(), x))?;
+    let old_size = std::fs::metadata(file).map_or(0, |m| m.len());
+    let new_size = writer.get_ref().metadata().map_or(0, |m| m.len());
+    println!("{} ({} bytes) -> {} ({} bytes)", file, old_size, out_file, new_size);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// List the `/names` string table by offset.
+fn strings(file: &str) -> Result<ExitCode, ReaderError> {
+    let (mut reader, msf) = load(file)?;
+    let strings = pdb::PDB::pdb_stream(&mut reader, &msf)
+        .and_then(|pdb| pdb.string_table(&mut reader, &msf))
+        .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+    for (offset, string) in strings.entries() {
+        println!("0x{:08x}  {}", offset, string);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// List the injected source files, or extract them under `out_dir`.
+fn sources(file: &str, out_dir: Option<&Path>) -> Result<ExitCode, ReaderError> {
+    let (mut reader, msf) = load(file)?;
+    let not_pdb = |x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x);
+    let pdb = pdb::PDB::pdb_stream(&mut reader, &msf).map_err(not_pdb)?;
+    let sources = pdb.injected_sources(&mut reader, &msf).map_err(not_pdb)?;
+    let Some(out_dir) = out_dir else {
+        println!("{:>10} {:<12} {:<8}  Virtual path", "Size", "Compression", "CRC");
+        for source in &sources {
+            println!(
+                "{:>10} {:<12} {:08x}  {}",
+                source.size(),
+                format!("{:?}", source.compression()),
+                source.crc(),
+                source.virtual_name()
+            );
+        }
+        return Ok(ExitCode::SUCCESS);
+    };
+    for source in &sources {
+        let data = pdb
+            .read_injected_source(&mut reader, &msf, source)
+            .map_err(not_pdb)?;
+        // Keep the virtual path's directories, but never leave out_dir.
+        let mut out_path = out_dir.to_path_buf();
+        for part in source.virtual_name().split(['/', '\\']) {
+            match part.replace(':', "") {
+                part if part.is_empty() || part == "." || part == ".." => {}
+                part => out_path.push(part),
+            }
+        }
+        out_path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&out_path, &data))
+            .map_err(|x| ReaderError::Write(out_path.clone(), x))?;
+        println!("{} ({} bytes) -> {}", source.virtual_name(), data.len(), out_path.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
 
-    let mut _pdb = pdb::PDB::pdb_stream(&mut reader, &mut msf)
+/// Show how the streams changed in the last commit, using the previous StreamDirectory in stream 0.
+fn history(file: &str) -> Result<ExitCode, ReaderError> {
+    let (mut reader, msf) = load(file)?;
+    let old = pdb::PDB::old_directory(&mut reader, &msf)
         .map_err(|x| ReaderError::NotPDBFile(Path::new(file).to_path_buf(), x))?;
+    let size = |size: Option<u32>| size.map_or("nil".to_string(), |size| size.to_string());
+    println!("Streams: {} -> {}", old.num_streams(), msf.num_streams());
+    for change in msf.diff(&old) {
+        match change {
+            msf::StreamChange::Added { stream, size: s } => {
+                println!("{:>6}  added    {}", stream, size(s))
+            }
+            msf::StreamChange::Removed { stream, size: s } => {
+                println!("{:>6}  removed  {}", stream, size(s))
+            }
+            msf::StreamChange::Resized {
+                stream,
+                old_size,
+                new_size,
+            } => println!("{:>6}  resized  {} -> {}", stream, size(old_size), size(new_size)),
+            msf::StreamChange::Moved { stream, size: s } => {
+                println!("{:>6}  moved    {}", stream, size(s))
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NTDLL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/pdb/ntdll.pdb");
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// An empty directory for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pdbparser-main-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn usage() {
+        for args in [&["pdb"][..], &["pdb", "extract", NTDLL], &["pdb", "lookup", "x"], &["pdb", "fsck", "a", "b"]] {
+            assert_eq!(run(&self::args(args)).unwrap(), ExitCode::FAILURE, "{:?}", args);
+        }
+    }
 
-    Ok(())
+    #[test]
+    fn streams() {
+        let (mut reader, msf) = load(NTDLL).unwrap();
+        let lines = stream_table(&mut reader, &msf);
+        assert_eq!(lines.len(), msf.num_streams() as usize + 1);
+        assert_eq!(lines[0], "Stream       Size #Blocks  Name                 Blocks");
+        assert_eq!(lines[2], "     1         93       1                       [1570]");
+        assert_eq!(lines[6], format!("     5          0       0  {:<20} []", "/LinkInfo"));
+        assert_eq!(lines[234], format!("   233         45       1  {:<20} [1531]", "/names"));
+
+        // Nil streams have no size, and a file without a PDB info stream has no names.
+        let mut builder = msf::MsfBuilder::new(512).unwrap();
+        builder.add_stream(b"data".to_vec());
+        builder.remove_stream(1);
+        let mut data = std::io::Cursor::new(Vec::new());
+        builder.write(&mut data).unwrap();
+        data.set_position(0);
+        let msf = msf::MSF::load(&mut data).unwrap();
+        let lines = stream_table(&mut data, &msf);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("     0          4       1  "));
+        assert_eq!(lines[2], format!("     1 {:>10}       0  {:<20} []", "nil", ""));
+    }
+
+    #[test]
+    fn extract_streams() {
+        let dir = temp_dir("extract");
+        let out = dir.to_string_lossy().into_owned();
+        let code = run(&args(&["pdb", "extract", NTDLL, "-o", &out, "1", "/names"])).unwrap();
+        assert_eq!(code, ExitCode::SUCCESS);
+        let (mut reader, msf) = load(NTDLL).unwrap();
+        assert_eq!(std::fs::read(dir.join("stream1")).unwrap(), msf.read_stream(&mut reader, 1).unwrap());
+        assert_eq!(std::fs::read(dir.join("names")).unwrap(), msf.read_stream(&mut reader, 233).unwrap());
+
+        assert!(matches!(
+            run(&args(&["pdb", "extract", NTDLL, "/nonexistent"])),
+            Err(ReaderError::UnknownStream(name)) if name == "/nonexistent"
+        ));
+        assert!(matches!(
+            run(&args(&["pdb", "extract", NTDLL, "-o"])),
+            Err(ReaderError::Usage(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,358 @@
+use crate::msf::{
+    check_block_size, Error, MSFStreamReader, MsfVersion, Result, SuperBlock, MSF, MSF_MAGIC,
+    NIL_STREAM_SIZE,
+};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Block holding the SuperBlock.
+const SUPER_BLOCK_INDEX: u32 = 0;
+/// Block index (within each interval) of the first free block map.
+const FPM1_INDEX: u32 = 1;
+/// Block index (within each interval) of the second free block map.
+const FPM2_INDEX: u32 = 2;
+/// Block size used when rewriting MSFZ files, which have no block size of their own.
+const DEFAULT_BLOCK_SIZE: u32 = 4096;
+
+/// Builds a brand-new MSF file out of a set of numbered streams.
+///
+/// Blocks are laid out the way the Microsoft linker does it: the SuperBlock
+/// in block 0, both free block maps in blocks 1 and 2 (repeated every
+/// `block_size` blocks), every stream's data, the StreamDirectory and
+/// finally the block map listing the StreamDirectory blocks.
+#[derive(Debug)]
+pub struct MsfBuilder {
+    block_size: u32,
+    /// None for nil (deleted) streams.
+    streams: Vec<Option<Vec<u8>>>,
+}
+
+impl MsfBuilder {
+    /// Create an empty builder. Valid block sizes are 512, 1024, 2048 and 4096 bytes.
+    pub fn new(block_size: u32) -> Result<Self> {
+        Ok(MsfBuilder {
+            block_size: check_block_size(block_size)?,
+            streams: Vec::new(),
+        })
+    }
+    /// Append a stream, returning its stream number.
+    pub fn add_stream(&mut self, data: Vec<u8>) -> u32 {
+        self.streams.push(Some(data));
+        (self.streams.len() - 1) as u32
+    }
+    /// Replace the contents of stream `stream_no`, growing the directory with nil streams if needed.
+    pub fn set_stream(&mut self, stream_no: u32, data: Vec<u8>) {
+        *self.stream_mut(stream_no) = data;
+    }
+    /// Mark stream `stream_no` as nil, growing the directory if needed.
+    pub fn remove_stream(&mut self, stream_no: u32) {
+        self.grow_to(stream_no);
+        self.streams[stream_no as usize] = None;
+    }
+    /// Get a writable buffer for stream `stream_no`, creating an empty stream if it is nil or missing.
+    pub fn stream_mut(&mut self, stream_no: u32) -> &mut Vec<u8> {
+        self.grow_to(stream_no);
+        self.streams[stream_no as usize].get_or_insert_with(Vec::new)
+    }
+    /// Number of streams, including nil streams.
+    pub fn num_streams(&self) -> u32 {
+        self.streams.len() as u32
+    }
+    fn grow_to(&mut self, stream_no: u32) {
+        if self.streams.len() <= stream_no as usize {
+            self.streams.resize(stream_no as usize + 1, None);
+        }
+    }
+
+    /// Assign blocks to every stream and write the complete MSF file.
+    pub fn write(&self, writer: &mut (impl Write + Seek)) -> Result<()> {
+        let sizes: Vec<Option<usize>> = self
+            .streams
+            .iter()
+            .map(|s| s.as_ref().map(Vec::len))
+            .collect();
+        write_msf(self.block_size, &sizes, writer, |writer, stream, blocks| {
+            match &self.streams[stream as usize] {
+                Some(data) => write_blocks(writer, self.block_size, blocks, data),
+                None => Ok(()),
+            }
+        })
+    }
+}
+
+/// Rewrite `msf` into `writer` with every stream's blocks contiguous and free blocks dropped.
+///
+/// Stream numbers and contents are unchanged, except for stream 0: the previous
+/// StreamDirectory it holds names blocks of the old layout, so it is written out empty. Streams
+/// are copied a block at a time, so memory use does not grow with the size of the file. MSF
+/// 2.00 files are written out as MSF 7.00, and MSFZ files are decompressed into an MSF 7.00 file
+/// with 4096-byte blocks.
+pub fn compact<W: Write + Seek>(
+    reader: &mut (impl Read + Seek),
+    msf: &MSF,
+    writer: &mut W,
+) -> Result<()> {
+    let sizes = (0..msf.num_streams())
+        .map(|stream| match stream {
+            0 => Ok(Some(0)),
+            _ => Ok(msf.stream_size(stream)?.map(|size| size as usize)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let block_size = match msf.sb.version {
+        MsfVersion::Msfz => DEFAULT_BLOCK_SIZE,
+        MsfVersion::Msf700 | MsfVersion::Msf200 => msf.sb.block_size,
+    };
+    let mut buf = vec![0_u8; block_size as usize];
+    write_msf(block_size, &sizes, writer, |writer, stream, blocks| {
+        if blocks.is_empty() {
+            return Ok(());
+        }
+        let mut msfsr = MSFStreamReader::new(&mut *reader, msf, stream)?;
+        let mut remaining = msfsr.stream_size() as usize;
+        for block in blocks {
+            let len = remaining.min(buf.len());
+            msfsr.read_exact(&mut buf[..len])?;
+            write_blocks(writer, block_size, &[*block], &buf[..len])?;
+            remaining -= len;
+        }
+        Ok(())
+    })
+}
+
+/// Lay out and write an MSF file whose streams have the given sizes (None for nil streams).
+///
+/// `write_stream` is called once per stream, in stream order, to fill the blocks assigned to it.
+fn write_msf<W: Write + Seek>(
+    block_size: u32,
+    sizes: &[Option<usize>],
+    writer: &mut W,
+    mut write_stream: impl FnMut(&mut W, u32, &[u32]) -> Result<()>,
+) -> Result<()> {
+    // Sizes are stored as u32s, with u32::MAX meaning a nil stream.
+    if let Some((stream, size)) = sizes
+        .iter()
+        .enumerate()
+        .find_map(|(i, size)| size.filter(|s| *s as u64 >= NIL_STREAM_SIZE as u64).map(|s| (i, s)))
+    {
+        return Err(Error::StreamTooLarge(stream as u32, size as u64));
+    }
+    let mut layout = Layout::new(block_size);
+
+    let stream_blocks: Vec<Vec<u32>> = sizes
+        .iter()
+        .map(|size| layout.allocate(size.unwrap_or(0)))
+        .collect();
+
+    let directory = serialize_directory(sizes, &stream_blocks);
+    let directory_blocks = layout.allocate(directory.len());
+    if directory_blocks.len() * 4 > block_size as usize {
+        return Err(Error::StreamDirectoryTooLarge);
+    }
+    let block_map_addr = layout.allocate(1)[0];
+    let block_map: Vec<u8> = directory_blocks
+        .iter()
+        .flat_map(|b| b.to_le_bytes())
+        .collect();
+
+    let num_blocks = layout.finish();
+    let sb = SuperBlock {
+        file_magic: *MSF_MAGIC,
+        version: MsfVersion::Msf700,
+        directory_blocks: Vec::new(),
+        block_size,
+        free_block_map: FPM1_INDEX,
+        num_blocks,
+        num_directory_bytes: directory.len() as u32,
+        unknown: 0,
+        block_map_addr,
+    };
+
+    write_blocks(writer, block_size, &[SUPER_BLOCK_INDEX], &sb.to_bytes())?;
+    let fpm = free_block_map(block_size, num_blocks);
+    for (interval, chunk) in fpm.chunks(block_size as usize).enumerate() {
+        let interval_start = interval as u32 * block_size;
+        write_blocks(writer, block_size, &[interval_start + FPM1_INDEX], chunk)?;
+        write_blocks(writer, block_size, &[interval_start + FPM2_INDEX], chunk)?;
+    }
+    for (stream, blocks) in stream_blocks.iter().enumerate() {
+        write_stream(writer, stream as u32, blocks)?;
+    }
+    write_blocks(writer, block_size, &directory_blocks, &directory)?;
+    write_blocks(writer, block_size, &[block_map_addr], &block_map)?;
+    writer.flush().map_err(Error::Write)
+}
+
+/// Serialize the StreamDirectory: stream count, stream sizes, then each stream's block list.
+fn serialize_directory(sizes: &[Option<usize>], stream_blocks: &[Vec<u32>]) -> Vec<u8> {
+    let mut dir = Vec::new();
+    dir.extend_from_slice(&(sizes.len() as u32).to_le_bytes());
+    for size in sizes {
+        let size = size.map_or(NIL_STREAM_SIZE, |s| s as u32);
+        dir.extend_from_slice(&size.to_le_bytes());
+    }
+    for block in stream_blocks.iter().flatten() {
+        dir.extend_from_slice(&block.to_le_bytes());
+    }
+    dir
+}
+
+/// Build the free block map for a file where every one of the `num_blocks` blocks is in use.
+///
+/// The map is the concatenation of one FPM block per interval; a set bit means the block is free.
+fn free_block_map(block_size: u32, num_blocks: u32) -> Vec<u8> {
+    let num_intervals = num_blocks.div_ceil(block_size);
+    let mut fpm = vec![0xFF_u8; (num_intervals * block_size) as usize];
+    for block in 0..num_blocks as usize {
+        fpm[block / 8] &= !(1 << (block % 8));
+    }
+    fpm
+}
+
+/// Write `data` across `blocks`, zero padding the final block.
+fn write_blocks(
+    writer: &mut (impl Write + Seek),
+    block_size: u32,
+    blocks: &[u32],
+    data: &[u8],
+) -> Result<()> {
+    let bs = block_size as usize;
+    for (i, block) in blocks.iter().enumerate() {
+        let start = (i * bs).min(data.len());
+        let end = ((i + 1) * bs).min(data.len());
+        let mut buf = vec![0_u8; bs];
+        buf[..end - start].copy_from_slice(&data[start..end]);
+        writer
+            .seek(SeekFrom::Start(*block as u64 * bs as u64))
+            .map_err(Error::Seek)?;
+        writer.write_all(&buf).map_err(Error::Write)?;
+    }
+    Ok(())
+}
+
+/// Hands out blocks in file order, skipping the free block map blocks of every interval.
+struct Layout {
+    block_size: u32,
+    next_block: u32,
+}
+
+impl Layout {
+    fn new(block_size: u32) -> Self {
+        Layout {
+            block_size,
+            next_block: FPM2_INDEX + 1,
+        }
+    }
+    fn is_fpm_block(&self, block: u32) -> bool {
+        let in_interval = block % self.block_size;
+        in_interval == FPM1_INDEX || in_interval == FPM2_INDEX
+    }
+    /// Allocate enough blocks to hold `num_bytes` bytes.
+    fn allocate(&mut self, num_bytes: usize) -> Vec<u32> {
+        let count = num_bytes.div_ceil(self.block_size as usize);
+        let mut blocks = Vec::with_capacity(count);
+        while blocks.len() < count {
+            if !self.is_fpm_block(self.next_block) {
+                blocks.push(self.next_block);
+            }
+            self.next_block += 1;
+        }
+        blocks
+    }
+    /// Total number of blocks, making sure the last interval's free block maps are part of the file.
+    fn finish(self) -> u32 {
+        let mut num_blocks = self.next_block;
+        while self.is_fpm_block(num_blocks) {
+            num_blocks += 1;
+        }
+        num_blocks
+    }
+}
+
+impl SuperBlock {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(0x38);
+        ret.extend_from_slice(&self.file_magic);
+        for field in [
+            self.block_size,
+            self.free_block_map,
+            self.num_blocks,
+            self.num_directory_bytes,
+            self.unknown,
+            self.block_map_addr,
+        ] {
+            ret.extend_from_slice(&field.to_le_bytes());
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::FreeBlockMap;
+    use std::io::Cursor;
+
+    fn build(builder: &MsfBuilder) -> Vec<u8> {
+        let mut file = Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        file.into_inner()
+    }
+
+    #[test]
+    fn round_trip_across_fpm_interval() {
+        let mut builder = MsfBuilder::new(512).unwrap();
+        builder.add_stream(Vec::new());
+        builder.add_stream(b"info".to_vec());
+        // 600 blocks of 512 bytes run past the FPM blocks of the second interval (513, 514).
+        let big: Vec<u8> = (0..600 * 512 + 17).map(|i| (i % 251) as u8).collect();
+        let big_stream = builder.add_stream(big.clone());
+        builder.remove_stream(5);
+        let data = build(&builder);
+
+        let mut reader = Cursor::new(&data[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        assert_eq!(msf.num_streams(), 6);
+        assert_eq!(msf.read_stream(&mut reader, 1).unwrap(), b"info");
+        assert_eq!(msf.read_stream(&mut reader, big_stream).unwrap(), big);
+        assert_eq!(msf.stream_size(5).unwrap(), None);
+        let blocks = msf.stream_blocks(big_stream).unwrap();
+        assert!(blocks.iter().any(|b| *b > 514));
+        assert!(!blocks.contains(&513) && !blocks.contains(&514));
+
+        let fpm = FreeBlockMap::load(&mut reader, &msf).unwrap();
+        assert_eq!(fpm.validate(&msf), Vec::new());
+        assert!(fpm.free_blocks().is_empty());
+    }
+
+    #[test]
+    fn stream_too_large() {
+        let sizes = [Some(1), Some(NIL_STREAM_SIZE as usize)];
+        let result = write_msf(4096, &sizes, &mut Cursor::new(Vec::new()), |_, _, _| Ok(()));
+        assert!(matches!(result, Err(Error::StreamTooLarge(1, size)) if size == NIL_STREAM_SIZE as u64));
+    }
+
+    #[test]
+    fn compact_drops_previous_directory() {
+        let mut builder = MsfBuilder::new(512).unwrap();
+        builder.add_stream(vec![0xAB; 100]);
+        builder.add_stream(b"info".to_vec());
+        builder.add_stream(vec![7; 3000]);
+        let data = build(&builder);
+        let mut reader = Cursor::new(&data[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+
+        let mut out = Cursor::new(Vec::new());
+        compact(&mut reader, &msf, &mut out).unwrap();
+        let out = out.into_inner();
+        let mut reader = Cursor::new(&out[..]);
+        let compacted = MSF::load(&mut reader).unwrap();
+        assert_eq!(compacted.stream_size(0).unwrap(), Some(0));
+        assert!(matches!(compacted.previous(&mut reader), Err(Error::NoPreviousDirectory)));
+        assert_eq!(compacted.read_stream(&mut reader, 1).unwrap(), b"info");
+        assert_eq!(compacted.read_stream(&mut reader, 2).unwrap(), vec![7; 3000]);
+    }
+
+    #[test]
+    fn invalid_block_size() {
+        assert!(matches!(MsfBuilder::new(1000), Err(Error::InvalidBlockSize(1000))));
+    }
+}
@@ -0,0 +1,384 @@
+use crate::msf::{
+    num_blocks_for, BlockProblem, Error, FreeBlockMap, MsfVersion, ParseLimits, StreamDirectory,
+    SuperBlock, MSF,
+};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+
+/// A single inconsistency found while checking an MSF file.
+#[derive(Debug)]
+pub struct Problem {
+    /// File offset of the offending data, if it can be pinned down.
+    pub offset: Option<u64>,
+    pub description: String,
+}
+
+impl Problem {
+    pub fn new(offset: Option<u64>, description: String) -> Self {
+        Problem {
+            offset,
+            description,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "0x{:08x}: {}", offset, self.description),
+            None => write!(f, "{}", self.description),
+        }
+    }
+}
+
+/// File offset of the MSF 7.00 `block_map_addr` field, which 2.00 files do not have.
+const BLOCK_MAP_ADDR_OFFSET: u64 = 0x34;
+/// File offset of the StreamDirectory block numbers, which only 2.00 files keep in the header.
+const SMALL_DIRECTORY_BLOCKS_OFFSET: u64 = 0x3C;
+
+/// File offsets of the SuperBlock fields, which differ between the two container versions.
+struct FieldOffsets {
+    free_block_map: u64,
+    num_blocks: u64,
+    num_directory_bytes: u64,
+}
+
+impl FieldOffsets {
+    fn for_version(version: MsfVersion) -> Self {
+        match version {
+            // MSFZ files have no block fields; they are checked by check_msfz instead.
+            MsfVersion::Msf700 | MsfVersion::Msfz => FieldOffsets {
+                free_block_map: 0x24,
+                num_blocks: 0x28,
+                num_directory_bytes: 0x2C,
+            },
+            MsfVersion::Msf200 => FieldOffsets {
+                free_block_map: 0x30,
+                num_blocks: 0x32,
+                num_directory_bytes: 0x34,
+            },
+        }
+    }
+}
+
+/// Validate the whole container, collecting every problem instead of stopping at the first.
+///
+/// Checks the magic and block size, the file size against `num_blocks`, that every block index
+/// is in range, that the StreamDirectory size matches `num_directory_bytes`, that no stream is
+/// larger than the file, and cross-checks the free block map. The MSF is returned when the
+/// SuperBlock and StreamDirectory could be loaded, so callers can go on to check the streams.
+/// A file that asks for more than `limits` allow is reported as such, not as corrupt.
+pub fn check(reader: &mut (impl Read + Seek), limits: &ParseLimits) -> (Option<MSF>, Vec<Problem>) {
+    let mut problems = Vec::new();
+    let file_size = match reader.seek(SeekFrom::End(0)).and_then(|_| reader.stream_position()) {
+        Ok(size) => size,
+        Err(e) => {
+            problems.push(Problem::new(None, format!("could not determine the file size: {}", e)));
+            return (None, problems);
+        }
+    };
+    if let Err(e) = reader.seek(SeekFrom::Start(0)) {
+        problems.push(Problem::new(None, format!("could not seek to the SuperBlock: {}", e)));
+        return (None, problems);
+    }
+
+    let sb = match SuperBlock::load(reader) {
+        Ok(sb) => sb,
+        Err(Error::NotPDBFile) => {
+            problems.push(Problem::new(Some(0), "unrecognized MSF magic".to_string()));
+            return (None, problems);
+        }
+        Err(Error::InvalidBlockSize(block_size)) => {
+            // The block size was the last field read; its offset depends on the magic's length.
+            let offset = reader.stream_position().ok().map(|p| p - 4);
+            problems.push(Problem::new(offset, format!("invalid block size {}", block_size)));
+            return (None, problems);
+        }
+        Err(e) => {
+            problems.push(Problem::new(Some(0), format!("truncated SuperBlock: {}", e)));
+            return (None, problems);
+        }
+    };
+    if sb.version == MsfVersion::Msfz {
+        return check_msfz(reader, limits, problems);
+    }
+    let offsets = FieldOffsets::for_version(sb.version);
+    let block_size = sb.block_size as u64;
+
+    let expected_size = sb.num_blocks as u64 * block_size;
+    if expected_size != file_size {
+        problems.push(Problem::new(
+            Some(offsets.num_blocks),
+            format!(
+                "num_blocks ({}) * block_size ({}) = {} bytes, but the file is {} bytes",
+                sb.num_blocks, sb.block_size, expected_size, file_size
+            ),
+        ));
+    }
+    if sb.version == MsfVersion::Msf700 && sb.free_block_map != 1 && sb.free_block_map != 2 {
+        problems.push(Problem::new(
+            Some(offsets.free_block_map),
+            format!("free_block_map is {}, expected 1 or 2", sb.free_block_map),
+        ));
+    }
+
+    // Where each StreamDirectory block number is stored in the file.
+    let directory_blocks = match sb.version {
+        MsfVersion::Msf700 => {
+            if sb.block_map_addr >= sb.num_blocks {
+                problems.push(Problem::new(
+                    Some(BLOCK_MAP_ADDR_OFFSET),
+                    format!(
+                        "block_map_addr {} is past the last block {}",
+                        sb.block_map_addr,
+                        sb.num_blocks.saturating_sub(1)
+                    ),
+                ));
+                return (None, problems);
+            }
+            if sb.num_directory_bytes.div_ceil(sb.block_size) as u64 * 4 > block_size {
+                problems.push(Problem::new(
+                    Some(offsets.num_directory_bytes),
+                    format!(
+                        "num_directory_bytes {} needs more blocks than fit in the block map",
+                        sb.num_directory_bytes
+                    ),
+                ));
+                return (None, problems);
+            }
+            match StreamDirectory::load_block_map(reader, &sb) {
+                Ok(blocks) => blocks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, b)| (sb.block_map_addr as u64 * block_size + 4 * i as u64, b))
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    problems.push(Problem::new(
+                        Some(sb.block_map_addr as u64 * block_size),
+                        format!("could not read the block map: {}", e),
+                    ));
+                    return (None, problems);
+                }
+            }
+        }
+        MsfVersion::Msf200 => sb
+            .directory_blocks
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (SMALL_DIRECTORY_BLOCKS_OFFSET + 2 * i as u64, *b))
+            .collect(),
+        // Already handed off to check_msfz.
+        MsfVersion::Msfz => return (None, problems),
+    };
+    let mut directory_in_range = true;
+    for (offset, block) in &directory_blocks {
+        if *block >= sb.num_blocks {
+            problems.push(Problem::new(
+                Some(*offset),
+                format!("StreamDirectory block {} is out of range", block),
+            ));
+            directory_in_range = false;
+        }
+    }
+    if !directory_in_range {
+        return (None, problems);
+    }
+
+    let sd = match StreamDirectory::load(reader, &sb, limits) {
+        Ok(sd) => sd,
+        Err(Error::LimitExceeded(e)) => {
+            problems.push(Problem::new(None, format!("the StreamDirectory exceeds a limit: {}", e)));
+            return (None, problems);
+        }
+        Err(e) => {
+            problems.push(Problem::new(
+                Some(offsets.num_directory_bytes),
+                format!(
+                    "StreamDirectory does not fit in num_directory_bytes ({}): {}",
+                    sb.num_directory_bytes, e
+                ),
+            ));
+            return (None, problems);
+        }
+    };
+
+    // Layout of the StreamDirectory: entry sizes for the stream size and block number tables.
+    let (header_len, size_entry_len, block_entry_len) = match sb.version {
+        MsfVersion::Msf700 | MsfVersion::Msfz => (4_u64, 4_u64, 4_u64),
+        MsfVersion::Msf200 => (4, 8, 2),
+    };
+    let directory_offset = |position: u64| -> Option<u64> {
+        let block = *sd.directory_blocks.get((position / block_size) as usize)?;
+        Some(block as u64 * block_size + position % block_size)
+    };
+    let total_blocks: u64 = sd.stream_blocks.iter().map(|b| b.len() as u64).sum();
+    let expected_directory_bytes =
+        header_len + size_entry_len * sd.num_streams as u64 + block_entry_len * total_blocks;
+    if expected_directory_bytes != sb.num_directory_bytes as u64 {
+        problems.push(Problem::new(
+            Some(offsets.num_directory_bytes),
+            format!(
+                "num_directory_bytes is {}, but the StreamDirectory takes {} bytes",
+                sb.num_directory_bytes, expected_directory_bytes
+            ),
+        ));
+    }
+
+    let mut block_entry = header_len + size_entry_len * sd.num_streams as u64;
+    for (stream, (size, blocks)) in sd.stream_sizes.iter().zip(&sd.stream_blocks).enumerate() {
+        let size_entry = header_len + size_entry_len * stream as u64;
+        if num_blocks_for(*size, sb.block_size) as u64 * block_size > expected_size {
+            problems.push(Problem::new(
+                directory_offset(size_entry),
+                format!("stream {} has size {}, larger than the file", stream, size),
+            ));
+        }
+        for block in blocks {
+            if *block >= sb.num_blocks {
+                problems.push(Problem::new(
+                    directory_offset(block_entry),
+                    format!("stream {} uses block {}, which is out of range", stream, block),
+                ));
+            }
+            block_entry += block_entry_len;
+        }
+    }
+
+    let msf = MSF {
+        sb,
+        sd,
+        msfz: None,
+        limits: *limits,
+    };
+    match FreeBlockMap::load(reader, &msf) {
+        Ok(fpm) => {
+            problems.extend(
+                fpm.validate(&msf)
+                    .into_iter()
+                    .filter_map(|p| block_problem(p, block_size)),
+            );
+        }
+        Err(e) => problems.push(Problem::new(
+            Some(offsets.free_block_map),
+            format!("could not read the free block map: {}", e),
+        )),
+    }
+    (Some(msf), problems)
+}
+
+/// Check an MSFZ file: its header, chunk table and stream directory, and that every chunk
+/// decompresses.
+fn check_msfz(
+    reader: &mut (impl Read + Seek),
+    limits: &ParseLimits,
+    mut problems: Vec<Problem>,
+) -> (Option<MSF>, Vec<Problem>) {
+    if let Err(e) = reader.seek(SeekFrom::Start(0)) {
+        problems.push(Problem::new(None, format!("could not seek to the header: {}", e)));
+        return (None, problems);
+    }
+    let msf = match MSF::load_with_limits(reader, *limits) {
+        Ok(msf) => msf,
+        Err(Error::LimitExceeded(e)) => {
+            problems.push(Problem::new(None, format!("the MSFZ container exceeds a limit: {}", e)));
+            return (None, problems);
+        }
+        Err(e) => {
+            problems.push(Problem::new(Some(0), format!("could not load the MSFZ container: {}", e)));
+            return (None, problems);
+        }
+    };
+    for stream in 0..msf.num_streams() {
+        if let Err(e) = msf.read_stream(reader, stream) {
+            problems.push(Problem::new(None, format!("stream {} could not be read: {}", stream, e)));
+        }
+    }
+    (Some(msf), problems)
+}
+
+fn block_problem(problem: BlockProblem, block_size: u64) -> Option<Problem> {
+    let (block, description) = match problem {
+        BlockProblem::SharedBlock {
+            block,
+            first,
+            second,
+        } => (block, format!("block {} is used by both {:?} and {:?}", block, first, second)),
+        BlockProblem::UsedButFree { block, owner } => (
+            block,
+            format!("block {} is used by {:?} but marked free", block, owner),
+        ),
+        BlockProblem::Unowned { block } => (
+            block,
+            format!("block {} is marked allocated but is not used", block),
+        ),
+        // Already reported against the StreamDirectory entry.
+        BlockProblem::OutOfRange { .. } => return None,
+    };
+    Some(Problem::new(Some(block as u64 * block_size), description))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::MsfBuilder;
+    use std::io::Cursor;
+
+    fn sample() -> Vec<u8> {
+        let mut builder = MsfBuilder::new(512).unwrap();
+        for i in 0..4_u8 {
+            builder.add_stream(vec![i; 700 * i as usize]);
+        }
+        let mut file = Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        file.into_inner()
+    }
+
+    fn problems(data: &[u8], limits: &ParseLimits) -> Vec<Problem> {
+        check(&mut Cursor::new(data), limits).1
+    }
+
+    #[test]
+    fn clean() {
+        let (msf, problems) = check(&mut Cursor::new(sample()), &ParseLimits::default());
+        assert_eq!(msf.unwrap().num_streams(), 4);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn limit_exceeded_is_not_corruption() {
+        let limits = ParseLimits {
+            max_streams: 2,
+            ..ParseLimits::default()
+        };
+        let problems = problems(&sample(), &limits);
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].description.contains("stream count limit exceeded"), "{}", problems[0]);
+    }
+
+    #[test]
+    fn block_map_addr_out_of_range() {
+        let mut data = sample();
+        data[BLOCK_MAP_ADDR_OFFSET as usize..][..4].copy_from_slice(&0xFFFF_u32.to_le_bytes());
+        let problems = problems(&data, &ParseLimits::default());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].offset, Some(BLOCK_MAP_ADDR_OFFSET));
+    }
+
+    #[test]
+    fn truncated_file() {
+        let data = sample();
+        let problems = problems(&data[..data.len() - 512], &ParseLimits::default());
+        assert_eq!(problems[0].offset, Some(0x28));
+    }
+
+    #[test]
+    fn used_block_marked_free() {
+        let mut data = sample();
+        // Block 3 holds stream 1; mark it free in the active FPM (block 1).
+        data[512] |= 1 << 3;
+        let problems = problems(&data, &ParseLimits::default());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].offset, Some(3 * 512));
+        assert!(problems[0].description.contains("marked free"));
+    }
+}
@@ -0,0 +1,300 @@
+use crate::msf::{Error, MsfVersion, Result, MSF};
+use std::io::{Read, Seek, SeekFrom};
+
+/// The free block map (FPM) of an MSF file: one bit per block, set when the block is free.
+///
+/// In MSF 7.00 files the map is split across the `free_block_map`th block (1 or 2) of every
+/// interval of `block_size` blocks. Each FPM block holds `8 * block_size` bits, so only the
+/// first few intervals carry bits; the FPM blocks of the other intervals are reserved but unused.
+/// MSF 2.00 files store the map in consecutive blocks starting at `free_block_map`.
+#[derive(Debug, Default)]
+pub struct FreeBlockMap {
+    bits: Vec<u8>,
+    num_blocks: u32,
+}
+
+/// What a block of the MSF file is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOwner {
+    SuperBlock,
+    FreeBlockMap,
+    /// The block listing the StreamDirectory blocks.
+    BlockMap,
+    StreamDirectory,
+    Stream(u32),
+}
+
+/// An inconsistency between the free block map and the blocks actually in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockProblem {
+    /// A block is claimed twice.
+    SharedBlock {
+        block: u32,
+        first: BlockOwner,
+        second: BlockOwner,
+    },
+    /// A block is in use but the free block map says it is free. Stream 0 is exempt.
+    UsedButFree { block: u32, owner: BlockOwner },
+    /// A block is marked allocated but nothing uses it.
+    Unowned { block: u32 },
+    /// A block past the end of the file is referenced.
+    OutOfRange { block: u32, owner: BlockOwner },
+}
+
+impl FreeBlockMap {
+    /// Read the active free block map of `msf`.
+    pub fn load(reader: &mut (impl Read + Seek), msf: &MSF) -> Result<Self> {
+        let block_size = msf.sb.block_size;
+        let num_blocks = msf.sb.num_blocks;
+        let mut bits = Vec::new();
+        for block in Self::blocks(msf)? {
+            reader
+                .seek(SeekFrom::Start(block as u64 * block_size as u64))
+                .map_err(Error::Seek)?;
+            let mut tmp = vec![0_u8; block_size as usize];
+            reader.read_exact(&mut tmp)?;
+            bits.extend_from_slice(&tmp);
+        }
+        Ok(FreeBlockMap { bits, num_blocks })
+    }
+    /// The blocks holding the bits of the active free block map, in order.
+    fn blocks(msf: &MSF) -> Result<Vec<u32>> {
+        let sb = &msf.sb;
+        let num_fpm_blocks = || sb.num_blocks.div_ceil(8 * sb.block_size);
+        match sb.version {
+            MsfVersion::Msf700 => {
+                if sb.free_block_map != 1 && sb.free_block_map != 2 {
+                    return Err(Error::InvalidFreeBlockMap(sb.free_block_map));
+                }
+                Ok((0..num_fpm_blocks())
+                    .map(|interval| interval * sb.block_size + sb.free_block_map)
+                    .collect())
+            }
+            MsfVersion::Msf200 => Ok((0..num_fpm_blocks()).map(|i| sb.free_block_map + i).collect()),
+            MsfVersion::Msfz => Ok(Vec::new()),
+        }
+    }
+    /// Total number of blocks in the file.
+    pub fn num_blocks(&self) -> u32 {
+        self.num_blocks
+    }
+    /// Whether `block` is marked free. Blocks past the end of the map are considered free.
+    pub fn is_free(&self, block: u32) -> bool {
+        self.bits
+            .get(block as usize / 8)
+            .is_none_or(|byte| byte >> (block % 8) & 1 == 1)
+    }
+    /// All blocks within the file that are marked free.
+    pub fn free_blocks(&self) -> Vec<u32> {
+        (0..self.num_blocks).filter(|b| self.is_free(*b)).collect()
+    }
+
+    /// Cross-check the free block map against the blocks used by the SuperBlock, the free
+    /// block maps, the StreamDirectory and every stream of `msf`. MSFZ files have no blocks.
+    pub fn validate(&self, msf: &MSF) -> Vec<BlockProblem> {
+        let mut problems = Vec::new();
+        if msf.sb.version == MsfVersion::Msfz {
+            return problems;
+        }
+        let mut owners: Vec<Option<BlockOwner>> = vec![None; self.num_blocks as usize];
+        let mut claim = |block: u32, owner: BlockOwner| {
+            let Some(slot) = owners.get_mut(block as usize) else {
+                problems.push(BlockProblem::OutOfRange { block, owner });
+                return;
+            };
+            if let Some(first) = *slot {
+                problems.push(BlockProblem::SharedBlock {
+                    block,
+                    first,
+                    second: owner,
+                });
+                return;
+            }
+            *slot = Some(owner);
+            // Stream 0 holds a copy of the previous StreamDirectory, and its blocks are
+            // deliberately left marked free.
+            if self.is_free(block) && owner != BlockOwner::Stream(0) {
+                problems.push(BlockProblem::UsedButFree { block, owner });
+            }
+        };
+
+        claim(0, BlockOwner::SuperBlock);
+        let sb = &msf.sb;
+        match sb.version {
+            MsfVersion::Msf700 => {
+                // Both free block maps are reserved in every interval, used or not.
+                for interval_start in (0..sb.num_blocks).step_by(sb.block_size as usize) {
+                    for fpm_block in [interval_start + 1, interval_start + 2] {
+                        if fpm_block < sb.num_blocks {
+                            claim(fpm_block, BlockOwner::FreeBlockMap);
+                        }
+                    }
+                }
+                claim(sb.block_map_addr, BlockOwner::BlockMap);
+            }
+            MsfVersion::Msf200 => {
+                for block in Self::blocks(msf).unwrap_or_default() {
+                    claim(block, BlockOwner::FreeBlockMap);
+                }
+            }
+            MsfVersion::Msfz => {}
+        }
+        for block in &msf.sd.directory_blocks {
+            claim(*block, BlockOwner::StreamDirectory);
+        }
+        for (stream, blocks) in msf.sd.stream_blocks.iter().enumerate() {
+            for block in blocks {
+                claim(*block, BlockOwner::Stream(stream as u32));
+            }
+        }
+
+        for (block, owner) in owners.iter().enumerate() {
+            if owner.is_none() && !self.is_free(block as u32) {
+                problems.push(BlockProblem::Unowned {
+                    block: block as u32,
+                });
+            }
+        }
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::MsfBuilder;
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: usize = 512;
+
+    /// A 7.00 file with a previous directory in stream 0 and two more streams.
+    fn sample() -> Vec<u8> {
+        let mut builder = MsfBuilder::new(BLOCK_SIZE as u32).unwrap();
+        builder.add_stream(vec![0xAB; 100]);
+        builder.add_stream(b"info".to_vec());
+        builder.add_stream(vec![7; 3000]);
+        let mut file = Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        file.into_inner()
+    }
+
+    fn load(data: &[u8]) -> (MSF, FreeBlockMap) {
+        let mut reader = Cursor::new(data);
+        let msf = MSF::load(&mut reader).unwrap();
+        let fpm = FreeBlockMap::load(&mut reader, &msf).unwrap();
+        (msf, fpm)
+    }
+
+    /// Flip the bit of `block` in the first free block map.
+    fn flip(data: &mut [u8], block: u32) {
+        data[BLOCK_SIZE + block as usize / 8] ^= 1 << (block % 8);
+    }
+
+    #[test]
+    fn consistent_file() {
+        let data = sample();
+        let (msf, fpm) = load(&data);
+        assert_eq!(fpm.num_blocks() as usize, data.len() / BLOCK_SIZE);
+        assert_eq!(fpm.validate(&msf), Vec::new());
+        assert!(fpm.free_blocks().is_empty());
+        assert!(!fpm.is_free(0));
+        // Bits past the end of the map read as free.
+        assert!(fpm.is_free(u32::MAX));
+    }
+
+    #[test]
+    fn used_but_free() {
+        let mut data = sample();
+        let (msf, _) = load(&data);
+        let block = msf.stream_blocks(2).unwrap()[1];
+        flip(&mut data, block);
+        let (msf, fpm) = load(&data);
+        assert_eq!(fpm.free_blocks(), [block]);
+        assert_eq!(
+            fpm.validate(&msf),
+            [BlockProblem::UsedButFree {
+                block,
+                owner: BlockOwner::Stream(2)
+            }]
+        );
+    }
+
+    #[test]
+    fn previous_directory_may_be_free() {
+        let mut data = sample();
+        let (msf, _) = load(&data);
+        let block = msf.stream_blocks(0).unwrap()[0];
+        flip(&mut data, block);
+        let (msf, fpm) = load(&data);
+        assert!(fpm.is_free(block));
+        assert_eq!(fpm.validate(&msf), Vec::new());
+    }
+
+    #[test]
+    fn unowned() {
+        let mut data = sample();
+        let block = (data.len() / BLOCK_SIZE) as u32;
+        // Grow the file by one block that the map says is allocated.
+        data.resize(data.len() + BLOCK_SIZE, 0);
+        let num_blocks = u32::from_le_bytes(data[40..44].try_into().unwrap());
+        data[40..44].copy_from_slice(&(num_blocks + 1).to_le_bytes());
+        flip(&mut data, block);
+        let (msf, fpm) = load(&data);
+        assert_eq!(fpm.validate(&msf), [BlockProblem::Unowned { block }]);
+    }
+
+    #[test]
+    fn shared_and_out_of_range() {
+        let data = sample();
+        let (mut msf, fpm) = load(&data);
+        let shared = msf.sd.stream_blocks[1][0];
+        msf.sd.stream_blocks[2].push(shared);
+        msf.sd.stream_blocks[2].push(fpm.num_blocks() + 5);
+        assert_eq!(
+            fpm.validate(&msf),
+            [
+                BlockProblem::SharedBlock {
+                    block: shared,
+                    first: BlockOwner::Stream(1),
+                    second: BlockOwner::Stream(2)
+                },
+                BlockProblem::OutOfRange {
+                    block: fpm.num_blocks() + 5,
+                    owner: BlockOwner::Stream(2)
+                }
+            ]
+        );
+        // A stream claiming the SuperBlock or a free block map.
+        msf.sd.stream_blocks[2] = vec![0, 2];
+        assert_eq!(
+            fpm.validate(&msf)[..2],
+            [
+                BlockProblem::SharedBlock {
+                    block: 0,
+                    first: BlockOwner::SuperBlock,
+                    second: BlockOwner::Stream(2)
+                },
+                BlockProblem::SharedBlock {
+                    block: 2,
+                    first: BlockOwner::FreeBlockMap,
+                    second: BlockOwner::Stream(2)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn second_map_and_bad_index() {
+        let data = sample();
+        let (mut msf, _) = load(&data);
+        // The builder keeps both maps identical, so switching to the second changes nothing.
+        msf.sb.free_block_map = 2;
+        let fpm = FreeBlockMap::load(&mut Cursor::new(&data[..]), &msf).unwrap();
+        assert_eq!(fpm.validate(&msf), Vec::new());
+        msf.sb.free_block_map = 3;
+        assert!(matches!(
+            FreeBlockMap::load(&mut Cursor::new(&data[..]), &msf),
+            Err(Error::InvalidFreeBlockMap(3))
+        ));
+    }
+}
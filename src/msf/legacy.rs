@@ -0,0 +1,167 @@
+//! Support for the legacy "Microsoft C/C++ program database 2.00" (small MSF) container.
+//!
+//! The 2.00 header is laid out as
+//!
+//! | Offset | Type       | Field                                   |
+//! |--------|------------|-----------------------------------------|
+//! | 0x00   | [u8; 0x2C] | magic                                   |
+//! | 0x2C   | u32        | block size                              |
+//! | 0x30   | u16        | block of the valid free block map       |
+//! | 0x32   | u16        | number of blocks                        |
+//! | 0x34   | u32        | StreamDirectory size in bytes           |
+//! | 0x38   | u32        | unused (in-memory pointer)              |
+//! | 0x3C   | [u16]      | blocks holding the StreamDirectory      |
+//!
+//! and the StreamDirectory is a u16 stream count, a u16 of padding, one
+//! `(u32 size, u32 unused)` pair per stream and finally every stream's u16 block list.
+
+use crate::msf::{
+    check_block_size, num_blocks_for, MsfVersion, ParseLimits, Result, StreamDirectory, SuperBlock,
+    NIL_STREAM_SIZE,
+};
+use crate::util;
+use std::io::Read;
+
+/// Magic bytes at the very start of every MSF 2.00 file.
+pub(super) const SMALL_MSF_MAGIC: &[u8; 0x2C] =
+    b"Microsoft C/C++ program database 2.00\r\n\x1aJG\x00\x00";
+
+/// Finish loading a 2.00 header whose first 0x20 bytes (`file_magic`) have already been consumed.
+pub(super) fn load_super_block(reader: &mut impl Read, file_magic: [u8; 0x20]) -> Result<SuperBlock> {
+    let magic_tail = util::consume!(reader, 0x0C, "MSF 2.00 Header")?;
+    if magic_tail[..] != SMALL_MSF_MAGIC[0x20..] {
+        return Err(super::Error::NotPDBFile);
+    }
+    let block_size = check_block_size(util::consume!(reader, u32, "Block Size")?)?;
+    let free_block_map = util::consume!(reader, u16, "Free Block Map")? as u32;
+    let num_blocks = util::consume!(reader, u16, "Num Blocks")? as u32;
+    let num_directory_bytes = util::consume!(reader, u32, "Num Directory Bytes")?;
+    let unknown = util::consume!(reader, u32, "Unknown")?;
+    let mut directory_blocks = Vec::new();
+    for _ in 0..num_blocks_for(num_directory_bytes, block_size) {
+        directory_blocks.push(util::consume!(reader, u16, "Stream Directory Blocks")? as u32);
+    }
+    Ok(SuperBlock {
+        file_magic,
+        version: MsfVersion::Msf200,
+        directory_blocks,
+        block_size,
+        free_block_map,
+        num_blocks,
+        num_directory_bytes,
+        unknown,
+        block_map_addr: 0,
+    })
+}
+
+/// Parse the MSF 2.00 StreamDirectory from its raw bytes.
+pub(super) fn parse_stream_directory(
+    reader: &mut impl Read,
+    block_size: u32,
+    limits: &ParseLimits,
+) -> Result<StreamDirectory> {
+    let mut ret = StreamDirectory {
+        num_streams: util::consume!(reader, u16, "Number of Streams")? as u32,
+        ..Default::default()
+    };
+    limits.check_streams(ret.num_streams)?;
+    let _padding = util::consume!(reader, u16, "Padding")?;
+    for _ in 0..ret.num_streams {
+        let stream_size = util::consume!(reader, u32, "Stream Size")?;
+        if stream_size != NIL_STREAM_SIZE {
+            limits.check_stream_size(stream_size)?;
+        }
+        ret.stream_sizes.push(stream_size);
+        let _unused = util::consume!(reader, u32, "Stream Pointer")?;
+    }
+    for stream_size in &ret.stream_sizes {
+        let num_blocks_in_stream = num_blocks_for(*stream_size, block_size);
+        let mut cur_vec: Vec<u32> = Vec::new();
+        for _ in 0..num_blocks_in_stream {
+            cur_vec.push(util::consume!(reader, u16, "Block Number")? as u32);
+        }
+        ret.stream_blocks.push(cur_vec);
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::{check, FreeBlockMap, MSF};
+    use std::io::Cursor;
+
+    const BLOCK_SIZE: usize = 1024;
+
+    /// A 2.00 file with the header in block 0, the free block map in block 1, the
+    /// StreamDirectory in block 2 and the streams after it.
+    fn small_msf(streams: &[Option<&[u8]>]) -> Vec<u8> {
+        let mut blocks: Vec<Vec<u8>> = vec![Vec::new(); 3];
+        let mut dir = Vec::new();
+        dir.extend_from_slice(&(streams.len() as u16).to_le_bytes());
+        dir.extend_from_slice(&[0, 0]);
+        for stream in streams {
+            let size = stream.map_or(NIL_STREAM_SIZE, |s| s.len() as u32);
+            dir.extend_from_slice(&size.to_le_bytes());
+            dir.extend_from_slice(&[0; 4]);
+        }
+        for stream in streams.iter().flatten() {
+            for chunk in stream.chunks(BLOCK_SIZE) {
+                dir.extend_from_slice(&(blocks.len() as u16).to_le_bytes());
+                blocks.push(chunk.to_vec());
+            }
+        }
+        assert!(dir.len() <= BLOCK_SIZE);
+        let num_blocks = blocks.len();
+
+        let mut header = SMALL_MSF_MAGIC.to_vec();
+        header.extend_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        header.extend_from_slice(&1_u16.to_le_bytes());
+        header.extend_from_slice(&(num_blocks as u16).to_le_bytes());
+        header.extend_from_slice(&(dir.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&2_u16.to_le_bytes());
+        blocks[0] = header;
+        let mut fpm = vec![0xFF_u8; BLOCK_SIZE];
+        for block in 0..num_blocks {
+            fpm[block / 8] &= !(1 << (block % 8));
+        }
+        blocks[1] = fpm;
+        blocks[2] = dir;
+
+        blocks
+            .into_iter()
+            .flat_map(|mut block| {
+                block.resize(BLOCK_SIZE, 0);
+                block
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_streams() {
+        let big: Vec<u8> = (0..2500).map(|i| (i % 253) as u8).collect();
+        let data = small_msf(&[Some(b""), Some(b"hello"), None, Some(&big)]);
+        let mut reader = Cursor::new(&data[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        assert_eq!(msf.version(), MsfVersion::Msf200);
+        assert_eq!(msf.block_size(), BLOCK_SIZE);
+        assert_eq!(msf.num_streams(), 4);
+        assert_eq!(msf.read_stream(&mut reader, 1).unwrap(), b"hello");
+        assert_eq!(msf.stream_size(2).unwrap(), None);
+        assert_eq!(msf.read_stream(&mut reader, 3).unwrap(), big);
+        assert_eq!(msf.stream_blocks(3).unwrap(), &[4, 5, 6]);
+
+        let fpm = FreeBlockMap::load(&mut reader, &msf).unwrap();
+        assert_eq!(fpm.validate(&msf), Vec::new());
+        let (msf, problems) = check(&mut Cursor::new(&data[..]), &ParseLimits::default());
+        assert!(msf.is_some());
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn truncated_header() {
+        let data = small_msf(&[Some(b"x")]);
+        assert!(MSF::load(&mut Cursor::new(&data[..0x34])).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+//! Resource limits applied while loading untrusted files.
+//!
+//! Every size and count in a PDB comes from the file itself. The loaders check them against a
+//! [ParseLimits] before acting on them, so a small malicious file cannot ask for gigabytes.
+
+/// Upper bounds on what a file may make the loaders allocate.
+///
+/// The defaults are generous enough for any PDB a real linker produces. Use
+/// [ParseLimits::unlimited] to trust the file completely.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Maximum number of streams in the StreamDirectory.
+    pub max_streams: u32,
+    /// Maximum size in bytes of a single stream.
+    pub max_stream_size: u32,
+    /// Maximum size in bytes of any one buffer allocated on the file's say-so, such as the
+    /// StreamDirectory, a decompressed chunk or the string buffer of the named stream map.
+    pub max_allocation: u64,
+    /// Maximum number of buckets of a serialized hash table.
+    pub max_hash_table_capacity: u32,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_streams: 1 << 16,
+            max_stream_size: 1 << 30,
+            max_allocation: 1 << 30,
+            max_hash_table_capacity: 1 << 20,
+        }
+    }
+}
+
+/// One of the limits of a [ParseLimits].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Streams,
+    StreamSize,
+    Allocation,
+    HashTableCapacity,
+}
+
+/// A file asked for more than a [ParseLimits] allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    /// What the file asked for.
+    pub requested: u64,
+    /// The configured maximum.
+    pub max: u64,
+}
+
+impl ParseLimits {
+    /// Limits that never trigger.
+    pub fn unlimited() -> Self {
+        ParseLimits {
+            max_streams: u32::MAX,
+            max_stream_size: u32::MAX,
+            max_allocation: u64::MAX,
+            max_hash_table_capacity: u32::MAX,
+        }
+    }
+    pub(crate) fn check_streams(&self, num_streams: u32) -> Result<(), LimitExceeded> {
+        check(Limit::Streams, num_streams as u64, self.max_streams as u64)
+    }
+    pub(crate) fn check_stream_size(&self, size: u32) -> Result<(), LimitExceeded> {
+        check(Limit::StreamSize, size as u64, self.max_stream_size as u64)
+    }
+    pub(crate) fn check_allocation(&self, size: u64) -> Result<(), LimitExceeded> {
+        check(Limit::Allocation, size, self.max_allocation)
+    }
+    pub(crate) fn check_hash_table_capacity(&self, capacity: u32) -> Result<(), LimitExceeded> {
+        check(Limit::HashTableCapacity, capacity as u64, self.max_hash_table_capacity as u64)
+    }
+}
+
+fn check(limit: Limit, requested: u64, max: u64) -> Result<(), LimitExceeded> {
+    if requested > max {
+        return Err(LimitExceeded { limit, requested, max });
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Limit::Streams => write!(f, "stream count"),
+            Limit::StreamSize => write!(f, "stream size"),
+            Limit::Allocation => write!(f, "allocation"),
+            Limit::HashTableCapacity => write!(f, "hash table capacity"),
+        }
+    }
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} limit exceeded: {} requested, at most {} allowed",
+            self.limit, self.requested, self.max
+        )
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
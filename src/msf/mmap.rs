@@ -0,0 +1,120 @@
+use crate::msf::{Error, MSFStreamReader, Result, MSF};
+use memmap2::Mmap;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+/// An MSF file mapped into memory.
+///
+/// Streams whose blocks are contiguous on disk are handed out as slices borrowed
+/// straight from the mapping; only fragmented streams are copied into a buffer.
+pub struct MappedMsf {
+    map: Mmap,
+    msf: MSF,
+}
+
+impl MappedMsf {
+    /// Map the file at `path` and load its MSF structures.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path).map_err(Error::Map)?;
+        // SAFETY: the mapping is read-only. As with any memory-mapped file, another
+        // process truncating or rewriting the PDB while it is mapped is not supported.
+        let map = unsafe { Mmap::map(&file) }.map_err(Error::Map)?;
+        let msf = MSF::load(&mut Cursor::new(&map[..]))?;
+        Ok(MappedMsf { map, msf })
+    }
+    /// The loaded MSF structures.
+    pub fn msf(&self) -> &MSF {
+        &self.msf
+    }
+    /// The whole mapped file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.map
+    }
+    /// Get the contents of stream `stream_no`. Nil streams are returned as empty.
+    ///
+    /// The result borrows from the mapping when the stream's blocks are contiguous. Streams of
+    /// MSFZ files are always decompressed into a buffer.
+    pub fn stream(&self, stream_no: u32) -> Result<Cow<'_, [u8]>> {
+        if self.msf.msfz.is_some() {
+            let mut ret = Vec::new();
+            MSFStreamReader::new(&mut Cursor::new(&self.map[..]), &self.msf, stream_no)?
+                .read_to_end(&mut ret)?;
+            return Ok(Cow::Owned(ret));
+        }
+        let size = self.msf.stream_size(stream_no)?.unwrap_or(0) as usize;
+        let blocks = &self.msf.sd.stream_blocks[stream_no as usize];
+        let block_size = self.msf.sb.block_size as usize;
+
+        let is_contiguous = blocks.windows(2).all(|w| w[1] == w[0].wrapping_add(1));
+        if let (true, Some(first)) = (is_contiguous, blocks.first()) {
+            let start = *first as usize * block_size;
+            return self
+                .map
+                .get(start..start + size)
+                .map(Cow::Borrowed)
+                .ok_or(Error::BlockNumberOutOfBounds);
+        }
+
+        let mut ret = Vec::with_capacity(size);
+        for block in blocks {
+            let start = *block as usize * block_size;
+            let len = block_size.min(size - ret.len());
+            let data = self
+                .map
+                .get(start..start + len)
+                .ok_or(Error::BlockNumberOutOfBounds)?;
+            ret.extend_from_slice(data);
+        }
+        Ok(Cow::Owned(ret))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::MsfBuilder;
+
+    #[test]
+    fn borrowed_and_owned_streams() {
+        let mut builder = MsfBuilder::new(512).unwrap();
+        builder.add_stream(Vec::new());
+        let small: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+        builder.add_stream(small.clone());
+        // 600 blocks of 512 bytes run into the free block maps of the second interval at
+        // blocks 513 and 514, so the stream is split around them.
+        let big: Vec<u8> = (0..600 * 512 + 17).map(|i| (i % 241) as u8).collect();
+        builder.add_stream(big.clone());
+        builder.remove_stream(3);
+        let mut file = Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        let path = std::env::temp_dir().join(format!("pdbparser-mmap-{}.pdb", std::process::id()));
+        std::fs::write(&path, file.into_inner()).unwrap();
+        let mapped = MappedMsf::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        let mapped = mapped.unwrap();
+
+        let blocks = mapped.msf().stream_blocks(1).unwrap();
+        assert!(blocks.windows(2).all(|w| w[1] == w[0] + 1));
+        match mapped.stream(1).unwrap() {
+            Cow::Borrowed(data) => {
+                assert_eq!(data, small);
+                let start = blocks[0] as usize * 512;
+                assert_eq!(data.as_ptr(), mapped.as_bytes()[start..].as_ptr());
+            }
+            Cow::Owned(_) => panic!("contiguous stream was copied"),
+        }
+
+        let blocks = mapped.msf().stream_blocks(2).unwrap();
+        assert!(blocks.windows(2).any(|w| w[1] != w[0] + 1));
+        match mapped.stream(2).unwrap() {
+            Cow::Owned(data) => assert_eq!(data, big),
+            Cow::Borrowed(_) => panic!("fragmented stream was borrowed"),
+        }
+
+        assert!(mapped.stream(0).unwrap().is_empty());
+        assert!(mapped.stream(3).unwrap().is_empty());
+        assert!(matches!(mapped.stream(4), Err(Error::StreamNumberOutOfBounds)));
+    }
+}
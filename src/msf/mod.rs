@@ -0,0 +1,671 @@
+    mod builder;
+    mod check;
+    mod fpm;
+    mod legacy;
+    mod limits;
+    mod mmap;
+    mod msfz;
+    mod previous;
+    mod shared;
+    mod source;
+
+    use crate::util;
+    use std::io::{Read, Seek, SeekFrom};
+    pub use builder::{compact, MsfBuilder};
+    pub use check::{check, Problem};
+    pub use fpm::{BlockOwner, BlockProblem, FreeBlockMap};
+    pub use limits::{Limit, LimitExceeded, ParseLimits};
+    pub use mmap::MappedMsf;
+    pub use previous::StreamChange;
+    pub use shared::{ReadAt, SharedMsf, SharedStreamReader};
+    pub use source::MsfSource;
+    type Result<T> = std::result::Result<T, Error>;
+
+    #[derive(Debug)]
+    pub enum Error {
+        Unknown,
+        Consume(std::io::Error),
+        /// A field of the container could not be read.
+        Field(util::FieldError),
+        NotPDBFile,
+        InvalidBlockSize(u32),
+        Seek(std::io::Error),
+        StreamDirectoryTooSmall,
+        /// A field of the StreamDirectory, starting at this byte offset within it, could not be
+        /// read.
+        InDirectory(u64, util::FieldError),
+        StreamNumberOutOfBounds,
+        BlockNumberOutOfBounds,
+        /// The StreamDirectory needs more blocks than fit in the block map.
+        StreamDirectoryTooLarge,
+        /// Error writing to the underlying writer.
+        Write(std::io::Error),
+        /// A stream to be written is larger than an MSF stream size can express.
+        StreamTooLarge(u32, u64),
+        /// The file could not be opened or memory-mapped.
+        Map(std::io::Error),
+        /// The SuperBlock names a free block map other than 1 or 2.
+        InvalidFreeBlockMap(u32),
+        /// Stream 0 is empty, so there is no previous StreamDirectory to recover.
+        NoPreviousDirectory,
+        /// The MSFZ header has a format version this crate does not know.
+        UnsupportedMsfzVersion(u64),
+        /// The MSFZ chunk table or stream directory is malformed.
+        InvalidMsfz(&'static str),
+        /// An MSFZ chunk or stream directory uses an unknown compression algorithm.
+        UnknownCompression(u32),
+        /// An MSFZ chunk or stream directory could not be decompressed.
+        Decompress(String),
+        /// The file is a cabinet that could not be expanded.
+        Cab(crate::cab::Error),
+        /// The file asks for more than the [ParseLimits] it is loaded with allow.
+        LimitExceeded(LimitExceeded),
+    }
+    impl From<std::io::Error> for Error{
+        fn from(error: std::io::Error) -> Self{
+            Error::Consume(error)
+        }
+    }
+    impl From<util::FieldError> for Error{
+        fn from(error: util::FieldError) -> Self{
+            Error::Field(error)
+        }
+    }
+    impl From<crate::cab::Error> for Error{
+        fn from(error: crate::cab::Error) -> Self{
+            Error::Cab(error)
+        }
+    }
+    impl From<LimitExceeded> for Error{
+        fn from(error: LimitExceeded) -> Self{
+            Error::LimitExceeded(error)
+        }
+    }
+    impl std::fmt::Display for Error {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            match self {
+                Error::Unknown => write!(f, "unknown error"),
+                Error::Consume(e) => e.fmt(f),
+                Error::Field(e) => e.fmt(f),
+                Error::NotPDBFile => write!(f, "not an MSF file"),
+                Error::InvalidBlockSize(size) => write!(f, "invalid block size {}", size),
+                Error::Seek(_) => write!(f, "could not seek in the file"),
+                Error::StreamDirectoryTooSmall => {
+                    write!(f, "the StreamDirectory is too small for the streams it lists")
+                }
+                Error::InDirectory(offset, e) => write!(f, "StreamDirectory offset 0x{:x}: {}", offset, e),
+                Error::StreamNumberOutOfBounds => write!(f, "stream number out of range"),
+                Error::BlockNumberOutOfBounds => write!(f, "block number out of range"),
+                Error::StreamDirectoryTooLarge => {
+                    write!(f, "the StreamDirectory needs more blocks than fit in the block map")
+                }
+                Error::Write(_) => write!(f, "could not write the file"),
+                Error::StreamTooLarge(stream, size) => {
+                    write!(f, "stream {} is {} bytes, too large for an MSF stream", stream, size)
+                }
+                Error::Map(_) => write!(f, "could not map the file"),
+                Error::InvalidFreeBlockMap(n) => write!(f, "free block map is {}, expected 1 or 2", n),
+                Error::NoPreviousDirectory => write!(f, "stream 0 holds no previous StreamDirectory"),
+                Error::UnsupportedMsfzVersion(v) => write!(f, "unsupported MSFZ version {}", v),
+                Error::InvalidMsfz(e) => write!(f, "malformed MSFZ container: {}", e),
+                Error::UnknownCompression(c) => write!(f, "unknown compression {}", c),
+                Error::Decompress(e) => write!(f, "could not decompress: {}", e),
+                Error::Cab(_) => write!(f, "could not expand the cabinet"),
+                Error::LimitExceeded(e) => e.fmt(f),
+            }
+        }
+    }
+    impl std::error::Error for Error {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                Error::Consume(e) => e.source(),
+                Error::Field(e) | Error::InDirectory(_, e) => e.source(),
+                Error::Seek(e) | Error::Write(e) | Error::Map(e) => Some(e),
+                Error::Cab(e) => Some(e),
+                _ => None,
+            }
+        }
+    }
+
+    /// A position within a stream, for pointing at where parsing went wrong.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct StreamOffset {
+        pub stream: u32,
+        /// Byte offset within the stream.
+        pub offset: u64,
+        /// Where that byte is in the file, if it is stored uncompressed within the stream.
+        pub file_offset: Option<u64>,
+    }
+    impl std::fmt::Display for StreamOffset {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "stream {} offset 0x{:x}", self.stream, self.offset)?;
+            if let Some(file_offset) = self.file_offset {
+                write!(f, " (file offset 0x{:x})", file_offset)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    pub struct MSF {
+        sb: SuperBlock,
+        sd: StreamDirectory,
+        /// Where the stream data lives in MSFZ files, which have no blocks.
+        msfz: Option<msfz::Container>,
+        /// The limits the file was loaded with, reused by anything parsed from it later.
+        limits: ParseLimits,
+    }
+
+    /// Magic bytes at the very start of every MSF 7.00 file.
+    const MSF_MAGIC: &[u8; 0x20] = b"Microsoft C/C++ MSF 7.00\r\n\x1aDS\x00\x00\x00";
+    /// Stream size used in the StreamDirectory for deleted (nil) streams.
+    const NIL_STREAM_SIZE: u32 = 0xFFFF_FFFF;
+
+    /// The two MSF container formats.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub enum MsfVersion {
+        /// "Microsoft C/C++ MSF 7.00", with 32-bit block numbers.
+        #[default]
+        Msf700,
+        /// The legacy "Microsoft C/C++ program database 2.00" format, with 16-bit block numbers.
+        Msf200,
+        /// The compressed "Microsoft MSFZ Container", which stores streams in compressed chunks
+        /// instead of blocks. Its block size and block lists are all zero or empty.
+        Msfz,
+    }
+
+    #[derive(Default, Clone)]
+    struct SuperBlock {
+        /// Must be equal to "Microsoft C / C++ MSF 7.00\\r\\n" followed by the bytes 1A 44 53 00 00 00.
+        /// For MSF 2.00 files this holds the first 0x20 bytes of the longer 2.00 magic.
+        file_magic: [u8; 0x20],
+        /// Container version, determined by the magic.
+        version: MsfVersion,
+        /// Only used by MSF 2.00 files, whose header lists the StreamDirectory blocks directly.
+        directory_blocks: Vec<u32>,
+        /// The block size of the internal file system. Valid values are 512, 1024, 2048, and 4096 bytes.
+        block_size: u32,
+        /// FreeBlockMapBlock can only be 1 or 2!
+        free_block_map: u32,
+        /// The total number of blocks in the file. NumBlocks * BlockSize should equal the size of the file on disk.
+        num_blocks: u32,
+        ///The size of the stream directory, in bytes.
+        num_directory_bytes: u32,
+        /// ?
+        unknown: u32,
+        /// The index of a block within the MSF file.
+        block_map_addr: u32,
+    }
+    #[derive(Default)]
+    struct StreamDirectory {
+        num_streams: u32,
+        stream_sizes: Vec<u32>,       // stream_sizes[num_streams]
+        stream_blocks: Vec<Vec<u32>>, // stream_blocks[num_streams][ceil(stream_sizes/block_size)]
+        /// The blocks the StreamDirectory itself was read from.
+        directory_blocks: Vec<u32>,
+    }
+    impl StreamDirectory {
+        pub fn load(reader: &mut (impl Read + Seek), sb: &SuperBlock, limits: &ParseLimits) -> Result<Self> {
+            // Reads through the StreamDirectory to build a list of streams
+            limits.check_allocation(sb.num_directory_bytes as u64)?;
+            let directory_blocks = match sb.version {
+                MsfVersion::Msf700 => Self::load_block_map(reader, sb)?,
+                // The 2.00 header lists the StreamDirectory blocks itself.
+                MsfVersion::Msf200 => sb.directory_blocks.clone(),
+                MsfVersion::Msfz => return Err(Error::InvalidMsfz("MSFZ files have no block map")),
+            };
+            let mut bytes = Vec::new();
+            for block in &directory_blocks {
+                reader
+                    .seek(SeekFrom::Start(*block as u64 * sb.block_size as u64))
+                    .map_err(Error::Seek)?;
+                let mut tmp = vec![0_u8; sb.block_size as usize];
+                reader.read_exact(&mut tmp)?;
+                bytes.extend_from_slice(&tmp);
+            }
+            bytes.truncate(sb.num_directory_bytes as usize);
+            let mut cursor = std::io::Cursor::new(bytes);
+            let mut ret = match sb.version {
+                MsfVersion::Msf700 | MsfVersion::Msfz => Self::parse(&mut cursor, sb.block_size, limits),
+                MsfVersion::Msf200 => legacy::parse_stream_directory(&mut cursor, sb.block_size, limits),
+            }
+            .map_err(|e| directory_error(e, cursor.position()))?;
+            ret.directory_blocks = directory_blocks;
+            Ok(ret)
+        }
+        /// Read the list of blocks the StreamDirectory is stored in.
+        fn load_block_map(reader: &mut (impl Read + Seek), sb: &SuperBlock) -> Result<Vec<u32>> {
+            // Need to find the StreamDirectory!
+            // The StreamDirectory could be stored across multiple blocks, so there is a layer of indirection here
+            //  which is an array of u32 indicating the underlying blocks of the StreamDirectory. This array is located
+            //  at the `block_map_addr`th block in the MSF, with ceil(num_directory_bytes / block_size) entries.
+            let num_indirection_entries = sb.num_directory_bytes.div_ceil(sb.block_size);
+            let stream_dir_indirection_offset = sb.block_map_addr as u64 * sb.block_size as u64;
+            reader
+                .seek(SeekFrom::Start(stream_dir_indirection_offset))
+                .map_err(Error::Seek)?;
+            let mut indirection_blocks: Vec<u32> = Vec::new();
+            for _ in 0..num_indirection_entries {
+                indirection_blocks.push(util::consume!(reader, u32, "Stream Directory Fragment Blocks")?)
+            }
+            Ok(indirection_blocks)
+        }
+        /// Parse the MSF 7.00 StreamDirectory from its raw bytes.
+        fn parse(reader: &mut impl Read, block_size: u32, limits: &ParseLimits) -> Result<Self> {
+            let mut ret = Self {
+                num_streams: util::consume!(reader, u32, "Number of Streams")?,
+                ..Default::default()
+            };
+            limits.check_streams(ret.num_streams)?;
+            for _ in 0..ret.num_streams {
+                let stream_size = util::consume!(reader, u32, "Stream Size")?;
+                if stream_size != NIL_STREAM_SIZE {
+                    limits.check_stream_size(stream_size)?;
+                }
+                ret.stream_sizes.push(stream_size);
+            }
+            //stream_blocks: Vec<Vec<u32>>, // stream_blocks[num_streams][ceil(stream_sizes/block_size)]
+            for stream_size in &ret.stream_sizes {
+                let num_blocks_in_stream = num_blocks_for(*stream_size, block_size);
+                let mut cur_vec: Vec<u32> = Vec::new();
+                for _ in 0..num_blocks_in_stream {
+                    cur_vec.push(util::consume!(reader, u32, "Block Number")?);
+                }
+                ret.stream_blocks.push(cur_vec);
+            }
+            Ok(ret)
+        }
+    }
+    /// Attach the offset within the StreamDirectory to a field that could not be read, given
+    /// that parsing stopped at `position`. A field that was cut short is reported at its start.
+    fn directory_error(error: Error, position: u64) -> Error {
+        match error {
+            Error::Field(e) => Error::InDirectory(position.saturating_sub(e.bytes_read() as u64), e),
+            e => e,
+        }
+    }
+    /// Number of blocks a stream of `stream_size` bytes occupies.
+    fn num_blocks_for(stream_size: u32, block_size: u32) -> u32 {
+        if stream_size == NIL_STREAM_SIZE {
+            0
+        } else {
+            stream_size.div_ceil(block_size)
+        }
+    }
+    impl SuperBlock {
+        pub fn load(reader: &mut (impl Read + Seek)) -> Result<Self> {
+            let file_magic = util::consume!(reader, 0x20, "MSF Header")?;
+            if file_magic[..] == legacy::SMALL_MSF_MAGIC[..0x20] {
+                return legacy::load_super_block(reader, file_magic);
+            }
+            if &file_magic == msfz::MSFZ_MAGIC {
+                // The rest of the header is read by msfz::load.
+                return Ok(SuperBlock {
+                    file_magic,
+                    version: MsfVersion::Msfz,
+                    ..Default::default()
+                });
+            }
+            if &file_magic != MSF_MAGIC {
+                return Err(Error::NotPDBFile);
+            }
+            let block_size = check_block_size(util::consume!(reader, u32, "Block Size")?)?;
+            Ok(SuperBlock {
+                file_magic,
+                version: MsfVersion::Msf700,
+                directory_blocks: Vec::new(),
+                block_size,
+                free_block_map: util::consume!(reader, u32, "Free Block Map")?,
+                num_blocks: util::consume!(reader, u32, "Num Blocks")?,
+                num_directory_bytes: util::consume!(reader, u32, "Num Directory Bytes")?,
+                unknown: util::consume!(reader, u32, "Unknown")?,
+                block_map_addr: util::consume!(reader, u32, "Block Map Addr")?,
+            })
+        }
+    }
+
+    /// Valid values are 512, 1024, 2048, and 4096 bytes.
+    fn check_block_size(block_size: u32) -> Result<u32> {
+        if block_size != 512u32
+            && block_size != 1024u32
+            && block_size != 2048u32
+            && block_size != 4096u32
+        {
+            return Err(Error::InvalidBlockSize(block_size));
+        }
+        Ok(block_size)
+    }
+
+    impl MSF {
+        /// Load the MSF structures with the default [ParseLimits].
+        pub fn load(reader: &mut (impl Read + Seek)) -> Result<Self> {
+            Self::load_with_limits(reader, ParseLimits::default())
+        }
+        /// Load the MSF structures, failing with [Error::LimitExceeded] if the file asks for
+        /// more than `limits` allow.
+        pub fn load_with_limits(reader: &mut (impl Read + Seek), limits: ParseLimits) -> Result<Self> {
+            let sb = SuperBlock::load(reader)?;
+            if sb.version == MsfVersion::Msfz {
+                return msfz::load(reader, sb, limits);
+            }
+            let sd = StreamDirectory::load(reader, &sb, &limits)?;
+            Ok(MSF {
+                sb,
+                sd,
+                msfz: None,
+                limits,
+            })
+        }
+        /// The limits the file was loaded with.
+        pub fn limits(&self) -> &ParseLimits {
+            &self.limits
+        }
+        pub fn block_size(&self) -> usize {
+            self.sb.block_size as usize
+        }
+        /// Which MSF container version the file uses.
+        pub fn version(&self) -> MsfVersion {
+            self.sb.version
+        }
+        /// Number of streams in the StreamDirectory.
+        pub fn num_streams(&self) -> u32 {
+            self.sd.num_streams
+        }
+        /// Size in bytes of stream `stream_no`, or None for a nil stream.
+        pub fn stream_size(&self, stream_no: u32) -> Result<Option<u32>> {
+            let size = *self
+                .sd
+                .stream_sizes
+                .get(stream_no as usize)
+                .ok_or(Error::StreamNumberOutOfBounds)?;
+            Ok((size != NIL_STREAM_SIZE).then_some(size))
+        }
+        /// The blocks holding stream `stream_no`, in stream order.
+        pub fn stream_blocks(&self, stream_no: u32) -> Result<&[u32]> {
+            self.sd
+                .stream_blocks
+                .get(stream_no as usize)
+                .map(Vec::as_slice)
+                .ok_or(Error::StreamNumberOutOfBounds)
+        }
+        /// Read the whole of stream `stream_no`. Nil streams read as empty.
+        pub fn read_stream(&self, reader: &mut (impl Read + Seek), stream_no: u32) -> Result<Vec<u8>> {
+            let mut msfsr = MSFStreamReader::new(reader, self, stream_no)?;
+            let mut ret = Vec::new();
+            msfsr.read_to_end(&mut ret)?;
+            Ok(ret)
+        }
+        /// File offset of byte `position` of stream `stream_no`, or None past the end of the stream.
+        pub fn file_offset(&self, stream_no: u32, position: u64) -> Option<u64> {
+            match self.stream_extent(stream_no, position) {
+                Ok(Some(Extent::File { offset, .. })) => Some(offset),
+                _ => None,
+            }
+        }
+        /// Locate byte `position` of stream `stream_no`, for error reports.
+        pub fn stream_offset(&self, stream_no: u32, position: u64) -> StreamOffset {
+            StreamOffset {
+                stream: stream_no,
+                offset: position,
+                file_offset: self.file_offset(stream_no, position),
+            }
+        }
+        /// Size in bytes of stream `stream_no`, with nil and missing streams treated as empty.
+        fn stream_len(&self, stream_no: u32) -> u64 {
+            self.stream_size(stream_no).ok().flatten().unwrap_or(0) as u64
+        }
+        /// Locate byte `position` of stream `stream_no` in the file.
+        ///
+        /// Returns where the byte is stored and how many bytes can be read from there before
+        /// hitting the end of the block, fragment or stream, or None at or past the end of the stream.
+        fn stream_extent(&self, stream_no: u32, position: u64) -> Result<Option<Extent>> {
+            let size = self.stream_len(stream_no);
+            if position >= size {
+                return Ok(None);
+            }
+            if let Some(container) = &self.msfz {
+                return container.extent(stream_no, position).map(Some);
+            }
+            let block_size = self.sb.block_size as u64;
+            let block = *self
+                .sd
+                .stream_blocks
+                .get(stream_no as usize)
+                .ok_or(Error::StreamNumberOutOfBounds)?
+                .get((position / block_size) as usize)
+                .ok_or(Error::BlockNumberOutOfBounds)?;
+            let pos_in_block = position % block_size;
+            let available = (block_size - pos_in_block).min(size - position);
+            Ok(Some(Extent::File {
+                offset: block as u64 * block_size + pos_in_block,
+                len: available as usize,
+            }))
+        }
+    }
+
+    /// Where the bytes at a position in a stream are stored.
+    enum Extent {
+        /// `len` bytes at file offset `offset`.
+        File { offset: u64, len: usize },
+        /// `len` bytes at offset `offset` of decompressed MSFZ chunk `chunk`.
+        Chunk { chunk: u32, offset: usize, len: usize },
+    }
+
+    /// Reads the contents of a single stream, hiding the blocks it is scattered across.
+    pub struct MSFStreamReader<'a, R> {
+        reader: &'a mut R,
+        msf: &'a MSF,
+        stream_number: u32,
+        /// Byte offset into the stream.
+        position: u64,
+        /// Where the underlying reader is positioned, if known, so sequential reads don't re-seek it.
+        reader_position: Option<u64>,
+        /// The last MSFZ chunk read from.
+        chunk: msfz::ChunkCache,
+    }
+    impl<'a, R: Read + Seek> MSFStreamReader<'a, R> {
+        pub fn new(
+            reader: &'a mut R,
+            msf_struct: &'a MSF,
+            strm_num: u32,
+        ) -> std::result::Result<Self, Error> {
+            if msf_struct.sd.num_streams <= strm_num {
+                return Err(Error::StreamNumberOutOfBounds);
+            }
+            Ok(MSFStreamReader {
+                reader,
+                msf: msf_struct,
+                stream_number: strm_num,
+                position: 0,
+                reader_position: None,
+                chunk: Default::default(),
+            })
+        }
+        /// Switch to reading stream `stream_no`, starting from its beginning.
+        pub fn change_stream(&mut self, stream_no: u32) -> Result<()> {
+            if self.msf.sd.num_streams <= stream_no {
+                return Err(Error::StreamNumberOutOfBounds);
+            }
+            self.stream_number = stream_no;
+            self.position = 0;
+            Ok(())
+        }
+        /// Size of the stream in bytes.
+        pub fn stream_size(&self) -> u64 {
+            self.msf.stream_len(self.stream_number)
+        }
+    }
+    impl<'a, R: Read + Seek> Read for MSFStreamReader<'a, R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
+            let (offset, available) = match self
+                .msf
+                .stream_extent(self.stream_number, self.position)
+                .map_err(to_io_error)?
+            {
+                None => return Ok(0),
+                Some(Extent::File { offset, len }) => (offset, len),
+                Some(Extent::Chunk { chunk, offset, len }) => {
+                    let reader = &mut *self.reader;
+                    self.reader_position = None;
+                    let data = self
+                        .chunk
+                        .get(self.msf, chunk, |file_offset, compressed| {
+                            reader.seek(SeekFrom::Start(file_offset))?;
+                            reader.read_exact(compressed)
+                        })
+                        .map_err(to_io_error)?;
+                    let len = len.min(buf.len());
+                    buf[..len].copy_from_slice(&data[offset..offset + len]);
+                    self.position += len as u64;
+                    return Ok(len);
+                }
+            };
+            let len = available.min(buf.len());
+            if self.reader_position != Some(offset) {
+                self.reader_position = None;
+                self.reader.seek(SeekFrom::Start(offset))?;
+            }
+            let amt_read = self.reader.read(&mut buf[..len])?;
+            // The directory says the stream goes on, so the file ending here is truncation, not
+            // the end of the stream.
+            if amt_read == 0 && len > 0 {
+                self.reader_position = None;
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            self.position += amt_read as u64;
+            self.reader_position = Some(offset + amt_read as u64);
+            Ok(amt_read)
+        }
+    }
+    impl<'a, R: Read + Seek> Seek for MSFStreamReader<'a, R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::result::Result<u64, std::io::Error> {
+            self.position = seek_position(self.position, self.stream_size(), pos)?;
+            Ok(self.position)
+        }
+    }
+    /// Resolve `pos` against a stream of `size` bytes currently at `position`.
+    ///
+    /// Like a file, seeking past the end is allowed and subsequent reads return 0 bytes.
+    fn seek_position(position: u64, size: u64, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(offset) => position.checked_add_signed(offset),
+            SeekFrom::End(offset) => size.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })
+    }
+    /// Surface an MSF error through the `Read` and `Seek` traits.
+    fn to_io_error(error: Error) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::io::Cursor;
+
+        /// A file with an empty stream 0, a 4-byte stream 1 and a 1300-byte stream 2 spread
+        /// over three 512-byte blocks.
+        fn sample() -> (Vec<u8>, Vec<u8>) {
+            let mut builder = MsfBuilder::new(512).unwrap();
+            builder.add_stream(Vec::new());
+            builder.add_stream(b"info".to_vec());
+            let big: Vec<u8> = (0..1300).map(|i| (i % 251) as u8).collect();
+            builder.add_stream(big.clone());
+            let mut file = Cursor::new(Vec::new());
+            builder.write(&mut file).unwrap();
+            (file.into_inner(), big)
+        }
+
+        #[test]
+        fn seek_within_stream() {
+            let (data, big) = sample();
+            let mut reader = Cursor::new(&data[..]);
+            let msf = MSF::load(&mut reader).unwrap();
+            let mut stream = MSFStreamReader::new(&mut reader, &msf, 2).unwrap();
+            // The real size, not the three blocks' worth.
+            assert_eq!(stream.stream_size(), 1300);
+            assert_eq!(stream.seek(SeekFrom::End(0)).unwrap(), 1300);
+            assert_eq!(stream.stream_position().unwrap(), 1300);
+
+            let mut buf = [0_u8; 8];
+            assert_eq!(stream.seek(SeekFrom::End(-8)).unwrap(), 1292);
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, big[1292..]);
+            assert_eq!(stream.seek(SeekFrom::Current(-800)).unwrap(), 500);
+            // Across the boundary between the first and second block.
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(buf, big[500..508]);
+            assert_eq!(stream.stream_position().unwrap(), 508);
+            assert_eq!(stream.seek(SeekFrom::Start(1024)).unwrap(), 1024);
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            assert_eq!(rest, big[1024..]);
+
+            assert!(stream.seek(SeekFrom::Current(-2000)).is_err());
+            assert!(stream.seek(SeekFrom::End(-1301)).is_err());
+            // The failed seeks left the position alone.
+            assert_eq!(stream.stream_position().unwrap(), 1300);
+        }
+
+        #[test]
+        fn seek_past_end() {
+            let (data, _) = sample();
+            let mut reader = Cursor::new(&data[..]);
+            let msf = MSF::load(&mut reader).unwrap();
+            let mut stream = MSFStreamReader::new(&mut reader, &msf, 1).unwrap();
+            assert_eq!(stream.seek(SeekFrom::End(10)).unwrap(), 14);
+            let mut buf = [0_u8; 4];
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            assert_eq!(stream.seek(SeekFrom::Start(1000)).unwrap(), 1000);
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            stream.change_stream(0).unwrap();
+            assert_eq!(stream.stream_position().unwrap(), 0);
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        }
+
+        #[test]
+        fn truncated_directory() {
+            let (mut data, _) = sample();
+            // Claim 10 bytes of StreamDirectory: the stream count and two and a half sizes.
+            data[44..48].copy_from_slice(&10_u32.to_le_bytes());
+            let error = MSF::load(&mut Cursor::new(&data[..])).err().unwrap();
+            match &error {
+                Error::InDirectory(8, e) => assert_eq!((e.field(), e.bytes_read()), ("Stream Size", 2)),
+                e => panic!("unexpected {:?}", e),
+            }
+            assert_eq!(
+                error.to_string(),
+                "StreamDirectory offset 0x8: truncated while reading Stream Size"
+            );
+            assert!(std::error::Error::source(&error).is_none());
+        }
+
+        #[test]
+        fn truncated_file() {
+            let (data, big) = sample();
+            let msf = MSF::load(&mut Cursor::new(&data[..])).unwrap();
+            // Cut the file inside the second block of stream 2.
+            let blocks = msf.stream_blocks(2).unwrap();
+            let cut = blocks[1] as usize * 512 + 100;
+            let mut reader = Cursor::new(&data[..cut]);
+            match msf.read_stream(&mut reader, 2) {
+                Err(Error::Consume(e)) => assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof),
+                other => panic!("unexpected {:?}", other.map(|data| data.len())),
+            }
+            let mut stream = MSFStreamReader::new(&mut reader, &msf, 2).unwrap();
+            let mut buf = vec![0_u8; 1300];
+            assert_eq!(stream.read(&mut buf).unwrap(), 512);
+            assert_eq!(buf[..512], big[..512]);
+            assert_eq!(stream.read(&mut buf).unwrap(), 100);
+            assert_eq!(stream.read(&mut buf).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+            // Stream 1 lies entirely past the cut.
+            let cut = msf.stream_blocks(1).unwrap()[0] as usize * 512;
+            assert!(msf.read_stream(&mut Cursor::new(&data[..cut]), 1).is_err());
+        }
+    }
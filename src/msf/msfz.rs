@@ -0,0 +1,487 @@
+//! Support for the compressed "Microsoft MSFZ Container" format.
+//!
+//! Instead of fixed-size blocks, MSFZ files store each stream as a list of fragments. A
+//! fragment is either stored uncompressed at a file offset, or lives inside a chunk that is
+//! compressed as a whole. The header is laid out as
+//!
+//! | Offset | Type       | Field                                   |
+//! |--------|------------|-----------------------------------------|
+//! | 0x00   | [u8; 0x20] | magic                                   |
+//! | 0x20   | u64        | format version, must be 0               |
+//! | 0x28   | u64        | file offset of the stream directory     |
+//! | 0x30   | u64        | file offset of the chunk table          |
+//! | 0x38   | u32        | number of streams                       |
+//! | 0x3C   | u32        | stream directory compression            |
+//! | 0x40   | u32        | stream directory size, compressed       |
+//! | 0x44   | u32        | stream directory size, uncompressed     |
+//! | 0x48   | u32        | number of chunks                        |
+//! | 0x4C   | u32        | chunk table size in bytes               |
+//!
+//! Each chunk table entry is a u64 file offset, a u32 compression, and the u32 compressed and
+//! uncompressed sizes. Once decompressed, the stream directory lists every stream's fragments as
+//! `(u32 size, u32 location_lo, u32 location_hi)`, terminated by a size of 0. Nil streams are a
+//! lone size of 0xFFFFFFFF. When bit 63 of the location is set, the low 31 bits of `location_hi`
+//! are a chunk number and `location_lo` the offset within the decompressed chunk; otherwise the
+//! location is a file offset.
+//!
+//! Chunks are stored uncompressed, as zstd frames, or as raw deflate data (RFC 1951, with no
+//! zlib wrapper).
+
+use crate::msf::{
+    Error, Extent, MsfVersion, ParseLimits, Result, StreamDirectory, SuperBlock, MSF, NIL_STREAM_SIZE,
+};
+use crate::util;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Magic bytes at the very start of every MSFZ file.
+pub(super) const MSFZ_MAGIC: &[u8; 0x20] = b"Microsoft MSFZ Container\r\n\x1aALD\x00\x00";
+
+/// The only MSFZ format version there is.
+const MSFZ_VERSION: u64 = 0;
+/// Size in bytes of a chunk table entry.
+const CHUNK_ENTRY_SIZE: u32 = 20;
+/// Set in a fragment location when the fragment is stored in a compressed chunk.
+const CHUNK_LOCATION_BIT: u64 = 1 << 63;
+/// Compressed chunks are read at most this many bytes at a time.
+const READ_PIECE_SIZE: usize = 1 << 20;
+
+/// How a chunk or the stream directory is compressed.
+const COMPRESSION_NONE: u32 = 0;
+const COMPRESSION_ZSTD: u32 = 1;
+const COMPRESSION_DEFLATE: u32 = 2;
+
+/// The chunk table and stream fragments of an MSFZ file.
+#[derive(Debug, Default)]
+pub(super) struct Container {
+    chunks: Vec<Chunk>,
+    /// Every stream's fragments, in stream order. Empty for nil streams.
+    fragments: Vec<Vec<Fragment>>,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    file_offset: u64,
+    compression: u32,
+    compressed_size: u32,
+    uncompressed_size: u32,
+}
+
+#[derive(Debug)]
+struct Fragment {
+    size: u32,
+    location: Location,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    File(u64),
+    Chunk { chunk: u32, offset: u32 },
+}
+
+/// Finish loading an MSFZ file whose magic has already been consumed.
+pub(super) fn load(reader: &mut (impl Read + Seek), mut sb: SuperBlock, limits: ParseLimits) -> Result<MSF> {
+    let version = util::consume!(reader, u64, "MSFZ Version")?;
+    if version != MSFZ_VERSION {
+        return Err(Error::UnsupportedMsfzVersion(version));
+    }
+    let stream_dir_offset = util::consume!(reader, u64, "Stream Directory Offset")?;
+    let chunk_table_offset = util::consume!(reader, u64, "Chunk Table Offset")?;
+    let num_streams = util::consume!(reader, u32, "Number of Streams")?;
+    let stream_dir_compression = util::consume!(reader, u32, "Stream Directory Compression")?;
+    let stream_dir_size_compressed = util::consume!(reader, u32, "Stream Directory Compressed Size")?;
+    let stream_dir_size = util::consume!(reader, u32, "Stream Directory Size")?;
+    let num_chunks = util::consume!(reader, u32, "Number of Chunks")?;
+    let chunk_table_size = util::consume!(reader, u32, "Chunk Table Size")?;
+    if num_chunks.checked_mul(CHUNK_ENTRY_SIZE) != Some(chunk_table_size) {
+        return Err(Error::InvalidMsfz("chunk table size does not match the number of chunks"));
+    }
+    limits.check_streams(num_streams)?;
+    limits.check_allocation(stream_dir_size_compressed as u64)?;
+    limits.check_allocation(stream_dir_size as u64)?;
+
+    reader
+        .seek(SeekFrom::Start(chunk_table_offset))
+        .map_err(Error::Seek)?;
+    let mut chunks = Vec::new();
+    for _ in 0..num_chunks {
+        let chunk = Chunk {
+            file_offset: util::consume!(reader, u64, "Chunk File Offset")?,
+            compression: util::consume!(reader, u32, "Chunk Compression")?,
+            compressed_size: util::consume!(reader, u32, "Chunk Compressed Size")?,
+            uncompressed_size: util::consume!(reader, u32, "Chunk Uncompressed Size")?,
+        };
+        limits.check_allocation(chunk.compressed_size as u64)?;
+        limits.check_allocation(chunk.uncompressed_size as u64)?;
+        chunks.push(chunk);
+    }
+
+    let compressed = read_at(reader, stream_dir_offset, stream_dir_size_compressed, "Stream Directory")?;
+    let directory = decompress(stream_dir_compression, &compressed, stream_dir_size)?;
+    let mut cursor = std::io::Cursor::new(directory);
+    let mut sd = StreamDirectory {
+        num_streams,
+        ..Default::default()
+    };
+    let mut container = Container {
+        chunks,
+        fragments: Vec::new(),
+    };
+    for _ in 0..num_streams {
+        let (size, fragments) = container.parse_stream(&mut cursor)?;
+        if size != NIL_STREAM_SIZE {
+            limits.check_stream_size(size)?;
+        }
+        sd.stream_sizes.push(size);
+        sd.stream_blocks.push(Vec::new());
+        container.fragments.push(fragments);
+    }
+
+    sb.version = MsfVersion::Msfz;
+    sb.num_directory_bytes = stream_dir_size;
+    Ok(MSF {
+        sb,
+        sd,
+        msfz: Some(container),
+        limits,
+    })
+}
+
+impl Container {
+    /// Parse one stream's fragment list, returning the stream size and its fragments.
+    fn parse_stream(&self, reader: &mut impl Read) -> Result<(u32, Vec<Fragment>)> {
+        let mut size = 0_u32;
+        let mut fragments = Vec::new();
+        loop {
+            let fragment_size = util::consume!(reader, u32, "Fragment Size")?;
+            if fragment_size == 0 {
+                return Ok((size, fragments));
+            }
+            if fragment_size == NIL_STREAM_SIZE && fragments.is_empty() {
+                return Ok((NIL_STREAM_SIZE, fragments));
+            }
+            let location_lo = util::consume!(reader, u32, "Fragment Location")? as u64;
+            let location_hi = util::consume!(reader, u32, "Fragment Location")? as u64;
+            let location = (location_hi << 32) | location_lo;
+            let location = if location & CHUNK_LOCATION_BIT != 0 {
+                let chunk = ((location & !CHUNK_LOCATION_BIT) >> 32) as u32;
+                let offset = location as u32;
+                let in_bounds = self.chunks.get(chunk as usize).is_some_and(|c| {
+                    offset as u64 + fragment_size as u64 <= c.uncompressed_size as u64
+                });
+                if !in_bounds {
+                    return Err(Error::InvalidMsfz("fragment lies outside its chunk"));
+                }
+                Location::Chunk { chunk, offset }
+            } else {
+                Location::File(location)
+            };
+            size = size
+                .checked_add(fragment_size)
+                .filter(|size| *size != NIL_STREAM_SIZE)
+                .ok_or(Error::InvalidMsfz("stream is larger than 4GB"))?;
+            fragments.push(Fragment {
+                size: fragment_size,
+                location,
+            });
+        }
+    }
+
+    /// Locate byte `position` of stream `stream_no`, which must be within the stream.
+    pub(super) fn extent(&self, stream_no: u32, position: u64) -> Result<Extent> {
+        let fragments = self
+            .fragments
+            .get(stream_no as usize)
+            .ok_or(Error::StreamNumberOutOfBounds)?;
+        let mut start = 0_u64;
+        for fragment in fragments {
+            let end = start + fragment.size as u64;
+            if position < end {
+                let offset = position - start;
+                let len = (end - position) as usize;
+                return Ok(match fragment.location {
+                    Location::File(file_offset) => Extent::File {
+                        offset: file_offset + offset,
+                        len,
+                    },
+                    Location::Chunk { chunk, offset: chunk_offset } => Extent::Chunk {
+                        chunk,
+                        offset: chunk_offset as usize + offset as usize,
+                        len,
+                    },
+                });
+            }
+            start = end;
+        }
+        Err(Error::BlockNumberOutOfBounds)
+    }
+}
+
+/// The most recently used decompressed chunk, kept by each stream reader.
+#[derive(Default)]
+pub(super) struct ChunkCache {
+    chunk: Option<u32>,
+    data: Vec<u8>,
+}
+
+impl ChunkCache {
+    /// Get the decompressed contents of chunk `chunk_no` of `msf`, using `read_at` to read the
+    /// compressed bytes at a file offset if the chunk is not already cached.
+    pub(super) fn get(
+        &mut self,
+        msf: &MSF,
+        chunk_no: u32,
+        mut read_at: impl FnMut(u64, &mut [u8]) -> std::io::Result<()>,
+    ) -> Result<&[u8]> {
+        if self.chunk != Some(chunk_no) {
+            let chunk = msf
+                .msfz
+                .as_ref()
+                .and_then(|container| container.chunks.get(chunk_no as usize))
+                .ok_or(Error::BlockNumberOutOfBounds)?;
+            // Read piecewise so a bogus compressed size runs into the end of the file before
+            // it can cause a huge allocation.
+            let mut compressed = Vec::new();
+            while compressed.len() < chunk.compressed_size as usize {
+                let start = compressed.len();
+                let len = (chunk.compressed_size as usize - start).min(READ_PIECE_SIZE);
+                compressed.resize(start + len, 0);
+                read_at(chunk.file_offset.saturating_add(start as u64), &mut compressed[start..])?;
+            }
+            self.chunk = None;
+            self.data = decompress(chunk.compression, &compressed, chunk.uncompressed_size)?;
+            self.chunk = Some(chunk_no);
+        }
+        Ok(&self.data)
+    }
+}
+
+/// Read the `len` bytes of `field` at file offset `offset`.
+fn read_at(reader: &mut (impl Read + Seek), offset: u64, len: u32, field: &'static str) -> Result<Vec<u8>> {
+    reader.seek(SeekFrom::Start(offset)).map_err(Error::Seek)?;
+    Ok(util::consume_bytes(reader, len as u64, field)?)
+}
+
+/// Decompress `data`, which must expand to exactly `size` bytes.
+fn decompress(compression: u32, data: &[u8], size: u32) -> Result<Vec<u8>> {
+    let size = size as usize;
+    let ret = match compression {
+        COMPRESSION_NONE => data.to_vec(),
+        COMPRESSION_ZSTD => {
+            let mut decoder = ruzstd::decoding::StreamingDecoder::new(data)
+                .map_err(|e| Error::Decompress(e.to_string()))?;
+            let mut ret = Vec::new();
+            (&mut decoder)
+                .take(size as u64 + 1)
+                .read_to_end(&mut ret)
+                .map_err(|e| Error::Decompress(e.to_string()))?;
+            ret
+        }
+        COMPRESSION_DEFLATE => {
+            // Deflate chunks are raw RFC 1951 data, without a zlib header or trailer.
+            miniz_oxide::inflate::decompress_to_vec_with_limit(data, size)
+                .map_err(|e| Error::Decompress(format!("{:?}", e.status)))?
+        }
+        _ => return Err(Error::UnknownCompression(compression)),
+    };
+    if ret.len() != size {
+        return Err(Error::Decompress(format!(
+            "expected {} bytes, got {}",
+            size,
+            ret.len()
+        )));
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::SharedMsf;
+    use std::io::Cursor;
+
+    const HEADER_SIZE: usize = 0x50;
+
+    /// Where a fragment is stored: uncompressed in the file, or in a chunk.
+    enum Place {
+        File,
+        Chunk(usize),
+    }
+
+    type Fragments<'a> = Vec<(Place, &'a [u8])>;
+
+    /// Build an MSFZ file with a zstd chunk 0 and a deflate chunk 1. Each stream is a list of
+    /// fragments, or `None` for a nil stream.
+    fn build(streams: &[Option<Fragments>]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        let mut chunks = [Vec::new(), Vec::new()];
+        let mut dir = Vec::new();
+        for stream in streams {
+            let Some(fragments) = stream else {
+                dir.extend(NIL_STREAM_SIZE.to_le_bytes());
+                continue;
+            };
+            for (place, data) in fragments {
+                dir.extend((data.len() as u32).to_le_bytes());
+                let location = match place {
+                    Place::File => (HEADER_SIZE + raw.len()) as u64,
+                    Place::Chunk(chunk) => {
+                        CHUNK_LOCATION_BIT | (*chunk as u64) << 32 | chunks[*chunk].len() as u64
+                    }
+                };
+                match place {
+                    Place::File => raw.extend_from_slice(data),
+                    Place::Chunk(chunk) => chunks[*chunk].extend_from_slice(data),
+                }
+                dir.extend((location as u32).to_le_bytes());
+                dir.extend(((location >> 32) as u32).to_le_bytes());
+            }
+            dir.extend(0_u32.to_le_bytes());
+        }
+
+        let mut file = vec![0; HEADER_SIZE];
+        file.extend(&raw);
+        let mut table = Vec::new();
+        for (chunk, data) in chunks.iter().enumerate() {
+            let (compression, compressed) = if chunk == 0 {
+                (COMPRESSION_ZSTD, zstd(data))
+            } else {
+                (COMPRESSION_DEFLATE, miniz_oxide::deflate::compress_to_vec(data, 6))
+            };
+            table.extend((file.len() as u64).to_le_bytes());
+            table.extend(compression.to_le_bytes());
+            table.extend((compressed.len() as u32).to_le_bytes());
+            table.extend((data.len() as u32).to_le_bytes());
+            file.extend(compressed);
+        }
+        let table_offset = file.len() as u64;
+        file.extend(&table);
+        let dir_offset = file.len() as u64;
+        let compressed_dir = zstd(&dir);
+        file.extend(&compressed_dir);
+
+        let mut header = Vec::new();
+        header.extend(MSFZ_MAGIC);
+        header.extend(MSFZ_VERSION.to_le_bytes());
+        header.extend(dir_offset.to_le_bytes());
+        header.extend(table_offset.to_le_bytes());
+        header.extend((streams.len() as u32).to_le_bytes());
+        header.extend(COMPRESSION_ZSTD.to_le_bytes());
+        header.extend((compressed_dir.len() as u32).to_le_bytes());
+        header.extend((dir.len() as u32).to_le_bytes());
+        header.extend((chunks.len() as u32).to_le_bytes());
+        header.extend((table.len() as u32).to_le_bytes());
+        file[..HEADER_SIZE].copy_from_slice(&header);
+        file
+    }
+
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        ruzstd::encoding::compress_to_vec(data, ruzstd::encoding::CompressionLevel::Fastest)
+    }
+
+    fn sample() -> (Vec<u8>, Vec<Option<Vec<u8>>>) {
+        let text: Vec<u8> = (0..3000_u32).flat_map(|i| (i % 251).to_le_bytes()).collect();
+        let streams = vec![
+            Some(vec![]),
+            Some(vec![(Place::Chunk(0), &b"zstd chunk"[..])]),
+            Some(vec![(Place::Chunk(1), &b"deflate chunk"[..])]),
+            // One stream spread over the file and both chunks.
+            Some(vec![
+                (Place::File, &text[..100]),
+                (Place::Chunk(0), &text[100..5000]),
+                (Place::Chunk(1), &text[5000..9000]),
+                (Place::File, &text[9000..]),
+            ]),
+            None,
+        ];
+        let want = vec![
+            Some(vec![]),
+            Some(b"zstd chunk".to_vec()),
+            Some(b"deflate chunk".to_vec()),
+            Some(text.clone()),
+            None,
+        ];
+        (build(&streams), want)
+    }
+
+    #[test]
+    fn load_streams() {
+        let (bytes, want) = sample();
+        let mut reader = Cursor::new(&bytes[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        assert_eq!(msf.version(), MsfVersion::Msfz);
+        assert_eq!(msf.num_streams() as usize, want.len());
+        for (stream, want) in want.iter().enumerate() {
+            let stream = stream as u32;
+            assert_eq!(msf.stream_size(stream).unwrap(), want.as_ref().map(|w| w.len() as u32));
+            let want = want.clone().unwrap_or_default();
+            assert_eq!(msf.read_stream(&mut reader, stream).unwrap(), want);
+        }
+    }
+
+    #[test]
+    fn open_file() {
+        let (bytes, want) = sample();
+        let path = std::env::temp_dir().join(format!("pdbparser-msfz-{}.pdb", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let opened = MSF::open(&path);
+        let shared = SharedMsf::open(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let (mut source, msf) = opened.unwrap();
+        assert_eq!(msf.version(), MsfVersion::Msfz);
+        for (stream, want) in want.iter().enumerate() {
+            let want = want.clone().unwrap_or_default();
+            assert_eq!(msf.read_stream(&mut source, stream as u32).unwrap(), want);
+        }
+        // Read the multi-fragment stream from an offset inside its zstd fragment.
+        let shared = shared.unwrap();
+        let mut reader = shared.stream_reader(3).unwrap();
+        reader.seek(SeekFrom::Start(4000)).unwrap();
+        let mut got = Vec::new();
+        reader.read_to_end(&mut got).unwrap();
+        assert_eq!(got, want[3].as_ref().unwrap()[4000..]);
+    }
+
+    #[test]
+    fn reject_bad_chunks() {
+        let (mut bytes, _) = sample();
+        let table_offset = u64::from_le_bytes(bytes[0x30..0x38].try_into().unwrap()) as usize;
+        bytes[table_offset + 8] = 9;
+        let mut reader = Cursor::new(&bytes[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        let err = msf.read_stream(&mut reader, 1).unwrap_err();
+        assert!(err.to_string().contains("unknown compression 9"), "{err}");
+
+        // A fragment that runs past the end of its decompressed chunk.
+        let bad = build(&[Some(vec![(Place::Chunk(0), &b"x"[..])])]);
+        let dir_offset = u64::from_le_bytes(bad[0x28..0x30].try_into().unwrap()) as usize;
+        let mut dir = vec![];
+        dir.extend(2_u32.to_le_bytes());
+        dir.extend(0_u32.to_le_bytes());
+        dir.extend(0x8000_0000_u32.to_le_bytes());
+        dir.extend(0_u32.to_le_bytes());
+        let compressed = zstd(&dir);
+        let mut bad = bad[..dir_offset].to_vec();
+        bad.extend(&compressed);
+        bad[0x40..0x44].copy_from_slice(&(compressed.len() as u32).to_le_bytes());
+        bad[0x44..0x48].copy_from_slice(&(dir.len() as u32).to_le_bytes());
+        assert!(matches!(
+            MSF::load(&mut Cursor::new(&bad[..])),
+            Err(Error::InvalidMsfz(_))
+        ));
+    }
+
+    #[test]
+    fn deflate_is_raw() {
+        let data = b"deflate chunk, deflate chunk, deflate chunk";
+        let raw = miniz_oxide::deflate::compress_to_vec(data, 6);
+        assert_eq!(decompress(COMPRESSION_DEFLATE, &raw, data.len() as u32).unwrap(), data);
+
+        // zlib-wrapped data is not a valid deflate chunk.
+        let zlib = miniz_oxide::deflate::compress_to_vec_zlib(data, 6);
+        assert!(matches!(
+            decompress(COMPRESSION_DEFLATE, &zlib, data.len() as u32),
+            Err(Error::Decompress(_))
+        ));
+    }
+}
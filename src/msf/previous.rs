@@ -0,0 +1,215 @@
+use crate::msf::{
+    directory_error, legacy, Error, MsfVersion, Result, StreamDirectory, SuperBlock, MSF,
+};
+use std::io::{Read, Seek};
+
+/// How a stream differs between the previous StreamDirectory and the current one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamChange {
+    /// The stream only exists in the current directory.
+    Added { stream: u32, size: Option<u32> },
+    /// The stream only existed in the previous directory.
+    Removed { stream: u32, size: Option<u32> },
+    /// The stream changed size. Sizes of None are nil streams.
+    Resized {
+        stream: u32,
+        old_size: Option<u32>,
+        new_size: Option<u32>,
+    },
+    /// The stream kept its size but was rewritten to different blocks.
+    Moved { stream: u32, size: Option<u32> },
+}
+
+impl MSF {
+    /// Load the StreamDirectory as it was before the last commit of the file.
+    ///
+    /// Stream 0 holds a copy of the previous StreamDirectory. The returned MSF shares this
+    /// file's SuperBlock, so streams can be opened with [crate::msf::MSFStreamReader] and
+    /// [MSF::read_stream] as they were before the last incremental link. Blocks freed by the
+    /// last commit may since have been reused, so old stream contents are not guaranteed to
+    /// be intact.
+    pub fn previous(&self, reader: &mut (impl Read + Seek)) -> Result<MSF> {
+        // MSFZ files are written in one go and never carry a previous directory.
+        if self.msfz.is_some() || self.stream_size(0)?.unwrap_or(0) == 0 {
+            return Err(Error::NoPreviousDirectory);
+        }
+        let bytes = self.read_stream(reader, 0)?;
+        let mut cursor = std::io::Cursor::new(bytes);
+        let mut sd = match self.sb.version {
+            MsfVersion::Msf700 | MsfVersion::Msfz => {
+                StreamDirectory::parse(&mut cursor, self.sb.block_size, &self.limits)
+            }
+            MsfVersion::Msf200 => {
+                legacy::parse_stream_directory(&mut cursor, self.sb.block_size, &self.limits)
+            }
+        }
+        .map_err(|e| directory_error(e, cursor.position()))?;
+        sd.directory_blocks = self.sd.stream_blocks[0].clone();
+        let mut sb = SuperBlock {
+            num_directory_bytes: cursor.position() as u32,
+            ..self.sb.clone()
+        };
+        if sb.version == MsfVersion::Msf200 {
+            sb.directory_blocks = sd.directory_blocks.clone();
+        }
+        Ok(MSF {
+            sb,
+            sd,
+            msfz: None,
+            limits: self.limits,
+        })
+    }
+
+    /// List the streams that differ between `old` and `self`, in stream order.
+    pub fn diff(&self, old: &MSF) -> Vec<StreamChange> {
+        let num_streams = self.num_streams().max(old.num_streams());
+        let mut changes = Vec::new();
+        for stream in 0..num_streams {
+            let old_size = old.stream_size(stream);
+            let new_size = self.stream_size(stream);
+            let change = match (old_size, new_size) {
+                (Err(_), Ok(size)) => StreamChange::Added { stream, size },
+                (Ok(size), Err(_)) => StreamChange::Removed { stream, size },
+                (Ok(old_size), Ok(new_size)) if old_size != new_size => StreamChange::Resized {
+                    stream,
+                    old_size,
+                    new_size,
+                },
+                (Ok(size), Ok(_)) if old.sd.stream_blocks[stream as usize]
+                    != self.sd.stream_blocks[stream as usize] =>
+                {
+                    StreamChange::Moved { stream, size }
+                }
+                _ => continue,
+            };
+            changes.push(change);
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::{MsfBuilder, NIL_STREAM_SIZE};
+    use std::io::Cursor;
+
+    fn build(builder: &MsfBuilder) -> Vec<u8> {
+        let mut file = Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        file.into_inner()
+    }
+
+    /// Serialize the StreamDirectory of `msf` the way stream 0 keeps it.
+    fn directory(msf: &MSF) -> Vec<u8> {
+        let mut ret = msf.num_streams().to_le_bytes().to_vec();
+        for stream in 0..msf.num_streams() {
+            let size = msf.stream_size(stream).unwrap().unwrap_or(NIL_STREAM_SIZE);
+            ret.extend(size.to_le_bytes());
+        }
+        for blocks in &msf.sd.stream_blocks {
+            for block in blocks {
+                ret.extend(block.to_le_bytes());
+            }
+        }
+        ret
+    }
+
+    /// A file as first linked, and the same file relinked with its old directory in stream 0:
+    /// stream 1 kept its contents but moved, stream 2 grew, stream 3 became nil and stream 4
+    /// was added as a nil stream.
+    fn relinked() -> (Vec<u8>, Vec<u8>) {
+        let mut builder = MsfBuilder::new(512).unwrap();
+        builder.add_stream(Vec::new());
+        builder.add_stream(b"info".to_vec());
+        builder.add_stream(vec![7; 3000]);
+        builder.add_stream(b"gone".to_vec());
+        let first = build(&builder);
+        let old = MSF::load(&mut Cursor::new(&first[..])).unwrap();
+
+        let mut builder = MsfBuilder::new(512).unwrap();
+        builder.add_stream(directory(&old));
+        builder.add_stream(b"info".to_vec());
+        builder.add_stream(vec![8; 5000]);
+        builder.remove_stream(4);
+        (first, build(&builder))
+    }
+
+    #[test]
+    fn load_previous() {
+        let (first, second) = relinked();
+        let old = MSF::load(&mut Cursor::new(&first[..])).unwrap();
+        let mut reader = Cursor::new(&second[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        let previous = msf.previous(&mut reader).unwrap();
+        assert_eq!(previous.num_streams(), 4);
+        for stream in 0..4 {
+            assert_eq!(previous.stream_size(stream).unwrap(), old.stream_size(stream).unwrap());
+            assert_eq!(previous.stream_blocks(stream).unwrap(), old.stream_blocks(stream).unwrap());
+        }
+        assert_eq!(previous.sd.directory_blocks, msf.sd.stream_blocks[0]);
+        assert_eq!(previous.sb.num_directory_bytes as usize, directory(&old).len());
+        // The file as first linked has nothing before it.
+        let mut reader = Cursor::new(&first[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        assert!(matches!(msf.previous(&mut reader), Err(Error::NoPreviousDirectory)));
+    }
+
+    #[test]
+    fn diff_directories() {
+        let (_, second) = relinked();
+        let mut reader = Cursor::new(&second[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        let previous = msf.previous(&mut reader).unwrap();
+        let size = directory(&previous).len() as u32;
+        assert_eq!(
+            msf.diff(&previous),
+            [
+                StreamChange::Resized {
+                    stream: 0,
+                    old_size: Some(0),
+                    new_size: Some(size)
+                },
+                StreamChange::Moved {
+                    stream: 1,
+                    size: Some(4)
+                },
+                StreamChange::Resized {
+                    stream: 2,
+                    old_size: Some(3000),
+                    new_size: Some(5000)
+                },
+                StreamChange::Resized {
+                    stream: 3,
+                    old_size: Some(4),
+                    new_size: None
+                },
+                StreamChange::Added {
+                    stream: 4,
+                    size: None
+                },
+            ]
+        );
+        assert_eq!(
+            previous.diff(&msf).last(),
+            Some(&StreamChange::Removed {
+                stream: 4,
+                size: None
+            })
+        );
+        assert_eq!(msf.diff(&msf), Vec::new());
+    }
+
+    #[test]
+    fn malformed_previous() {
+        let mut builder = MsfBuilder::new(512).unwrap();
+        // More streams than the directory has room to size.
+        builder.add_stream(vec![0xFF; 8]);
+        builder.add_stream(b"info".to_vec());
+        let data = build(&builder);
+        let mut reader = Cursor::new(&data[..]);
+        let msf = MSF::load(&mut reader).unwrap();
+        let result = msf.previous(&mut reader);
+        assert!(result.is_err() && !matches!(result, Err(Error::NoPreviousDirectory)));
+    }
+}
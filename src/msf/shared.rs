@@ -0,0 +1,317 @@
+use crate::msf::{msfz, seek_position, to_io_error, Error, Extent, ParseLimits, Result, MSF};
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// A data source that can be read at any offset through a shared reference.
+///
+/// Unlike `Read + Seek` there is no cursor to share, so any number of readers can use the
+/// same source at once, from any number of threads.
+pub trait ReadAt {
+    /// Read into `buf` starting at `offset`, returning how many bytes were read.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize>;
+}
+
+/// Positional file reads are only available on Unix and Windows.
+#[cfg(any(unix, windows))]
+impl ReadAt for File {
+    #[cfg(unix)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+    #[cfg(windows)]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let data = usize::try_from(offset)
+            .ok()
+            .and_then(|offset| self.get(offset..))
+            .unwrap_or_default();
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+}
+
+impl ReadAt for Mmap {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self[..].read_at(buf, offset)
+    }
+}
+
+/// A `Send + Sync` handle to an MSF file that can hand out many independent stream readers.
+///
+/// Each [SharedStreamReader] keeps its own position and reads with positional reads, so the
+/// handle can be wrapped in an `Arc` and used to read streams concurrently from many threads.
+pub struct SharedMsf<S> {
+    source: S,
+    msf: MSF,
+}
+
+#[cfg(any(unix, windows))]
+impl SharedMsf<File> {
+    /// Open the file at `path` and load its MSF structures.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        SharedMsf::new(File::open(path)?)
+    }
+}
+
+impl<S: ReadAt> SharedMsf<S> {
+    /// Load the MSF structures from `source` with the default [ParseLimits].
+    pub fn new(source: S) -> Result<Self> {
+        Self::new_with_limits(source, ParseLimits::default())
+    }
+    /// Load the MSF structures from `source`, rejecting files that ask for more than `limits`
+    /// allow.
+    pub fn new_with_limits(source: S, limits: ParseLimits) -> Result<Self> {
+        let msf = MSF::load_with_limits(
+            &mut Cursor {
+                source: &source,
+                position: 0,
+            },
+            limits,
+        )?;
+        Ok(SharedMsf { source, msf })
+    }
+    /// The loaded MSF structures.
+    pub fn msf(&self) -> &MSF {
+        &self.msf
+    }
+    /// Open a reader over stream `stream_no`, positioned at its start.
+    pub fn stream_reader(&self, stream_no: u32) -> Result<SharedStreamReader<'_, S>> {
+        if self.msf.num_streams() <= stream_no {
+            return Err(Error::StreamNumberOutOfBounds);
+        }
+        Ok(SharedStreamReader {
+            source: &self.source,
+            msf: &self.msf,
+            stream_number: stream_no,
+            position: 0,
+            chunk: Default::default(),
+        })
+    }
+}
+
+/// Reads a single stream of a [SharedMsf], with its own cursor.
+pub struct SharedStreamReader<'a, S> {
+    source: &'a S,
+    msf: &'a MSF,
+    stream_number: u32,
+    /// Byte offset into the stream.
+    position: u64,
+    /// The last MSFZ chunk read from.
+    chunk: msfz::ChunkCache,
+}
+
+impl<'a, S: ReadAt> SharedStreamReader<'a, S> {
+    /// Size of the stream in bytes.
+    pub fn stream_size(&self) -> u64 {
+        self.msf.stream_len(self.stream_number)
+    }
+}
+
+impl<'a, S: ReadAt> Read for SharedStreamReader<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let amt_read = match self
+            .msf
+            .stream_extent(self.stream_number, self.position)
+            .map_err(to_io_error)?
+        {
+            None => 0,
+            Some(Extent::File { offset, len }) => {
+                let len = len.min(buf.len());
+                match self.source.read_at(&mut buf[..len], offset)? {
+                    // The stream goes on past the end of the source, which is truncated.
+                    0 if len > 0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                    amt_read => amt_read,
+                }
+            }
+            Some(Extent::Chunk { chunk, offset, len }) => {
+                let source = self.source;
+                let data = self
+                    .chunk
+                    .get(self.msf, chunk, |file_offset, compressed| {
+                        read_exact_at(source, compressed, file_offset)
+                    })
+                    .map_err(to_io_error)?;
+                let len = len.min(buf.len());
+                buf[..len].copy_from_slice(&data[offset..offset + len]);
+                len
+            }
+        };
+        self.position += amt_read as u64;
+        Ok(amt_read)
+    }
+}
+
+impl<'a, S: ReadAt> Seek for SharedStreamReader<'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = seek_position(self.position, self.stream_size(), pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Fill `buf` from `offset`, failing if the source ends first.
+fn read_exact_at<S: ReadAt + ?Sized>(source: &S, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match source.read_at(buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            amt_read => {
+                buf = &mut buf[amt_read..];
+                offset += amt_read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adapts a [ReadAt] source to `Read + Seek` so the MSF structures can be loaded from it.
+struct Cursor<'a, S: ?Sized> {
+    source: &'a S,
+    position: u64,
+}
+
+impl<'a, S: ReadAt + ?Sized> Read for Cursor<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let amt_read = self.source.read_at(buf, self.position)?;
+        self.position += amt_read as u64;
+        Ok(amt_read)
+    }
+}
+
+impl<'a, S: ReadAt + ?Sized> Seek for Cursor<'a, S> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(position) => position,
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset).ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek to a negative position")
+            })?,
+            SeekFrom::End(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "ReadAt sources have no known end",
+                ))
+            }
+        };
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::msf::MsfBuilder;
+    use std::sync::Arc;
+
+    #[cfg(any(unix, windows))]
+    const _: () = {
+        const fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedMsf<File>>();
+        assert_send_sync::<SharedMsf<Mmap>>();
+    };
+
+    fn sample() -> (Vec<u8>, Vec<Vec<u8>>) {
+        let streams: Vec<Vec<u8>> = (0..8_u32)
+            .map(|i| (0..i * 1000).map(|b| (b * (i + 1)) as u8).collect())
+            .collect();
+        let mut builder = MsfBuilder::new(512).unwrap();
+        for stream in &streams {
+            builder.add_stream(stream.clone());
+        }
+        let mut file = std::io::Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        (file.into_inner(), streams)
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let (data, streams) = sample();
+        let shared = Arc::new(SharedMsf::new(data).unwrap());
+        let streams = Arc::new(streams);
+        let threads: Vec<_> = (0..8_u32)
+            .map(|stream| {
+                let shared = Arc::clone(&shared);
+                let streams = Arc::clone(&streams);
+                std::thread::spawn(move || {
+                    let mut reader = shared.stream_reader(stream).unwrap();
+                    let mut data = Vec::new();
+                    reader.read_to_end(&mut data).unwrap();
+                    assert_eq!(data, streams[stream as usize]);
+                    if data.len() > 1 {
+                        reader.seek(SeekFrom::Start(1)).unwrap();
+                        let mut byte = [0];
+                        reader.read_exact(&mut byte).unwrap();
+                        assert_eq!(byte[0], data[1]);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(matches!(shared.stream_reader(8), Err(Error::StreamNumberOutOfBounds)));
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn open_file() {
+        let (data, streams) = sample();
+        let path = std::env::temp_dir().join(format!("pdbparser-shared-{}.pdb", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let shared = SharedMsf::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        let shared = shared.unwrap();
+        let mut data = Vec::new();
+        shared.stream_reader(5).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, streams[5]);
+    }
+
+    #[test]
+    fn truncated_source() {
+        let (data, streams) = sample();
+        let msf = SharedMsf::new(data.clone()).unwrap().msf;
+        // Cut the source 100 bytes into the second block of stream 3.
+        let cut = msf.stream_blocks(3).unwrap()[1] as usize * 512 + 100;
+        let shared = SharedMsf {
+            source: data[..cut].to_vec(),
+            msf,
+        };
+        let mut reader = shared.stream_reader(3).unwrap();
+        let mut buf = vec![0_u8; 3000];
+        assert_eq!(reader.read(&mut buf).unwrap(), 512);
+        assert_eq!(buf[..512], streams[3][..512]);
+        assert_eq!(reader.read(&mut buf).unwrap(), 100);
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+        let mut data = Vec::new();
+        let error = shared.stream_reader(7).unwrap().read_to_end(&mut data).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        // Streams entirely before the cut are intact.
+        let mut data = Vec::new();
+        shared.stream_reader(2).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, streams[2]);
+    }
+
+    #[test]
+    fn limits() {
+        let (data, _) = sample();
+        let limits = ParseLimits {
+            max_streams: 4,
+            ..ParseLimits::default()
+        };
+        assert!(matches!(
+            SharedMsf::new_with_limits(data, limits),
+            Err(Error::LimitExceeded(_))
+        ));
+    }
+}
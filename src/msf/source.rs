@@ -0,0 +1,102 @@
+use crate::cab;
+use crate::msf::{Error, Limit, LimitExceeded, ParseLimits, Result, MSF};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// An MSF file opened from disk. CAB-compressed files, such as the `.pd_` files of symbol
+/// stores, are expanded into memory; anything else is read straight from the file.
+pub enum MsfSource {
+    File(BufReader<File>),
+    /// The file expanded from a cabinet, and its name within the cabinet.
+    Expanded { name: String, data: Cursor<Vec<u8>> },
+}
+
+impl MsfSource {
+    /// Open the file at `path`, expanding it first if it is a cabinet.
+    ///
+    /// A cabinet is expected to hold a single file, as `.pd_` files do; only the first is used.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_limits(path, &ParseLimits::default())
+    }
+    /// Like [MsfSource::open], but a cabinet may not expand to more than the maximum allocation
+    /// of `limits`.
+    pub fn open_with_limits(path: impl AsRef<Path>, limits: &ParseLimits) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path).map_err(Error::Map)?);
+        if !cab::is_cabinet(&mut reader)? {
+            return Ok(MsfSource::File(reader));
+        }
+        let files = cab::extract_with_limit(&mut reader, limits.max_allocation).map_err(|e| match e {
+            cab::Error::FolderTooLarge(size) => Error::LimitExceeded(LimitExceeded {
+                limit: Limit::Allocation,
+                requested: size,
+                max: limits.max_allocation,
+            }),
+            e => Error::Cab(e),
+        })?;
+        let file = files
+            .into_iter()
+            .next()
+            .ok_or(Error::NotPDBFile)?;
+        Ok(MsfSource::Expanded {
+            name: file.name,
+            data: Cursor::new(file.data),
+        })
+    }
+    /// The name the file had before it was compressed into a cabinet, such as `foo.pdb` for a
+    /// `foo.pd_`. None for files that were not compressed.
+    pub fn expanded_name(&self) -> Option<&str> {
+        match self {
+            MsfSource::File(_) => None,
+            MsfSource::Expanded { name, .. } => Some(name),
+        }
+    }
+}
+
+impl MSF {
+    /// Open the file at `path`, expanding it if it is a cabinet, and load its MSF structures.
+    pub fn open(path: impl AsRef<Path>) -> Result<(MsfSource, MSF)> {
+        Self::open_with_limits(path, ParseLimits::default())
+    }
+    /// Like [MSF::open], applying `limits` to both the cabinet and the MSF structures.
+    pub fn open_with_limits(path: impl AsRef<Path>, limits: ParseLimits) -> Result<(MsfSource, MSF)> {
+        let mut source = MsfSource::open_with_limits(path, &limits)?;
+        let msf = MSF::load_with_limits(&mut source, limits)?;
+        Ok((source, msf))
+    }
+}
+
+impl Read for MsfSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            MsfSource::File(reader) => reader.read(buf),
+            MsfSource::Expanded { data, .. } => data.read(buf),
+        }
+    }
+}
+
+impl Seek for MsfSource {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            MsfSource::File(reader) => reader.seek(pos),
+            MsfSource::Expanded { data, .. } => data.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expanded_name() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut cab = MsfSource::open(dir.join("testdata/cab/mszip.cab")).unwrap();
+        assert_eq!(cab.expanded_name(), Some("text.txt"));
+        let mut start = [0; 5];
+        cab.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"00000");
+        let pdb = MsfSource::open(dir.join("pdb/ntdll.pdb")).unwrap();
+        assert_eq!(pdb.expanded_name(), None);
+    }
+}
@@ -1,7 +1,8 @@
 
-use crate::msf;
+use crate::msf::{LimitExceeded, ParseLimits};
 use crate::util;
-use std::io::{Read};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -10,10 +11,14 @@ type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     /// Error consuming from a reader
     Consume(std::io::Error),
+    /// A field of the hash table could not be read
+    Field(util::FieldError),
     /// There was an issue reading from the [SerializedHashTable](pdb::hashtable::SerializedHashTable)
     HashTableInvalid,
     /// The requested entry was not found in the HashTable
-    HashTableEntryNotFound(u32)
+    HashTableEntryNotFound(u32),
+    /// The HashTable is larger than the [ParseLimits] allow
+    LimitExceeded(LimitExceeded),
 }
 /// Convert an io error into hashtable::Error
 impl From<std::io::Error> for Error{
@@ -22,102 +27,478 @@ impl From<std::io::Error> for Error{
         Error::Consume(error)
     }
 }
+impl From<util::FieldError> for Error{
+    fn from(error: util::FieldError) -> Self{
+        Error::Field(error)
+    }
+}
+impl From<LimitExceeded> for Error{
+    fn from(error: LimitExceeded) -> Self{
+        Error::LimitExceeded(error)
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Consume(e) => e.fmt(f),
+            Error::Field(e) => e.fmt(f),
+            Error::HashTableInvalid => write!(f, "invalid hash table"),
+            Error::HashTableEntryNotFound(key) => write!(f, "no hash table entry for key {}", key),
+            Error::LimitExceeded(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Consume(e) => e.source(),
+            Error::Field(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// The string hash the PDB uses for its hash tables (`hashStringV1` in the reference
+/// implementation). It ignores ASCII case.
+pub fn hash_string_v1(bytes: &[u8]) -> u32 {
+    let mut chunks = bytes.chunks_exact(4);
+    let mut result = chunks
+        .by_ref()
+        .fold(0_u32, |acc, c| acc ^ u32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+    let mut rest = chunks.remainder();
+    if rest.len() >= 2 {
+        result ^= u16::from_le_bytes([rest[0], rest[1]]) as u32;
+        rest = &rest[2..];
+    }
+    if let Some(b) = rest.first() {
+        result ^= *b as u32;
+    }
+    result |= 0x2020_2020;
+    result ^= result >> 11;
+    result ^ (result >> 16)
+}
+
+/// A value that can be stored in a [SerializedHashTable].
+pub trait HashTableValue: Sized {
+    /// Read the value as it is serialized after its key.
+    fn load(reader: &mut impl Read) -> Result<Self>;
+    /// Write the value as [load](HashTableValue::load) expects it.
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()>;
+}
+
+impl HashTableValue for u32 {
+    fn load(reader: &mut impl Read) -> Result<Self> {
+        Ok(util::consume!(reader, u32, "value")?)
+    }
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&self.to_le_bytes())
+    }
+}
+
+/// How the keys of a [SerializedHashTable] are hashed and compared. Keys are stored as u32s,
+/// but they may stand for something else, such as the offset of a string in a buffer.
+pub trait HashTableTraits {
+    /// What a stored key stands for.
+    type Key: ?Sized;
+    /// Hash of `key`, before it is reduced to a bucket.
+    fn hash(&self, key: &Self::Key) -> u32;
+    /// Hash of the key stored as `stored`, used to rehash when the table grows.
+    fn hash_stored(&self, stored: u32) -> u32;
+    /// Whether `stored` stands for `key`.
+    fn matches(&self, stored: u32, key: &Self::Key) -> bool;
+}
+
+/// Keys that are plain u32s, hashed as themselves.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdentityTraits;
+
+impl HashTableTraits for IdentityTraits {
+    type Key = u32;
+    fn hash(&self, key: &u32) -> u32 {
+        *key
+    }
+    fn hash_stored(&self, stored: u32) -> u32 {
+        stored
+    }
+    fn matches(&self, stored: u32, key: &u32) -> bool {
+        stored == *key
+    }
+}
 
-#[derive(Debug, Default)]
 /// A single entry into a hashtable
+#[derive(Debug, Clone)]
 struct HashTableEntry<T> {
     key: u32,
     value: T,
 }
-#[derive(Debug, Default)]
+
 /// Bit vector that represents the existence of an entry in a given bucket
+#[derive(Debug, Default, Clone)]
 struct BitVector {
-    word_count: u32,
-    words: Vec<u8>,
+    words: Vec<u32>,
+}
+
+/// Implementation for a Bit Vector
+impl BitVector {
+    /// Load a BitVector from an MSFStream
+    fn load(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        let wc = util::consume!(reader, u32, "word_count")?;
+        limits.check_allocation(wc as u64 * 4)?;
+        let b = util::consume_bytes(reader, wc as u64 * 4, "words")?;
+        Ok(BitVector {
+            words: b
+                .chunks_exact(4)
+                .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+                .collect(),
+        })
+    }
+    /// Write the words up to the last set bit, preceded by their count.
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        let used = self.words.iter().rposition(|w| *w != 0).map_or(0, |i| i + 1);
+        writer.write_all(&(used as u32).to_le_bytes())?;
+        for word in &self.words[..used] {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    fn get(&self, idx: u32) -> bool {
+        self.words
+            .get(idx as usize / 32)
+            .is_some_and(|w| w >> (idx % 32) & 1 == 1)
+    }
+    fn set(&mut self, idx: u32) {
+        let word = idx as usize / 32;
+        if self.words.len() <= word {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (idx % 32);
+    }
+    fn reset(&mut self, idx: u32) {
+        if let Some(w) = self.words.get_mut(idx as usize / 32) {
+            *w &= !(1 << (idx % 32));
+        }
+    }
+    /// Get the indices of the set bits.
+    fn get_set_indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, w)| {
+            (0..32)
+                .filter(move |n| w >> n & 1 == 1)
+                .map(move |n| (i * 32) as u32 + n)
+        })
+    }
 }
 
-#[derive(Debug, Default)]
-/// A Serialized HashTable implementation for parsing PDBs
+/// The on-disk hash table of a PDB. Buckets are probed linearly from the key's hash, and
+/// removed entries leave a deleted marker so that probing continues past them.
+#[derive(Debug, Clone)]
 pub struct SerializedHashTable<T> {
-    size: usize,
-    capacity: usize,
+    size: u32,
+    capacity: u32,
     present_vec: BitVector,
     deleted_vec: BitVector,
-    entries: Vec<HashTableEntry<T>>,
+    /// Present buckets only, keyed by bucket index.
+    buckets: BTreeMap<u32, HashTableEntry<T>>,
 }
 
-/// Implementation for HashTableEntry
-impl HashTableEntry<u32> {
-    /// Load a HashTableEntry from an MSFStream
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        return Ok(HashTableEntry {
-            key: util::consume!(reader, u32, "k")?,
-            value: util::consume!(reader, u32, "v")?,
-        });
+impl<T> Default for SerializedHashTable<T> {
+    fn default() -> Self {
+        Self::with_capacity(8)
     }
 }
-/// Implementation for a Bit Vector
-impl BitVector {
-    /// Load a BitVector from an MSFStream
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        let wc = util::consume!(reader, u32, "word_count")?;
 
-        let mut b = vec![0_u8; (wc * 4) as usize];
-        for i in 0..wc {
-            b[i as usize] = util::consume!(reader, u8, "BitVec")?;
-        }
+/// Most entries a table of `capacity` buckets holds before it grows.
+fn max_load(capacity: u32) -> u32 {
+    // Computed in u64 so that hostile capacities cannot overflow; the result always fits.
+    (capacity as u64 * 2 / 3 + 1) as u32
+}
 
-        let ret = BitVector {
-            word_count: wc,
-            words: b,
-        };
-        return Ok(ret);
+impl<T> SerializedHashTable<T> {
+    /// An empty table with the same initial capacity the linker uses.
+    pub fn new() -> Self {
+        Self::default()
     }
-    /// Get the indices of the set bits.
-    pub fn get_set_indices(&self) -> Vec<u32>{
-        let mut indices: Vec<u32> = Vec::with_capacity(self.word_count as usize); // assume we only use a quarter of the bitvector, for perf
-        for (i, bv) in self.words.iter().enumerate() {
-            //bv is an u8;
-            for n in 0..8 {
-                let val = bv >> n & 1;
-                if val == 1 {
-                    indices.push(n as u32 + (i * 8) as u32);
+    /// An empty table with `capacity` buckets.
+    pub fn with_capacity(capacity: u32) -> Self {
+        SerializedHashTable {
+            size: 0,
+            capacity: capacity.max(1),
+            present_vec: BitVector::default(),
+            deleted_vec: BitVector::default(),
+            buckets: BTreeMap::new(),
+        }
+    }
+    /// Number of entries in the table.
+    pub fn len(&self) -> usize {
+        self.size as usize
+    }
+    /// Whether the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+    /// Number of buckets in the table.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+    /// Every (key, value) pair in the table, in bucket order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &T)> + '_ {
+        self.buckets.values().map(|entry| (entry.key, &entry.value))
+    }
+    /// Probe for `key`. Returns the bucket holding it, or else the first free bucket on its
+    /// probe sequence.
+    fn find<K: HashTableTraits>(&self, key: &K::Key, traits: &K) -> (Option<u32>, Option<u32>) {
+        let start = traits.hash(key) % self.capacity;
+        let mut idx = start;
+        let mut first_free = None;
+        loop {
+            if self.present_vec.get(idx) {
+                if self.buckets.get(&idx).is_some_and(|e| traits.matches(e.key, key)) {
+                    return (Some(idx), first_free);
                 }
+            } else {
+                first_free.get_or_insert(idx);
+                // Insertion fills the first free bucket it finds, so nothing was ever stored
+                // past a bucket that has never been used.
+                if !self.deleted_vec.get(idx) {
+                    break;
+                }
+            }
+            idx = (idx + 1) % self.capacity;
+            if idx == start {
+                break;
+            }
+        }
+        (None, first_free)
+    }
+    /// Get the value stored for `key`, hashing and comparing it with `traits`.
+    pub fn get_as<K: HashTableTraits>(&self, key: &K::Key, traits: &K) -> Option<&T> {
+        let idx = self.find(key, traits).0?;
+        self.buckets.get(&idx).map(|e| &e.value)
+    }
+    /// Store `value` for `key`, which is written to disk as `stored` unless `key` is already
+    /// present. Returns the value it replaces, if any. The table grows once it is more than two thirds full.
+    pub fn set_as<K: HashTableTraits>(
+        &mut self,
+        key: &K::Key,
+        stored: u32,
+        value: T,
+        traits: &K,
+    ) -> Option<T> {
+        let (found, free) = self.find(key, traits);
+        if let Some(idx) = found {
+            let entry = self.buckets.get_mut(&idx)?;
+            return Some(std::mem::replace(&mut entry.value, value));
+        }
+        let idx = match free {
+            Some(idx) => idx,
+            // Only a table loaded completely full has no free bucket.
+            None => {
+                self.rehash(self.capacity.saturating_mul(2), traits);
+                return self.set_as(key, stored, value, traits);
             }
+        };
+        self.buckets.insert(idx, HashTableEntry { key: stored, value });
+        self.present_vec.set(idx);
+        self.deleted_vec.reset(idx);
+        self.size += 1;
+        if self.size >= max_load(self.capacity) {
+            let capacity = max_load(self.capacity).saturating_mul(2);
+            self.rehash(capacity, traits);
         }
-        indices
+        None
+    }
+    /// Remove `key` from the table, returning its value.
+    pub fn remove_as<K: HashTableTraits>(&mut self, key: &K::Key, traits: &K) -> Option<T> {
+        let idx = self.find(key, traits).0?;
+        let entry = self.buckets.remove(&idx)?;
+        self.present_vec.reset(idx);
+        self.deleted_vec.set(idx);
+        self.size -= 1;
+        Some(entry.value)
+    }
+    /// Move every entry into a table of `capacity` buckets, dropping the deleted markers.
+    fn rehash<K: HashTableTraits>(&mut self, capacity: u32, traits: &K) {
+        let mut grown = SerializedHashTable::with_capacity(capacity);
+        for (_, entry) in std::mem::take(&mut self.buckets) {
+            let mut idx = traits.hash_stored(entry.key) % capacity;
+            while grown.present_vec.get(idx) {
+                idx = (idx + 1) % capacity;
+            }
+            grown.present_vec.set(idx);
+            grown.buckets.insert(idx, entry);
+        }
+        grown.size = self.size;
+        *self = grown;
+    }
+    /// Get the value found at key in the SerializedHashTable
+    pub fn get(&self, key: u32) -> Result<&T> {
+        self.get_as(&key, &IdentityTraits)
+            .ok_or(Error::HashTableEntryNotFound(key))
+    }
+    /// Store `value` for the u32 `key`, returning the value it replaces.
+    pub fn set(&mut self, key: u32, value: T) -> Option<T> {
+        self.set_as(&key, key, value, &IdentityTraits)
+    }
+    /// Remove the u32 `key`, returning its value.
+    pub fn remove(&mut self, key: u32) -> Option<T> {
+        self.remove_as(&key, &IdentityTraits)
+    }
+}
+
+impl<T: Copy> SerializedHashTable<T> {
+    /// Get every (key, value) pair present in the SerializedHashTable, in bucket order.
+    pub fn entries(&self) -> Vec<(u32, T)> {
+        self.iter().map(|(key, value)| (key, *value)).collect()
     }
 }
 
 /// Implementation of a SerializedHashTable found in a PDB file
-impl SerializedHashTable<u32> {
-    /// Load a SerializedHashTable from an MSFStream
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        let mut ret = Self::default();
-        ret.size = util::consume!(reader, u32, "size")? as usize;
-        ret.capacity = util::consume!(reader, u32, "capacity")? as usize;
-        ret.present_vec = BitVector::load(reader)?;
-        ret.deleted_vec = BitVector::load(reader)?;
-        ret.entries = Vec::with_capacity(ret.capacity);
-        for _ in 0..ret.capacity {
-            ret.entries.push(HashTableEntry::load(reader)?);
-        }
-        return Ok(ret);
+impl<T: HashTableValue> SerializedHashTable<T> {
+    /// Load a SerializedHashTable from an MSFStream with the default [ParseLimits]
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Self::load_with_limits(reader, &ParseLimits::default())
     }
-    /// Get the value found at key in the SerializedHashTable
-    pub fn get(&self, key: u32) -> Result<u32> {
-        //First, need to isolate the "present" entries, build a list of valid entries
-        let indices: Vec<u32> = self.present_vec.get_set_indices();
-
-        for idx in indices {
-            let entry = self
-                .entries
-                .get(idx as usize)
-                .ok_or(Error::HashTableInvalid)?;
-            if entry.key == key {
-                return Ok(entry.value);
+    /// Load a SerializedHashTable from an MSFStream, rejecting tables larger than `limits` allow
+    pub fn load_with_limits(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        let size = util::consume!(reader, u32, "size")?;
+        let capacity = util::consume!(reader, u32, "capacity")?;
+        limits.check_hash_table_capacity(capacity)?;
+        if capacity == 0 || size > max_load(capacity) {
+            return Err(Error::HashTableInvalid);
+        }
+        let mut ret = SerializedHashTable {
+            size,
+            capacity,
+            present_vec: BitVector::load(reader, limits)?,
+            deleted_vec: BitVector::load(reader, limits)?,
+            buckets: BTreeMap::new(),
+        };
+        for idx in ret.present_vec.get_set_indices() {
+            if idx >= capacity || ret.deleted_vec.get(idx) {
+                return Err(Error::HashTableInvalid);
             }
+            let key = util::consume!(reader, u32, "key")?;
+            let value = T::load(reader)?;
+            ret.buckets.insert(idx, HashTableEntry { key, value });
+        }
+        if ret.buckets.len() != size as usize {
+            return Err(Error::HashTableInvalid);
         }
-        return Err(Error::HashTableEntryNotFound(key));
+        Ok(ret)
     }
-}
\ No newline at end of file
+    /// Serialize the table as [load](SerializedHashTable::load) expects it.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&self.size.to_le_bytes())?;
+        writer.write_all(&self.capacity.to_le_bytes())?;
+        self.present_vec.write(writer)?;
+        self.deleted_vec.write(writer)?;
+        for entry in self.buckets.values() {
+            writer.write_all(&entry.key.to_le_bytes())?;
+            entry.value.write(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn round_trip(table: &SerializedHashTable<u32>) -> SerializedHashTable<u32> {
+        let mut bytes = Vec::new();
+        table.write(&mut bytes).unwrap();
+        let loaded = SerializedHashTable::<u32>::load(&mut Cursor::new(&bytes)).unwrap();
+        let mut again = Vec::new();
+        loaded.write(&mut again).unwrap();
+        assert_eq!(again, bytes);
+        loaded
+    }
+
+    #[test]
+    fn set_and_remove() {
+        let mut table = SerializedHashTable::new();
+        assert_eq!(table.set(1, 10), None);
+        assert_eq!(table.set(1, 11), Some(10));
+        // 9 and 17 probe from the same bucket as 1.
+        table.set(9, 90);
+        table.set(17, 170);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.remove(9), Some(90));
+        assert_eq!(table.remove(9), None);
+        // Lookups continue past the deleted bucket, and insertion reuses it.
+        assert_eq!(*table.get(17).unwrap(), 170);
+        assert!(matches!(table.get(9), Err(Error::HashTableEntryNotFound(9))));
+        table.set(25, 250);
+        assert_eq!(table.entries(), [(1, 11), (25, 250), (17, 170)]);
+        let loaded = round_trip(&table);
+        assert_eq!(loaded.entries(), table.entries());
+    }
+
+    #[test]
+    fn growth() {
+        let mut table = SerializedHashTable::new();
+        assert_eq!(table.capacity(), 8);
+        for key in 0..5 {
+            table.set(key, key * 2);
+        }
+        assert_eq!(table.capacity(), 8);
+        // The sixth entry reaches the maximum load of a table of 8, which grows to twice that.
+        table.set(5, 10);
+        assert_eq!(table.capacity(), 12);
+        for key in 6..100 {
+            table.set(key, key * 2);
+        }
+        assert_eq!(table.len(), 100);
+        assert!((0..100).all(|key| *table.get(key).unwrap() == key * 2));
+        let loaded = round_trip(&table);
+        assert_eq!(loaded.capacity(), table.capacity());
+        assert_eq!(loaded.entries(), table.entries());
+    }
+
+    #[test]
+    fn hostile_capacity() {
+        let header = |size: u32, capacity: u32| {
+            let mut bytes = Vec::new();
+            for word in [size, capacity, 0, 0] {
+                bytes.extend(word.to_le_bytes());
+            }
+            bytes
+        };
+        let unlimited = ParseLimits::unlimited();
+        for capacity in [0x8000_0000, u32::MAX] {
+            let bytes = header(0, capacity);
+            let table =
+                SerializedHashTable::<u32>::load_with_limits(&mut Cursor::new(&bytes), &unlimited)
+                    .unwrap();
+            assert_eq!(table.capacity(), capacity);
+            assert!(table.is_empty());
+            assert!(matches!(
+                SerializedHashTable::<u32>::load(&mut Cursor::new(&bytes)),
+                Err(Error::LimitExceeded(_))
+            ));
+        }
+        for (size, capacity) in [(0, 0), (7, 8)] {
+            assert!(matches!(
+                SerializedHashTable::<u32>::load_with_limits(
+                    &mut Cursor::new(&header(size, capacity)),
+                    &unlimited
+                ),
+                Err(Error::HashTableInvalid)
+            ));
+        }
+        // A present bucket outside the table.
+        let mut bytes = header(1, 8);
+        bytes[8..12].copy_from_slice(&1_u32.to_le_bytes());
+        bytes.splice(12..12, 0x100_u32.to_le_bytes());
+        bytes.extend([0; 8]);
+        assert!(matches!(
+            SerializedHashTable::<u32>::load(&mut Cursor::new(&bytes)),
+            Err(Error::HashTableInvalid)
+        ));
+    }
+
+    #[test]
+    fn string_hash() {
+        assert_eq!(hash_string_v1(b"/names"), hash_string_v1(b"/NAMES"));
+    }
+}
@@ -0,0 +1,393 @@
+use crate::msf::{LimitExceeded, ParseLimits};
+use crate::pdb::hashtable::{self, HashTableValue, SerializedHashTable};
+use crate::util;
+use std::io::{Read, Write};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Version of the `/src/headerblock` stream and of its entries.
+const SRC_HEADER_BLOCK_VERSION: u32 = 19980827;
+/// Size of a serialized [SrcHeaderBlockEntry].
+const SRC_HEADER_BLOCK_ENTRY_SIZE: u32 = 44;
+
+#[derive(Debug)]
+/// All of the errors that could possible be returned from this module
+pub enum Error {
+    /// A field of the header block could not be read
+    Field(util::FieldError),
+    /// The hash table of the header block is invalid
+    HashTable(hashtable::Error),
+    /// The header block version is not the one this crate knows
+    InvalidVersion(u32),
+    /// An entry has a size other than the one this crate knows
+    InvalidEntrySize(u32),
+    /// A name index is not in the `/names` string table
+    MissingName(u32),
+    /// The source file's stream holds data this crate cannot decompress
+    UnsupportedCompression(SourceCompression),
+    /// The compressed source file is corrupt
+    Decompress(String),
+    /// The source file is larger than the [ParseLimits] allow
+    LimitExceeded(LimitExceeded),
+}
+impl From<util::FieldError> for Error {
+    fn from(error: util::FieldError) -> Self {
+        Error::Field(error)
+    }
+}
+impl From<hashtable::Error> for Error {
+    fn from(error: hashtable::Error) -> Self {
+        match error {
+            hashtable::Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            hashtable::Error::Field(e) => Error::Field(e),
+            e => Error::HashTable(e),
+        }
+    }
+}
+impl From<LimitExceeded> for Error {
+    fn from(error: LimitExceeded) -> Self {
+        Error::LimitExceeded(error)
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Field(e) => e.fmt(f),
+            Error::HashTable(e) => e.fmt(f),
+            Error::InvalidVersion(ver) => write!(f, "unknown source header block version {}", ver),
+            Error::InvalidEntrySize(size) => write!(f, "invalid source header entry size {}", size),
+            Error::MissingName(offset) => write!(f, "no string at /names offset 0x{:x}", offset),
+            Error::UnsupportedCompression(c) => write!(f, "unsupported source compression {:?}", c),
+            Error::Decompress(e) => write!(f, "could not decompress source file: {}", e),
+            Error::LimitExceeded(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Field(e) => e.source(),
+            Error::HashTable(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// How an injected source file is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceCompression {
+    None,
+    RunLengthEncoded,
+    Huffman,
+    Lz,
+    /// The embedded source format of .NET PDBs: a u32 uncompressed size, zero if the data is
+    /// stored as is, followed by raw deflate data.
+    DotNet,
+    /// A value this crate does not know, kept as is.
+    Unknown(u8),
+}
+impl From<u8> for SourceCompression {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => SourceCompression::None,
+            1 => SourceCompression::RunLengthEncoded,
+            2 => SourceCompression::Huffman,
+            3 => SourceCompression::Lz,
+            101 => SourceCompression::DotNet,
+            v => SourceCompression::Unknown(v),
+        }
+    }
+}
+
+/// An entry of the `/src/headerblock` hash table, keyed on the `/names` offset of the virtual
+/// file name.
+#[derive(Debug, Clone, Copy)]
+pub struct SrcHeaderBlockEntry {
+    size: u32,
+    version: u32,
+    crc: u32,
+    file_size: u32,
+    file_name: u32,
+    object_name: u32,
+    virtual_name: u32,
+    compression: u8,
+    is_virtual: u8,
+}
+
+impl HashTableValue for SrcHeaderBlockEntry {
+    fn load(reader: &mut impl Read) -> std::result::Result<Self, hashtable::Error> {
+        let entry = SrcHeaderBlockEntry {
+            size: util::consume!(reader, u32, "size")?,
+            version: util::consume!(reader, u32, "version")?,
+            crc: util::consume!(reader, u32, "crc")?,
+            file_size: util::consume!(reader, u32, "file_size")?,
+            file_name: util::consume!(reader, u32, "file_name")?,
+            object_name: util::consume!(reader, u32, "object_name")?,
+            virtual_name: util::consume!(reader, u32, "virtual_name")?,
+            compression: util::consume!(reader, u8, "compression")?,
+            is_virtual: util::consume!(reader, u8, "is_virtual")?,
+        };
+        util::consume!(reader, 10, "reserved")?;
+        Ok(entry)
+    }
+    fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        for field in [
+            self.size,
+            self.version,
+            self.crc,
+            self.file_size,
+            self.file_name,
+            self.object_name,
+            self.virtual_name,
+        ] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        writer.write_all(&[self.compression, self.is_virtual])?;
+        writer.write_all(&[0; 10])
+    }
+}
+
+/// The `/src/headerblock` stream, which indexes the `/src/files/...` streams.
+#[derive(Debug, Default)]
+pub struct SrcHeaderBlock {
+    age: u32,
+    file_time: u64,
+    entries: SerializedHashTable<SrcHeaderBlockEntry>,
+}
+
+impl SrcHeaderBlock {
+    /// Load a SrcHeaderBlock from an MSFStream with the default [ParseLimits]
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Self::load_with_limits(reader, &ParseLimits::default())
+    }
+    /// Load a SrcHeaderBlock from an MSFStream, rejecting tables larger than `limits` allow
+    pub fn load_with_limits(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        let version = util::consume!(reader, u32, "version")?;
+        if version != SRC_HEADER_BLOCK_VERSION {
+            return Err(Error::InvalidVersion(version));
+        }
+        let _size = util::consume!(reader, u32, "size")?;
+        let file_time = util::consume!(reader, u64, "file_time")?;
+        let age = util::consume!(reader, u32, "age")?;
+        util::consume!(reader, 44, "padding")?;
+        let entries = SerializedHashTable::<SrcHeaderBlockEntry>::load_with_limits(reader, limits)?;
+        if let Some((_, entry)) = entries
+            .iter()
+            .find(|(_, e)| e.size != SRC_HEADER_BLOCK_ENTRY_SIZE)
+        {
+            return Err(Error::InvalidEntrySize(entry.size));
+        }
+        Ok(SrcHeaderBlock {
+            age,
+            file_time,
+            entries,
+        })
+    }
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+    /// When the block was written, as a Windows FILETIME.
+    pub fn file_time(&self) -> u64 {
+        self.file_time
+    }
+    /// The raw entries, in bucket order.
+    pub fn entries(&self) -> impl Iterator<Item = &SrcHeaderBlockEntry> + '_ {
+        self.entries.iter().map(|(_, entry)| entry)
+    }
+}
+
+/// An injected source file, with its names resolved through the `/names` string table.
+#[derive(Debug, Clone)]
+pub struct InjectedSource {
+    file_name: String,
+    object_name: String,
+    virtual_name: String,
+    size: u32,
+    crc: u32,
+    compression: SourceCompression,
+    is_virtual: bool,
+}
+
+impl InjectedSource {
+    /// Resolve the names of `entry` with `name_at`, which gives the string at a `/names` offset.
+    pub fn new(entry: &SrcHeaderBlockEntry, name_at: impl Fn(u32) -> Option<String>) -> Result<Self> {
+        let name = |offset| name_at(offset).ok_or(Error::MissingName(offset));
+        Ok(InjectedSource {
+            file_name: name(entry.file_name)?,
+            object_name: name(entry.object_name)?,
+            virtual_name: name(entry.virtual_name)?,
+            size: entry.file_size,
+            crc: entry.crc,
+            compression: SourceCompression::from(entry.compression),
+            is_virtual: entry.is_virtual != 0,
+        })
+    }
+    /// The path of the file when it was injected.
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+    /// The object file the source belongs to.
+    pub fn object_name(&self) -> &str {
+        &self.object_name
+    }
+    /// The virtual path the debugger knows the file by.
+    pub fn virtual_name(&self) -> &str {
+        &self.virtual_name
+    }
+    /// Name of the stream holding the file.
+    pub fn stream_name(&self) -> String {
+        format!("/src/files/{}", self.virtual_name)
+    }
+    /// Size of the original source file, once decompressed.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    /// Checksum of the original source file, once decompressed.
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    pub fn compression(&self) -> SourceCompression {
+        self.compression
+    }
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
+    }
+    /// Turn the contents of the file's stream into the source file.
+    pub fn decompress(&self, data: Vec<u8>, limits: &ParseLimits) -> Result<Vec<u8>> {
+        match self.compression {
+            SourceCompression::None => Ok(data),
+            SourceCompression::DotNet => {
+                let size = data
+                    .get(..4)
+                    .map(|s| u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+                    .ok_or_else(|| Error::Decompress("missing uncompressed size".to_string()))?;
+                if size == 0 {
+                    return Ok(data[4..].to_vec());
+                }
+                limits.check_allocation(size as u64)?;
+                let ret = miniz_oxide::inflate::decompress_to_vec_with_limit(&data[4..], size as usize)
+                    .map_err(|e| Error::Decompress(format!("{:?}", e.status)))?;
+                if ret.len() != size as usize {
+                    return Err(Error::Decompress(format!(
+                        "expected {} bytes, got {}",
+                        size,
+                        ret.len()
+                    )));
+                }
+                Ok(ret)
+            }
+            c => Err(Error::UnsupportedCompression(c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// `/names` offsets of the strings the entries refer to.
+    const NAMES: [(u32, &str); 4] = [
+        (1, "d:\\src\\foo.cpp"),
+        (16, "foo.obj"),
+        (24, "foo.cpp"),
+        (32, "gen.cs"),
+    ];
+    const PLAIN: &[u8] = b"int main() {}\n";
+    const DOTNET_SOURCE: &[u8] = b"class Gen { } // class Gen { } // class Gen { }\n";
+
+    fn entry(virtual_name: u32, file_size: u32, compression: u8) -> SrcHeaderBlockEntry {
+        SrcHeaderBlockEntry {
+            size: SRC_HEADER_BLOCK_ENTRY_SIZE,
+            version: SRC_HEADER_BLOCK_VERSION,
+            crc: 0x1234,
+            file_size,
+            file_name: 1,
+            object_name: 16,
+            virtual_name,
+            compression,
+            is_virtual: 0,
+        }
+    }
+
+    fn header_block(entries: &[SrcHeaderBlockEntry]) -> Vec<u8> {
+        let mut table = SerializedHashTable::new();
+        for entry in entries {
+            table.set(entry.virtual_name, *entry);
+        }
+        let mut stream = Vec::new();
+        stream.extend(SRC_HEADER_BLOCK_VERSION.to_le_bytes());
+        stream.extend(0_u32.to_le_bytes());
+        stream.extend(0x01D0_0000_0000_0000_u64.to_le_bytes());
+        stream.extend(3_u32.to_le_bytes());
+        stream.extend([0; 44]);
+        table.write(&mut stream).unwrap();
+        stream
+    }
+
+    fn name_at(offset: u32) -> Option<String> {
+        NAMES.iter().find(|(o, _)| *o == offset).map(|(_, name)| name.to_string())
+    }
+
+    #[test]
+    fn decode() {
+        let stream = header_block(&[
+            entry(24, PLAIN.len() as u32, 0),
+            entry(32, DOTNET_SOURCE.len() as u32, 101),
+        ]);
+        let block = SrcHeaderBlock::load(&mut Cursor::new(&stream)).unwrap();
+        assert_eq!(block.age(), 3);
+        assert_eq!(block.file_time(), 0x01D0_0000_0000_0000);
+        let sources: Vec<InjectedSource> = block
+            .entries()
+            .map(|entry| InjectedSource::new(entry, name_at).unwrap())
+            .collect();
+        assert_eq!(sources.len(), 2);
+        let plain = sources.iter().find(|s| s.virtual_name() == "foo.cpp").unwrap();
+        assert_eq!(plain.file_name(), "d:\\src\\foo.cpp");
+        assert_eq!(plain.object_name(), "foo.obj");
+        assert_eq!(plain.stream_name(), "/src/files/foo.cpp");
+        assert_eq!(plain.compression(), SourceCompression::None);
+        assert_eq!((plain.size(), plain.crc()), (PLAIN.len() as u32, 0x1234));
+        let limits = ParseLimits::default();
+        assert_eq!(plain.decompress(PLAIN.to_vec(), &limits).unwrap(), PLAIN);
+
+        let dotnet = sources.iter().find(|s| s.virtual_name() == "gen.cs").unwrap();
+        assert_eq!(dotnet.compression(), SourceCompression::DotNet);
+        assert_eq!(dotnet.size(), DOTNET_SOURCE.len() as u32);
+        let mut data = (DOTNET_SOURCE.len() as u32).to_le_bytes().to_vec();
+        data.extend(miniz_oxide::deflate::compress_to_vec(DOTNET_SOURCE, 6));
+        assert_eq!(dotnet.decompress(data.clone(), &limits).unwrap(), DOTNET_SOURCE);
+        // A size of zero means the source is stored as is.
+        let mut stored = vec![0; 4];
+        stored.extend(DOTNET_SOURCE);
+        assert_eq!(dotnet.decompress(stored, &limits).unwrap(), DOTNET_SOURCE);
+        // A stated size the data does not decompress to.
+        data[0] += 1;
+        assert!(matches!(dotnet.decompress(data, &limits), Err(Error::Decompress(_))));
+        assert!(matches!(dotnet.decompress(vec![1], &limits), Err(Error::Decompress(_))));
+    }
+
+    #[test]
+    fn reject_bad_blocks() {
+        let mut stream = header_block(&[entry(24, 1, 0)]);
+        stream[0] ^= 1;
+        assert!(matches!(
+            SrcHeaderBlock::load(&mut Cursor::new(&stream)),
+            Err(Error::InvalidVersion(19980826))
+        ));
+        let mut bad = entry(24, 1, 0);
+        bad.size = 40;
+        assert!(matches!(
+            SrcHeaderBlock::load(&mut Cursor::new(&header_block(&[bad]))),
+            Err(Error::InvalidEntrySize(40))
+        ));
+        let missing = entry(99, 1, 0);
+        assert!(matches!(InjectedSource::new(&missing, name_at), Err(Error::MissingName(99))));
+        let lz = InjectedSource::new(&entry(24, 1, 3), name_at).unwrap();
+        assert!(matches!(
+            lz.decompress(vec![0], &ParseLimits::default()),
+            Err(Error::UnsupportedCompression(SourceCompression::Lz))
+        ));
+    }
+}
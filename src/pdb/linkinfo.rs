@@ -0,0 +1,195 @@
+use crate::msf::{LimitExceeded, ParseLimits};
+use crate::util;
+use std::io::Read;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Size of the fixed part of the stream, before the strings.
+const LINK_INFO_HEADER_SIZE: u32 = 24;
+
+#[derive(Debug)]
+/// All of the errors that could possible be returned from this module
+pub enum Error {
+    /// A field of the link info could not be read
+    Field(util::FieldError),
+    /// The size in the header is smaller than the header itself
+    InvalidSize(u32),
+    /// A string offset points outside of the link info, or its string is not terminated
+    InvalidOffset(&'static str, u32),
+    /// The link info is larger than the [ParseLimits] allow
+    LimitExceeded(LimitExceeded),
+}
+impl From<util::FieldError> for Error {
+    fn from(error: util::FieldError) -> Self {
+        Error::Field(error)
+    }
+}
+impl From<LimitExceeded> for Error {
+    fn from(error: LimitExceeded) -> Self {
+        Error::LimitExceeded(error)
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Field(e) => e.fmt(f),
+            Error::InvalidSize(size) => write!(f, "invalid link info size {}", size),
+            Error::InvalidOffset(field, offset) => {
+                write!(f, "invalid {} offset 0x{:x}", field, offset)
+            }
+            Error::LimitExceeded(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Field(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// The `/LinkInfo` stream: where and how the linker was run.
+#[derive(Debug, Default)]
+pub struct LinkInfo {
+    version: u32,
+    cwd: String,
+    command: String,
+    output_file: String,
+    libraries: Vec<String>,
+}
+
+impl LinkInfo {
+    /// Load a LinkInfo from an MSFStream with the default [ParseLimits]
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Self::load_with_limits(reader, &ParseLimits::default())
+    }
+    /// Load a LinkInfo from an MSFStream, rejecting link info larger than `limits` allow
+    pub fn load_with_limits(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        let size = util::consume!(reader, u32, "size")?;
+        if size < LINK_INFO_HEADER_SIZE {
+            return Err(Error::InvalidSize(size));
+        }
+        let version = util::consume!(reader, u32, "version")?;
+        let cwd = util::consume!(reader, u32, "cwd_offset")?;
+        let command = util::consume!(reader, u32, "command_offset")?;
+        let output_file = util::consume!(reader, u32, "output_file_index")?;
+        let libraries = util::consume!(reader, u32, "libraries_offset")?;
+        limits.check_allocation((size - LINK_INFO_HEADER_SIZE) as u64)?;
+        let strings = util::consume_bytes(reader, (size - LINK_INFO_HEADER_SIZE) as u64, "strings")?;
+        // Offsets count from the start of the header.
+        let string_at = |field, offset: u32| {
+            offset
+                .checked_sub(LINK_INFO_HEADER_SIZE)
+                .and_then(|start| strings.get(start as usize..))
+                .and_then(|s| Some(&s[..s.iter().position(|b| *b == 0)?]))
+                .ok_or(Error::InvalidOffset(field, offset))
+        };
+        let command_bytes = string_at("command", command)?;
+        let output_bytes = command_bytes
+            .get(output_file as usize..)
+            .ok_or(Error::InvalidOffset("output file", output_file))?;
+        // The libraries are NUL-terminated strings ending with an empty one.
+        let mut libs = Vec::new();
+        let mut offset = libraries;
+        loop {
+            let lib = string_at("libraries", offset)?;
+            if lib.is_empty() {
+                break;
+            }
+            libs.push(String::from_utf8_lossy(lib).into_owned());
+            offset += lib.len() as u32 + 1;
+        }
+        Ok(LinkInfo {
+            version,
+            cwd: String::from_utf8_lossy(string_at("cwd", cwd)?).into_owned(),
+            command: String::from_utf8_lossy(command_bytes).into_owned(),
+            output_file: String::from_utf8_lossy(output_bytes).into_owned(),
+            libraries: libs,
+        })
+    }
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    /// The linker's current working directory.
+    pub fn cwd(&self) -> &str {
+        &self.cwd
+    }
+    /// The linker command line.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+    /// The command line from the output file on, which is where the linker points to.
+    pub fn output_file(&self) -> &str {
+        &self.output_file
+    }
+    /// The libraries the linker searched.
+    pub fn libraries(&self) -> &[String] {
+        &self.libraries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Serialize a link info whose header offsets are `offsets` (cwd, command, output file index,
+    /// libraries), followed by `strings`.
+    fn link_info(offsets: [u32; 4], strings: &[u8]) -> Vec<u8> {
+        let mut stream = Vec::new();
+        stream.extend((LINK_INFO_HEADER_SIZE + strings.len() as u32).to_le_bytes());
+        stream.extend(1_u32.to_le_bytes());
+        for offset in offsets {
+            stream.extend(offset.to_le_bytes());
+        }
+        stream.extend(strings);
+        stream
+    }
+
+    const STRINGS: &[u8] = b"c:\\build\0link.exe /out:a.dll a.obj\0kernel32.lib\0user32.lib\0\0";
+
+    #[test]
+    fn load() {
+        let stream = link_info([24, 33, 14, 59], STRINGS);
+        let info = LinkInfo::load(&mut Cursor::new(&stream)).unwrap();
+        assert_eq!(info.version(), 1);
+        assert_eq!(info.cwd(), "c:\\build");
+        assert_eq!(info.command(), "link.exe /out:a.dll a.obj");
+        assert_eq!(info.output_file(), "a.dll a.obj");
+        assert_eq!(info.libraries(), ["kernel32.lib", "user32.lib"]);
+    }
+
+    #[test]
+    fn reject_bad_offsets() {
+        let load = |offsets| LinkInfo::load(&mut Cursor::new(&link_info(offsets, STRINGS)));
+        // Before the strings, past the end, and into the unterminated tail of the strings.
+        assert!(matches!(load([0, 33, 14, 59]), Err(Error::InvalidOffset("cwd", 0))));
+        assert!(matches!(load([24, 1000, 14, 59]), Err(Error::InvalidOffset("command", 1000))));
+        assert!(matches!(load([24, 33, 100, 59]), Err(Error::InvalidOffset("output file", 100))));
+        assert!(matches!(load([24, 33, 14, u32::MAX]), Err(Error::InvalidOffset("libraries", u32::MAX))));
+        let unterminated = link_info([24, 24, 0, 24], b"c:\\build");
+        assert!(matches!(
+            LinkInfo::load(&mut Cursor::new(&unterminated)),
+            Err(Error::InvalidOffset("command", 24))
+        ));
+        // Libraries missing their terminating empty string.
+        let strings = &STRINGS[..STRINGS.len() - 1];
+        assert!(matches!(
+            LinkInfo::load(&mut Cursor::new(&link_info([24, 33, 14, 59], strings))),
+            Err(Error::InvalidOffset("libraries", 83))
+        ));
+    }
+
+    #[test]
+    fn reject_bad_sizes() {
+        let mut stream = link_info([24, 33, 14, 59], STRINGS);
+        stream[..4].copy_from_slice(&23_u32.to_le_bytes());
+        assert!(matches!(LinkInfo::load(&mut Cursor::new(&stream)), Err(Error::InvalidSize(23))));
+        stream[..4].copy_from_slice(&200_u32.to_le_bytes());
+        assert!(matches!(LinkInfo::load(&mut Cursor::new(&stream)), Err(Error::Field(_))));
+        stream[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(LinkInfo::load(&mut Cursor::new(&stream)), Err(Error::LimitExceeded(_))));
+    }
+}
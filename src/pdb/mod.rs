@@ -1,11 +1,17 @@
 
-mod hashtable;
-mod pdbstream;
+pub mod hashtable;
+pub mod injectedsource;
+pub mod linkinfo;
+pub mod pdbstream;
+pub mod stringtable;
 
 use crate::msf;
 use crate::util;
+use injectedsource::{InjectedSource, SrcHeaderBlock};
+use linkinfo::LinkInfo;
 use pdbstream::PdbStream;
-use std::io::{BufReader, Read};
+use stringtable::StringTable;
+use std::io::{Read, Seek};
 
 /// Result type alias for this module
 type Result<T> = std::result::Result<T, Error>;
@@ -17,6 +23,8 @@ pub enum Error {
     Unknown,
     /// Error consuming from the underlying  reader.
     Consume(std::io::Error),
+    /// A field could not be read.
+    Field(util::FieldError),
     /// There was an issue with the stream
     BadStream(u32, msf::Error),
     /// The version number was invalid
@@ -26,7 +34,17 @@ pub enum Error {
     /// key not found in the StreamMap.
     StreamMapKeyNotFound(String),
     /// Error parsing Stream
-    PdbStreamError(pdbstream::Error)
+    PdbStreamError(pdbstream::Error),
+    /// Error parsing the `/names` stream
+    StringTableError(stringtable::Error),
+    /// Error parsing the `/LinkInfo` stream
+    LinkInfoError(linkinfo::Error),
+    /// Error reading the injected source files
+    InjectedSourceError(injectedsource::Error),
+    /// The file asks for more than the [ParseLimits](msf::ParseLimits) of its MSF allow
+    LimitExceeded(msf::LimitExceeded),
+    /// The error happened while parsing the stream at the given location.
+    InStream(msf::StreamOffset, Box<Error>),
 }
 
 impl From<hashtable::Error> for Error{
@@ -41,7 +59,73 @@ impl From<std::io::Error> for Error{
 }
 impl From<pdbstream::Error> for Error{
     fn from(error: pdbstream::Error) -> Self{
-        Error::PdbStreamError(error)
+        match error {
+            pdbstream::Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            pdbstream::Error::Field(e) => Error::Field(e),
+            e => Error::PdbStreamError(e),
+        }
+    }
+}
+impl From<stringtable::Error> for Error{
+    fn from(error: stringtable::Error) -> Self{
+        match error {
+            stringtable::Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            stringtable::Error::Field(e) => Error::Field(e),
+            e => Error::StringTableError(e),
+        }
+    }
+}
+impl From<linkinfo::Error> for Error{
+    fn from(error: linkinfo::Error) -> Self{
+        match error {
+            linkinfo::Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            linkinfo::Error::Field(e) => Error::Field(e),
+            e => Error::LinkInfoError(e),
+        }
+    }
+}
+impl From<injectedsource::Error> for Error{
+    fn from(error: injectedsource::Error) -> Self{
+        match error {
+            injectedsource::Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            injectedsource::Error::Field(e) => Error::Field(e),
+            e => Error::InjectedSourceError(e),
+        }
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Unknown => write!(f, "unknown error"),
+            Error::Consume(e) => e.fmt(f),
+            Error::Field(e) => e.fmt(f),
+            Error::BadStream(stream, _) => write!(f, "could not read stream {}", stream),
+            Error::InvalidVersion => write!(f, "invalid PDB stream version"),
+            Error::HashTableError(e) => e.fmt(f),
+            Error::StreamMapKeyNotFound(name) => write!(f, "no stream named {:?}", name),
+            Error::PdbStreamError(e) => e.fmt(f),
+            Error::StringTableError(e) => e.fmt(f),
+            Error::LinkInfoError(e) => e.fmt(f),
+            Error::InjectedSourceError(e) => e.fmt(f),
+            Error::LimitExceeded(e) => e.fmt(f),
+            Error::InStream(location, _) => location.fmt(f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Consume(e) => e.source(),
+            Error::Field(e) => e.source(),
+            Error::BadStream(_, e) => Some(e),
+            Error::HashTableError(e) => e.source(),
+            Error::PdbStreamError(e) => e.source(),
+            Error::StringTableError(e) => e.source(),
+            Error::LinkInfoError(e) => e.source(),
+            Error::InjectedSourceError(e) => e.source(),
+            Error::InStream(_, e) => Some(e),
+            _ => None,
+        }
     }
 }
 
@@ -52,31 +136,216 @@ pub struct PDB {
 }
 
 impl PDB {
-    pub fn old_directory(
-        reader: &mut BufReader<std::fs::File>,
-        msf: &mut msf::MSF,
-    ) -> Result<Self> {
+    /// Load the `/names` string table, found through the named stream map.
+    pub fn string_table(&self, reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Result<StringTable> {
+        let stream_no = self.pdb_strm.get_stream_number("/names".to_string())?;
+        let mut msfsr = msf::MSFStreamReader::new(reader, msf, stream_no)
+            .map_err(|x| Error::BadStream(stream_no, x))?;
+        StringTable::load_with_limits(&mut msfsr, msf.limits())
+            .map_err(|e| Self::in_stream(&mut msfsr, msf, stream_no, e.into()))
+    }
+    /// Load the `/LinkInfo` stream. The linker usually leaves it empty, which gives None, as
+    /// does a PDB without one.
+    pub fn link_info(&self, reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Result<Option<LinkInfo>> {
+        let Some(stream_no) = self.pdb_strm.named_stream_map().get("/LinkInfo") else {
+            return Ok(None);
+        };
+        let mut msfsr = msf::MSFStreamReader::new(reader, msf, stream_no)
+            .map_err(|x| Error::BadStream(stream_no, x))?;
+        if msfsr.stream_size() == 0 {
+            return Ok(None);
+        }
+        LinkInfo::load_with_limits(&mut msfsr, msf.limits())
+            .map(Some)
+            .map_err(|e| Self::in_stream(&mut msfsr, msf, stream_no, e.into()))
+    }
+    /// List the source files injected into the PDB, as indexed by `/src/headerblock`. A PDB
+    /// without one has none.
+    pub fn injected_sources(&self, reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Result<Vec<InjectedSource>> {
+        let Some(stream_no) = self.pdb_strm.named_stream_map().get("/src/headerblock") else {
+            return Ok(Vec::new());
+        };
+        let mut msfsr = msf::MSFStreamReader::new(&mut *reader, msf, stream_no)
+            .map_err(|x| Error::BadStream(stream_no, x))?;
+        let block = SrcHeaderBlock::load_with_limits(&mut msfsr, msf.limits())
+            .map_err(|e| Self::in_stream(&mut msfsr, msf, stream_no, e.into()))?;
+        let strings = self.string_table(reader, msf)?;
+        let sources = block
+            .entries()
+            .map(|entry| InjectedSource::new(entry, |offset| strings.get(offset).map(|s| s.into_owned())))
+            .collect::<std::result::Result<_, _>>()?;
+        Ok(sources)
+    }
+    /// Read an injected source file, decompressing it if needed.
+    pub fn read_injected_source(
+        &self,
+        reader: &mut (impl Read + Seek),
+        msf: &msf::MSF,
+        source: &InjectedSource,
+    ) -> Result<Vec<u8>> {
+        let stream_no = self.pdb_strm.get_stream_number(source.stream_name())?;
+        let data = msf
+            .read_stream(reader, stream_no)
+            .map_err(|x| Error::BadStream(stream_no, x))?;
+        Ok(source.decompress(data, msf.limits())?)
+    }
+    /// Recover the StreamDirectory from before the last commit, stored in stream 0.
+    pub fn old_directory(reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Result<msf::MSF> {
+        msf.previous(reader).map_err(|x| Error::BadStream(0, x))
+    }
+    pub fn pdb_stream(reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Result<Self> {
         let mut ret = Self::default();
         let mut msfsr =
-            msf::MSFStreamReader::new(reader, msf, 0).map_err(|x| Error::BadStream(0, x))?;
+            msf::MSFStreamReader::new(reader, msf, 1).map_err(|x| Error::BadStream(1, x))?;
 
         //msfsr.seek(SeekFrom::Start(8191)).map_err(|x|{
         //    PdbError::Seek(x)
         //})?;
-        let a = util::consume!(msfsr, 0x2000, "Test");
-        println!("{:?}", a);
+        let pdb_stream = PdbStream::load_with_limits(&mut msfsr, msf.limits())
+            .map_err(|e| Self::in_stream(&mut msfsr, msf, 1, e.into()))?;
+        ret.pdb_strm = pdb_stream;
         Ok(ret)
     }
-    pub fn pdb_stream(reader: &mut BufReader<std::fs::File>, msf: &mut msf::MSF) -> Result<Self> {
-        let mut ret = Self::default();
-        let mut msfsr =
-            msf::MSFStreamReader::new(reader, msf, 1).map_err(|x| Error::BadStream(0, x))?;
+    /// Attach the location `msfsr` stopped at to an error from parsing stream `stream_no`. A
+    /// field that was cut short is reported at its start.
+    fn in_stream<R: Read + Seek>(
+        msfsr: &mut msf::MSFStreamReader<R>,
+        msf: &msf::MSF,
+        stream_no: u32,
+        error: Error,
+    ) -> Error {
+        let mut position = msfsr.stream_position().unwrap_or(0);
+        if let Error::Field(e) = &error {
+            position = position.saturating_sub(e.bytes_read() as u64);
+        }
+        Error::InStream(msf.stream_offset(stream_no, position), Box::new(error))
+    }
+    /// Check that the PDB info stream (stream 1) is well formed.
+    pub fn check_info_stream(reader: &mut (impl Read + Seek), msf: &msf::MSF) -> Option<msf::Problem> {
+        let mut msfsr = match msf::MSFStreamReader::new(reader, msf, 1) {
+            Ok(msfsr) => msfsr,
+            Err(e) => {
+                return Some(msf::Problem::new(None, format!("missing PDB info stream: {}", e)))
+            }
+        };
+        let e = PdbStream::load_with_limits(&mut msfsr, msf.limits()).err()?;
+        match Self::in_stream(&mut msfsr, msf, 1, e.into()) {
+            Error::InStream(location, e) => Some(msf::Problem::new(
+                location.file_offset,
+                format!(
+                    "stream 1 offset 0x{:x}: malformed PDB info stream: {}",
+                    location.offset, e
+                ),
+            )),
+            e => Some(msf::Problem::new(None, format!("malformed PDB info stream: {}", e))),
+        }
+    }
+    /// The parsed PDB info stream (stream 1).
+    pub fn info(&self) -> &PdbStream {
+        &self.pdb_strm
+    }
+    /// The PDB info stream, for editing its named stream map.
+    pub fn info_mut(&mut self) -> &mut PdbStream {
+        &mut self.pdb_strm
+    }
+}
 
-        //msfsr.seek(SeekFrom::Start(8191)).map_err(|x|{
-        //    PdbError::Seek(x)
-        //})?;
-        let pdb_stream = PdbStream::load(&mut msfsr)?;
-        println!("{:?}", pdb_stream.get_stream_number(String::from("/LinkInfo")));
-        Ok(ret)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Stream 1 of `pdb/ntdll.pdb`.
+    fn ntdll_info() -> Vec<u8> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("pdb/ntdll.pdb");
+        let (mut reader, msf) = msf::MSF::open(path).unwrap();
+        msf.read_stream(&mut reader, 1).unwrap()
+    }
+
+    /// A file whose stream 1 is `info`.
+    fn with_info(info: Vec<u8>) -> Vec<u8> {
+        let mut builder = msf::MsfBuilder::new(512).unwrap();
+        builder.add_stream(Vec::new());
+        builder.add_stream(info);
+        let mut file = Cursor::new(Vec::new());
+        builder.write(&mut file).unwrap();
+        file.into_inner()
+    }
+
+    /// Every message along the source chain of `error`, starting with its own.
+    fn chain(error: &dyn std::error::Error) -> Vec<String> {
+        let mut ret = vec![error.to_string()];
+        let mut source = error.source();
+        while let Some(e) = source {
+            ret.push(e.to_string());
+            source = e.source();
+        }
+        ret
+    }
+
+    #[test]
+    fn truncated_info_stream() {
+        // Cut stream 1 four bytes into the GUID, which starts at offset 0xc.
+        let data = with_info(ntdll_info()[..0x10].to_vec());
+        let mut reader = Cursor::new(&data[..]);
+        let msf = msf::MSF::load(&mut reader).unwrap();
+        let file_offset = msf.file_offset(1, 0xc).unwrap();
+        let error = PDB::pdb_stream(&mut reader, &msf).err().unwrap();
+        match &error {
+            Error::InStream(location, e) => {
+                assert_eq!(
+                    *location,
+                    msf::StreamOffset {
+                        stream: 1,
+                        offset: 0xc,
+                        file_offset: Some(file_offset),
+                    }
+                );
+                assert!(matches!(&**e, Error::Field(e) if e.field() == "unique_id" && e.bytes_read() == 4));
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+        assert_eq!(
+            chain(&error),
+            [
+                format!("stream 1 offset 0xc (file offset 0x{:x})", file_offset),
+                "truncated while reading unique_id".to_string(),
+            ]
+        );
+        let problem = PDB::check_info_stream(&mut reader, &msf).unwrap();
+        assert_eq!(problem.offset, Some(file_offset));
+        assert_eq!(
+            problem.description,
+            "stream 1 offset 0xc: malformed PDB info stream: truncated while reading unique_id"
+        );
+    }
+
+    #[test]
+    fn truncated_file() {
+        let data = with_info(ntdll_info());
+        let msf = msf::MSF::load(&mut Cursor::new(&data[..])).unwrap();
+        // The file ends inside the GUID, although the directory says stream 1 goes on.
+        let cut = msf.file_offset(1, 0x14).unwrap() as usize;
+        let error = PDB::pdb_stream(&mut Cursor::new(&data[..cut]), &msf).err().unwrap();
+        assert_eq!(
+            chain(&error),
+            [
+                format!("stream 1 offset 0xc (file offset 0x{:x})", cut - 8),
+                "could not read unique_id".to_string(),
+                "unexpected end of file".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn complete_info_stream() {
+        let data = with_info(ntdll_info());
+        let mut reader = Cursor::new(&data[..]);
+        let msf = msf::MSF::load(&mut reader).unwrap();
+        let pdb = PDB::pdb_stream(&mut reader, &msf).unwrap();
+        assert_eq!(pdb.info().header().age(), 3);
+        assert!(PDB::check_info_stream(&mut reader, &msf).is_none());
+        // The named streams point at streams this file does not have.
+        assert!(matches!(pdb.string_table(&mut reader, &msf), Err(Error::BadStream(233, _))));
     }
 }
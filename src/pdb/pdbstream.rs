@@ -1,7 +1,8 @@
 use crate::msf;
-use crate::pdb::hashtable::SerializedHashTable;
+use crate::msf::{LimitExceeded, ParseLimits};
+use crate::pdb::hashtable::{self, HashTableTraits, SerializedHashTable};
 use crate::util;
-use std::io::Read;
+use std::io::{Read, Write};
 
 type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
@@ -11,16 +12,22 @@ pub enum Error {
     Unknown,
     /// Error consuming from the underlying  reader.
     Consume(std::io::Error),
+    /// A field of the stream could not be read.
+    Field(util::FieldError),
     /// There was an issue with the stream
     BadStream(u32, msf::Error),
     /// The version number was invalid
     InvalidVersion,
     /// The read HashTable was invalid
-    HashTableError(crate::pdb::hashtable::Error),
+    HashTable(crate::pdb::hashtable::Error),
     /// key not found in the StreamMap.
     StreamMapKeyNotFound(String),
-    /// Feature code unrecognized
-    UnkownFeatureCode(u32)
+    /// The name is already in the StreamMap.
+    StreamMapKeyExists(String),
+    /// A stream name cannot contain a NUL character.
+    InvalidStreamName(String),
+    /// The stream asks for more than the [ParseLimits] allow
+    LimitExceeded(LimitExceeded),
 }
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
@@ -29,9 +36,52 @@ impl From<std::io::Error> for Error {
 }
 impl From<crate::pdb::hashtable::Error> for Error {
     fn from(error: crate::pdb::hashtable::Error) -> Self {
-        Error::HashTableError(error)
+        match error {
+            crate::pdb::hashtable::Error::LimitExceeded(e) => Error::LimitExceeded(e),
+            crate::pdb::hashtable::Error::Field(e) => Error::Field(e),
+            e => Error::HashTable(e),
+        }
+    }
+}
+impl From<util::FieldError> for Error {
+    fn from(error: util::FieldError) -> Self {
+        Error::Field(error)
+    }
+}
+impl From<LimitExceeded> for Error {
+    fn from(error: LimitExceeded) -> Self {
+        Error::LimitExceeded(error)
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Unknown => write!(f, "unknown error"),
+            Error::Consume(e) => e.fmt(f),
+            Error::Field(e) => e.fmt(f),
+            Error::BadStream(stream, _) => write!(f, "could not read stream {}", stream),
+            Error::InvalidVersion => write!(f, "invalid PDB stream version"),
+            Error::HashTable(e) => e.fmt(f),
+            Error::StreamMapKeyNotFound(name) => write!(f, "no stream named {:?}", name),
+            Error::StreamMapKeyExists(name) => write!(f, "a stream named {:?} already exists", name),
+            Error::InvalidStreamName(name) => write!(f, "invalid stream name {:?}", name),
+            Error::LimitExceeded(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Consume(e) => e.source(),
+            Error::Field(e) => e.source(),
+            Error::BadStream(_, e) => Some(e),
+            Error::HashTable(e) => e.source(),
+            _ => None,
+        }
     }
 }
+/// Version of the PDB info stream, named after the compiler release that introduced it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PDBStreamVersion {
     VC2 = 19941610,
     VC4 = 19950623,
@@ -39,120 +89,463 @@ pub enum PDBStreamVersion {
     VC50 = 19960307,
     VC98 = 19970604,
     VC70Dep = 19990604,
+    #[default]
     VC70 = 20000404,
     VC80 = 20030901,
     VC110 = 20091201,
     VC140 = 20140508,
 }
 
+impl PDBStreamVersion {
+    /// The version with the raw value `version`, if it is one this crate knows.
+    pub fn from_u32(version: u32) -> Option<Self> {
+        [
+            PDBStreamVersion::VC2,
+            PDBStreamVersion::VC4,
+            PDBStreamVersion::VC41,
+            PDBStreamVersion::VC50,
+            PDBStreamVersion::VC98,
+            PDBStreamVersion::VC70Dep,
+            PDBStreamVersion::VC70,
+            PDBStreamVersion::VC80,
+            PDBStreamVersion::VC110,
+            PDBStreamVersion::VC140,
+        ]
+        .into_iter()
+        .find(|v| *v as u32 == version)
+    }
+    /// Whether the header carries a GUID after the age. Older PDBs are identified by their
+    /// signature and age alone.
+    pub fn has_unique_id(&self) -> bool {
+        *self >= PDBStreamVersion::VC70
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct PDBStreamHeader {
-    version: u32,
+    version: PDBStreamVersion,
     signature: u32,
     age: u32,
     unique_id: u128,
 }
+/// Maps stream names such as `/names` or `/LinkInfo` to stream numbers.
 #[derive(Debug, Default)]
 pub struct NamedStreamMap {
-    str_len: u32,
-    strings: Vec<String>,
+    /// The NUL-terminated names; the hash table is keyed on offsets into it.
+    buffer: Vec<u8>,
     hash_table: SerializedHashTable<u32>,
+    /// The reference implementation's next name index (`niMac`), stored after the hash table.
+    /// It is zero in every PDB the linker writes, and kept as is.
+    ni_mac: u32,
+}
+
+/// Hashes the offsets of a [NamedStreamMap]'s hash table as the names they point to.
+struct NameTraits<'a> {
+    buffer: &'a [u8],
+}
+
+impl NameTraits<'_> {
+    fn name_at(&self, offset: u32) -> &[u8] {
+        let name = self.buffer.get(offset as usize..).unwrap_or_default();
+        let len = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        &name[..len]
+    }
+}
+
+impl HashTableTraits for NameTraits<'_> {
+    type Key = str;
+    fn hash(&self, key: &str) -> u32 {
+        // The reference implementation keeps only the low 16 bits of the hash.
+        hashtable::hash_string_v1(key.as_bytes()) as u16 as u32
+    }
+    fn hash_stored(&self, stored: u32) -> u32 {
+        hashtable::hash_string_v1(self.name_at(stored)) as u16 as u32
+    }
+    fn matches(&self, stored: u32, key: &str) -> bool {
+        self.name_at(stored) == key.as_bytes()
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct PdbStream {
     hdr: PDBStreamHeader,
     stream_map: NamedStreamMap,
-   // feature_codes : PDBFeatureCodeList
+    feature_codes: PDBFeatureCodeList,
 }
-#[derive(std::cmp::PartialEq,Debug)]
-enum PDBFeatureCode {
-    VC110 = 20091201,
-    VC140 = 20140508,
-    NoTypeMerge = 0x4D544F4E,
-    MinimalDebugInfo = 0x494E494D,
+/// A feature code from the list at the end of the PDB info stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PDBFeatureCode {
+    /// The PDB has an IPI stream (stream 4).
+    VC110,
+    /// The PDB has an IPI stream (stream 4).
+    VC140,
+    /// Types were not merged into the TPI stream (/DEBUG:CTYPES).
+    NoTypeMerge,
+    /// The PDB was linked with /DEBUG:FASTLINK and only refers to the objects' debug info.
+    MinimalDebugInfo,
+    /// A code this crate does not know, kept as is.
+    Unknown(u32),
+}
+impl PDBFeatureCode {
+    const NO_TYPE_MERGE: u32 = 0x4D544F4E;
+    const MINIMAL_DEBUG_INFO: u32 = 0x494E494D;
+
+    /// The raw value stored in the stream.
+    pub fn code(&self) -> u32 {
+        match self {
+            PDBFeatureCode::VC110 => PDBStreamVersion::VC110 as u32,
+            PDBFeatureCode::VC140 => PDBStreamVersion::VC140 as u32,
+            PDBFeatureCode::NoTypeMerge => Self::NO_TYPE_MERGE,
+            PDBFeatureCode::MinimalDebugInfo => Self::MINIMAL_DEBUG_INFO,
+            PDBFeatureCode::Unknown(code) => *code,
+        }
+    }
+}
+impl From<u32> for PDBFeatureCode {
+    fn from(code: u32) -> Self {
+        match code {
+            c if c == PDBStreamVersion::VC110 as u32 => PDBFeatureCode::VC110,
+            c if c == PDBStreamVersion::VC140 as u32 => PDBFeatureCode::VC140,
+            Self::NO_TYPE_MERGE => PDBFeatureCode::NoTypeMerge,
+            Self::MINIMAL_DEBUG_INFO => PDBFeatureCode::MinimalDebugInfo,
+            c => PDBFeatureCode::Unknown(c),
+        }
+    }
 }
+/// The feature codes that follow the named stream map.
 #[derive(Debug, Default)]
-struct PDBFeatureCodeList {
+pub struct PDBFeatureCodeList {
     codes: Vec<PDBFeatureCode>,
 }
 impl PDBFeatureCodeList {
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        let ret: Vec<PDBFeatureCode> = Vec::with_capacity(1);
-        let val = util::consume!(reader, u32, "test")?;
-
-        if PDBFeatureCode::VC110 as u32 != val
-            && PDBFeatureCode::VC140 as u32 != val
-            && PDBFeatureCode::MinimalDebugInfo as u32 != val
-            && PDBFeatureCode::NoTypeMerge as u32 != val
-        {return Err(Error::UnkownFeatureCode(val));}
-        Ok(PDBFeatureCodeList { codes: ret })
+    /// Read feature codes up to the end of the stream.
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        let mut codes = Vec::new();
+        loop {
+            match util::consume!(reader, u32, "feature_code") {
+                Ok(code) => codes.push(PDBFeatureCode::from(code)),
+                // The list simply runs to the end of the stream.
+                Err(e) if e.bytes_read() == 0 && std::error::Error::source(&e).is_none() => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(PDBFeatureCodeList { codes })
+    }
+    /// Serialize the list as [load](PDBFeatureCodeList::load) expects it.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        for code in &self.codes {
+            writer.write_all(&code.code().to_le_bytes())?;
+        }
+        Ok(())
+    }
+    pub fn codes(&self) -> &[PDBFeatureCode] {
+        &self.codes
+    }
+    /// Whether the PDB has an IPI stream (stream 4).
+    pub fn has_ipi_stream(&self) -> bool {
+        self.codes
+            .iter()
+            .any(|c| matches!(c, PDBFeatureCode::VC110 | PDBFeatureCode::VC140))
+    }
+    /// Whether types were left unmerged (/DEBUG:CTYPES).
+    pub fn no_type_merge(&self) -> bool {
+        self.codes.contains(&PDBFeatureCode::NoTypeMerge)
+    }
+    /// Whether the PDB was linked with /DEBUG:FASTLINK.
+    pub fn minimal_debug_info(&self) -> bool {
+        self.codes.contains(&PDBFeatureCode::MinimalDebugInfo)
     }
 }
 impl NamedStreamMap {
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        let mut strm_map = NamedStreamMap::default();
-        strm_map.str_len = util::consume!(reader, u32, "str_len")?;
-        let mut b = vec![0_u8; strm_map.str_len as usize];
-        let bytes = reader
-            .read_exact(&mut b)
-            .map(|_| b)
-            .map_err(|x| Error::Consume(x))
-            .expect("What?");
-        strm_map.strings = Vec::with_capacity(1);
-        let mut last_str_pos = 0;
-        for (i, b) in bytes.iter().enumerate() {
-            if *b == 0_u8 {
-                strm_map.strings.push(
-                    std::str::from_utf8(&bytes[last_str_pos..i])
-                        .unwrap()
-                        .to_string(),
-                );
-                last_str_pos = i + 1;
-            }
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Self::load_with_limits(reader, &ParseLimits::default())
+    }
+    pub fn load_with_limits(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        let str_len = util::consume!(reader, u32, "str_len")?;
+        limits.check_allocation(str_len as u64)?;
+        Ok(NamedStreamMap {
+            buffer: util::consume_bytes(reader, str_len as u64, "buffer")?,
+            hash_table: SerializedHashTable::load_with_limits(reader, limits)?,
+            ni_mac: util::consume!(reader, u32, "ni_mac")?,
+        })
+    }
+    /// Serialize the map as [load](NamedStreamMap::load) expects it.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&(self.buffer.len() as u32).to_le_bytes())?;
+        writer.write_all(&self.buffer)?;
+        self.hash_table.write(writer)?;
+        writer.write_all(&self.ni_mac.to_le_bytes())
+    }
+    fn traits(&self) -> NameTraits<'_> {
+        NameTraits {
+            buffer: &self.buffer,
         }
-        println!("Found the following streams: {:?}", strm_map.strings);
-        strm_map.hash_table = SerializedHashTable::load(reader)?;
-        println!("{:?}", strm_map.hash_table);
-        return Ok(strm_map);
     }
 
-    pub fn get_stream_number(&self, name: String) -> Result<u32> {
-        let pos = self
-            .strings
+    /// Get every (name, stream number) pair in the map.
+    pub fn entries(&self) -> Vec<(String, u32)> {
+        let traits = self.traits();
+        self.hash_table
             .iter()
-            .position(|r| *r == name)
-            .ok_or(Error::StreamMapKeyNotFound(name))?;
-        return Ok(self.hash_table.get(pos as u32)?);
+            .map(|(offset, stream)| {
+                let name = traits.name_at(offset);
+                (String::from_utf8_lossy(name).into_owned(), *stream)
+            })
+            .collect()
+    }
+    /// The stream number of `name`, if it is in the map.
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.hash_table.get_as(name, &self.traits()).copied()
+    }
+    pub fn get_stream_number(&self, name: String) -> Result<u32> {
+        self.get(&name).ok_or(Error::StreamMapKeyNotFound(name))
+    }
+    /// Map `name` to `stream`, returning the stream it was mapped to before.
+    pub fn insert(&mut self, name: &str, stream: u32) -> Result<Option<u32>> {
+        if name.contains('\0') {
+            return Err(Error::InvalidStreamName(name.to_string()));
+        }
+        // The offset is only stored if the name is new.
+        let offset = self.buffer.len() as u32;
+        if self.get(name).is_none() {
+            self.buffer.extend_from_slice(name.as_bytes());
+            self.buffer.push(0);
+        }
+        let traits = NameTraits {
+            buffer: &self.buffer,
+        };
+        Ok(self.hash_table.set_as(name, offset, stream, &traits))
+    }
+    /// Remove `name` from the map, returning its stream number. Like the linker, this leaves
+    /// the name in the string buffer.
+    pub fn remove(&mut self, name: &str) -> Result<u32> {
+        let traits = NameTraits {
+            buffer: &self.buffer,
+        };
+        self.hash_table
+            .remove_as(name, &traits)
+            .ok_or_else(|| Error::StreamMapKeyNotFound(name.to_string()))
+    }
+    /// Give the stream named `from` the name `to`.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        if from == to {
+            return self.get_stream_number(from.to_string()).map(|_| ());
+        }
+        if self.get(to).is_some() {
+            return Err(Error::StreamMapKeyExists(to.to_string()));
+        }
+        if to.contains('\0') {
+            return Err(Error::InvalidStreamName(to.to_string()));
+        }
+        let stream = self.remove(from)?;
+        self.insert(to, stream)?;
+        Ok(())
     }
 }
 impl PDBStreamHeader {
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        let ret = PDBStreamHeader {
-            version: util::consume!(reader, u32, "version")?,
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        let version = util::consume!(reader, u32, "version")?;
+        let version = PDBStreamVersion::from_u32(version).ok_or(Error::InvalidVersion)?;
+        Ok(PDBStreamHeader {
+            version,
             signature: util::consume!(reader, u32, "signature")?,
             age: util::consume!(reader, u32, "age")?,
-            unique_id: util::consume!(reader, u128, "unique_id")?,
-        };
-        if !ret.check_version(PDBStreamVersion::VC70) {
-            return Err(Error::InvalidVersion);
+            unique_id: if version.has_unique_id() {
+                util::consume!(reader, u128, "unique_id")?
+            } else {
+                0
+            },
+        })
+    }
+    /// Serialize the header as [load](PDBStreamHeader::load) expects it.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        writer.write_all(&(self.version as u32).to_le_bytes())?;
+        writer.write_all(&self.signature.to_le_bytes())?;
+        writer.write_all(&self.age.to_le_bytes())?;
+        if self.version.has_unique_id() {
+            writer.write_all(&self.unique_id.to_le_bytes())?;
         }
-        return Ok(ret);
+        Ok(())
     }
     pub fn check_version(&self, other_ver: PDBStreamVersion) -> bool {
-        self.version == other_ver as u32
+        self.version == other_ver
+    }
+    pub fn version(&self) -> PDBStreamVersion {
+        self.version
+    }
+    pub fn signature(&self) -> u32 {
+        self.signature
+    }
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+    /// The GUID identifying the PDB, or zero for versions before VC70, which have none.
+    pub fn unique_id(&self) -> u128 {
+        self.unique_id
+    }
+    /// The key the PDB is stored under on a symbol server.
+    pub fn symbol_server_key(&self) -> String {
+        if self.version.has_unique_id() {
+            crate::symstore::guid_key(self.unique_id, self.age)
+        } else {
+            crate::symstore::signature_key(self.signature, self.age)
+        }
     }
 }
 
 impl PdbStream {
-    pub fn load(reader: &mut msf::MSFStreamReader<std::fs::File>) -> Result<Self> {
-        return Ok(PdbStream {
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Self::load_with_limits(reader, &ParseLimits::default())
+    }
+    pub fn load_with_limits(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        Ok(PdbStream {
             hdr: PDBStreamHeader::load(reader)?,
-            stream_map: NamedStreamMap::load(reader)?,
-           // feature_codes : PDBFeatureCodeList::load(reader)?
-        });
+            stream_map: NamedStreamMap::load_with_limits(reader, limits)?,
+            feature_codes: PDBFeatureCodeList::load(reader)?,
+        })
     }
     pub fn get_stream_number(&self, name: String) -> Result<u32> {
         self.stream_map.get_stream_number(name)
     }
+    /// Get every (name, stream number) pair of the named stream map.
+    pub fn named_streams(&self) -> Vec<(String, u32)> {
+        self.stream_map.entries()
+    }
+    /// The named stream map, for editing. Write the stream back with [write](PdbStream::write).
+    pub fn named_stream_map_mut(&mut self) -> &mut NamedStreamMap {
+        &mut self.stream_map
+    }
+    pub fn named_stream_map(&self) -> &NamedStreamMap {
+        &self.stream_map
+    }
+    /// Serialize the whole stream, ready to be stored as stream 1.
+    pub fn write(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        self.hdr.write(writer)?;
+        self.stream_map.write(writer)?;
+        self.feature_codes.write(writer)
+    }
+    pub fn header(&self) -> &PDBStreamHeader {
+        &self.hdr
+    }
+    /// The feature codes that end the stream, telling which other streams to expect.
+    pub fn feature_codes(&self) -> &PDBFeatureCodeList {
+        &self.feature_codes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Stream 1 of `pdb/ntdll.pdb`.
+    const NTDLL_INFO: &[u8] = &[
+        0x94, 0x2e, 0x31, 0x01, 0xd6, 0xd1, 0x80, 0xa2, 0x03, 0x00, 0x00, 0x00, 0xd5, 0x4e, 0xef, 0x96,
+        0x40, 0x37, 0xaa, 0x2d, 0xa5, 0x1d, 0x4a, 0x42, 0x12, 0xea, 0x4b, 0x2c, 0x11, 0x00, 0x00, 0x00,
+        0x2f, 0x4c, 0x69, 0x6e, 0x6b, 0x49, 0x6e, 0x66, 0x6f, 0x00, 0x2f, 0x6e, 0x61, 0x6d, 0x65, 0x73,
+        0x00, 0x02, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00, 0x00, 0x00, 0xe9, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xdc, 0x51, 0x33, 0x01,
+    ];
+
+    #[test]
+    fn load_info_stream() {
+        let info = PdbStream::load(&mut Cursor::new(NTDLL_INFO)).unwrap();
+        let hdr = info.header();
+        assert_eq!(hdr.version(), PDBStreamVersion::VC70);
+        assert_eq!(hdr.signature(), 0xa280d1d6);
+        assert_eq!(hdr.age(), 3);
+        assert_eq!(hdr.symbol_server_key(), "96EF4ED537402DAAA51D4A4212EA4B2C3");
+        let mut names = info.named_streams();
+        names.sort();
+        assert_eq!(names, [("/LinkInfo".to_string(), 5), ("/names".to_string(), 233)]);
+        // The zero after the hash table is the map's niMac, not a feature code.
+        assert_eq!(info.named_stream_map().ni_mac, 0);
+        assert_eq!(info.feature_codes().codes(), [PDBFeatureCode::VC140]);
+        assert!(info.feature_codes().has_ipi_stream());
+    }
+
+    fn write(info: &PdbStream) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        info.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn write_unchanged() {
+        let info = PdbStream::load(&mut Cursor::new(NTDLL_INFO)).unwrap();
+        assert_eq!(write(&info), NTDLL_INFO);
+    }
+
+    #[test]
+    fn vc98_header() {
+        // Version 19970604 (VC98), signature 0x3a5f0b21, age 2, and no GUID.
+        let header_bytes = [0x2c, 0xba, 0x30, 0x01, 0x21, 0x0b, 0x5f, 0x3a, 0x02, 0x00, 0x00, 0x00];
+        let header = PDBStreamHeader::load(&mut Cursor::new(&header_bytes[..])).unwrap();
+        assert_eq!(header.version(), PDBStreamVersion::VC98);
+        assert!(!header.version().has_unique_id());
+        assert_eq!(header.signature(), 0x3a5f0b21);
+        assert_eq!(header.age(), 2);
+        assert_eq!(header.unique_id(), 0);
+        assert_eq!(header.symbol_server_key(), "3A5F0B212");
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(bytes, header_bytes);
+
+        // The named stream map follows straight after the age.
+        let mut stream = header_bytes.to_vec();
+        stream.extend_from_slice(&NTDLL_INFO[0x1C..]);
+        let info = PdbStream::load(&mut Cursor::new(&stream)).unwrap();
+        assert_eq!(info.header().unique_id(), 0);
+        assert_eq!(info.get_stream_number("/names".to_string()).unwrap(), 233);
+        assert_eq!(write(&info), stream);
+    }
+
+    #[test]
+    fn edit_and_write() {
+        let mut info = PdbStream::load(&mut Cursor::new(NTDLL_INFO)).unwrap();
+        let map = info.named_stream_map_mut();
+        assert_eq!(map.insert("/src/headerblock", 7).unwrap(), None);
+        assert_eq!(map.insert("/names", 234).unwrap(), Some(233));
+        map.rename("/LinkInfo", "/LinkInfo2").unwrap();
+        assert!(matches!(map.rename("/names", "/LinkInfo2"), Err(Error::StreamMapKeyExists(_))));
+        assert!(matches!(map.insert("a\0b", 1), Err(Error::InvalidStreamName(_))));
+        for stream in 0..20 {
+            map.insert(&format!("/src/files/{stream}.cpp"), 300 + stream).unwrap();
+        }
+        assert_eq!(map.remove("/src/files/3.cpp").unwrap(), 303);
+        assert!(matches!(map.remove("/LinkInfo"), Err(Error::StreamMapKeyNotFound(_))));
+
+        let bytes = write(&info);
+        let loaded = PdbStream::load(&mut Cursor::new(&bytes)).unwrap();
+        assert_eq!(write(&loaded), bytes);
+        let mut names = loaded.named_streams();
+        names.sort();
+        let mut want = info.named_streams();
+        want.sort();
+        assert_eq!(names, want);
+        assert_eq!(names.len(), 22);
+        let map = loaded.named_stream_map();
+        assert_eq!(map.get("/names"), Some(234));
+        assert_eq!(map.get("/LinkInfo2"), Some(5));
+        assert_eq!(map.get("/LinkInfo"), None);
+        assert_eq!(map.get("/src/files/3.cpp"), None);
+        assert_eq!(map.get("/src/files/19.cpp"), Some(319));
+        // The header and feature codes are written back as they were.
+        assert_eq!(bytes[..0x1C], NTDLL_INFO[..0x1C]);
+        assert_eq!(bytes[bytes.len() - 8..], NTDLL_INFO[NTDLL_INFO.len() - 8..]);
+    }
+
+    #[test]
+    fn reject_truncated_map() {
+        // Cut the stream off in the middle of niMac.
+        let end = NTDLL_INFO.len() - 6;
+        assert!(matches!(
+            PdbStream::load(&mut Cursor::new(&NTDLL_INFO[..end])),
+            Err(Error::Field(_))
+        ));
+        let mut bad = NTDLL_INFO.to_vec();
+        bad[0] = 0;
+        assert!(matches!(PdbStream::load(&mut Cursor::new(&bad)), Err(Error::InvalidVersion)));
+    }
 }
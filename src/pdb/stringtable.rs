@@ -0,0 +1,242 @@
+use crate::msf::{LimitExceeded, ParseLimits};
+use crate::pdb::hashtable::hash_string_v1;
+use crate::util;
+use std::borrow::Cow;
+use std::io::Read;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Signature at the start of the `/names` stream.
+const STRING_TABLE_SIGNATURE: u32 = 0xEFFEEFFE;
+
+#[derive(Debug)]
+/// All of the errors that could possible be returned from this module
+pub enum Error {
+    /// A field of the string table could not be read
+    Field(util::FieldError),
+    /// The stream does not start with the string table signature
+    InvalidSignature(u32),
+    /// The hash version is neither 1 nor 2
+    InvalidHashVersion(u32),
+    /// The string table is larger than the [ParseLimits] allow
+    LimitExceeded(LimitExceeded),
+}
+impl From<util::FieldError> for Error {
+    fn from(error: util::FieldError) -> Self {
+        Error::Field(error)
+    }
+}
+impl From<LimitExceeded> for Error {
+    fn from(error: LimitExceeded) -> Self {
+        Error::LimitExceeded(error)
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Field(e) => e.fmt(f),
+            Error::InvalidSignature(sig) => write!(f, "invalid string table signature 0x{:08x}", sig),
+            Error::InvalidHashVersion(ver) => write!(f, "unknown string table hash version {}", ver),
+            Error::LimitExceeded(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Field(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+/// The string hash of version 2 string tables (`hashStringV2` in the reference implementation).
+pub fn hash_string_v2(bytes: &[u8]) -> u32 {
+    let mut chunks = bytes.chunks_exact(4);
+    let mut hash = 0xb170a1bf_u32;
+    let mut mix = |item: u32| {
+        hash = hash.wrapping_add(item);
+        hash = hash.wrapping_add(hash << 10);
+        hash ^= hash >> 6;
+    };
+    for c in chunks.by_ref() {
+        mix(u32::from_le_bytes([c[0], c[1], c[2], c[3]]));
+    }
+    for b in chunks.remainder() {
+        mix(*b as u32);
+    }
+    hash.wrapping_mul(1664525).wrapping_add(1013904223)
+}
+
+/// The `/names` stream: the NUL-terminated strings other streams refer to by offset, and a hash
+/// table of those offsets to find a string's offset.
+#[derive(Debug, Default)]
+pub struct StringTable {
+    hash_version: u32,
+    buffer: Vec<u8>,
+    /// Offsets of the strings, probed linearly from the hash; zero marks an empty bucket.
+    buckets: Vec<u32>,
+    name_count: u32,
+}
+
+impl StringTable {
+    /// Load a StringTable from an MSFStream with the default [ParseLimits]
+    pub fn load(reader: &mut impl Read) -> Result<Self> {
+        Self::load_with_limits(reader, &ParseLimits::default())
+    }
+    /// Load a StringTable from an MSFStream, rejecting tables larger than `limits` allow
+    pub fn load_with_limits(reader: &mut impl Read, limits: &ParseLimits) -> Result<Self> {
+        let signature = util::consume!(reader, u32, "signature")?;
+        if signature != STRING_TABLE_SIGNATURE {
+            return Err(Error::InvalidSignature(signature));
+        }
+        let hash_version = util::consume!(reader, u32, "hash_version")?;
+        if !(1..=2).contains(&hash_version) {
+            return Err(Error::InvalidHashVersion(hash_version));
+        }
+        let byte_size = util::consume!(reader, u32, "byte_size")?;
+        limits.check_allocation(byte_size as u64)?;
+        let buffer = util::consume_bytes(reader, byte_size as u64, "strings")?;
+        let bucket_count = util::consume!(reader, u32, "bucket_count")?;
+        limits.check_allocation(bucket_count as u64 * 4)?;
+        let buckets = util::consume_bytes(reader, bucket_count as u64 * 4, "buckets")?
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Ok(StringTable {
+            hash_version,
+            buffer,
+            buckets,
+            name_count: util::consume!(reader, u32, "name_count")?,
+        })
+    }
+    /// The version of the hash used for the buckets, 1 or 2.
+    pub fn hash_version(&self) -> u32 {
+        self.hash_version
+    }
+    /// Number of strings in the table.
+    pub fn len(&self) -> usize {
+        self.name_count as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.name_count == 0
+    }
+    /// The raw bytes of the string at `offset`, without its NUL terminator.
+    pub fn get_bytes(&self, offset: u32) -> Option<&[u8]> {
+        let bytes = self.buffer.get(offset as usize..)?;
+        let len = bytes.iter().position(|b| *b == 0)?;
+        Some(&bytes[..len])
+    }
+    /// The string at `offset`.
+    pub fn get(&self, offset: u32) -> Option<Cow<'_, str>> {
+        self.get_bytes(offset).map(String::from_utf8_lossy)
+    }
+    /// The offset of `name`, if the table has it.
+    pub fn offset_of(&self, name: &str) -> Option<u32> {
+        if self.buckets.is_empty() {
+            return None;
+        }
+        let hash = match self.hash_version {
+            1 => hash_string_v1(name.as_bytes()),
+            _ => hash_string_v2(name.as_bytes()),
+        };
+        let start = hash as usize % self.buckets.len();
+        (0..self.buckets.len())
+            .map(|i| self.buckets[(start + i) % self.buckets.len()])
+            .take_while(|offset| *offset != 0)
+            .find(|offset| self.get_bytes(*offset) == Some(name.as_bytes()))
+    }
+    /// Every (offset, string) pair in the hash table, by offset.
+    pub fn entries(&self) -> Vec<(u32, Cow<'_, str>)> {
+        let mut offsets: Vec<u32> = self.buckets.iter().copied().filter(|o| *o != 0).collect();
+        offsets.sort_unstable();
+        offsets
+            .into_iter()
+            .filter_map(|offset| Some((offset, self.get(offset)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Serialize a `/names` stream holding `strings`, with twice as many buckets as strings.
+    fn names_stream(hash_version: u32, strings: &[&str]) -> Vec<u8> {
+        let mut buffer = vec![0];
+        let mut buckets = vec![0_u32; strings.len() * 2];
+        for string in strings {
+            let hash = match hash_version {
+                1 => hash_string_v1(string.as_bytes()),
+                _ => hash_string_v2(string.as_bytes()),
+            };
+            let mut idx = hash as usize % buckets.len();
+            while buckets[idx] != 0 {
+                idx = (idx + 1) % buckets.len();
+            }
+            buckets[idx] = buffer.len() as u32;
+            buffer.extend(string.as_bytes());
+            buffer.push(0);
+        }
+        let mut stream = Vec::new();
+        for word in [STRING_TABLE_SIGNATURE, hash_version, buffer.len() as u32] {
+            stream.extend(word.to_le_bytes());
+        }
+        stream.extend(&buffer);
+        stream.extend((buckets.len() as u32).to_le_bytes());
+        for bucket in &buckets {
+            stream.extend(bucket.to_le_bytes());
+        }
+        stream.extend((strings.len() as u32).to_le_bytes());
+        stream
+    }
+
+    #[test]
+    fn hash_v2() {
+        // Values from the reference implementation, which adds trailing bytes as unsigned chars.
+        assert_eq!(hash_string_v2(b"d:\\src\\foo.cpp"), 0xd1a8ff4b);
+        assert_eq!(hash_string_v2(b"foo.cpp"), 0xd12b8f9a);
+        assert_eq!(hash_string_v2("caf\u{e9}".as_bytes()), 0x09026ddc);
+    }
+
+    #[test]
+    fn load_and_look_up() {
+        let strings = ["d:\\src\\foo.cpp", "caf\u{e9}.h", "a", "foo.cpp"];
+        for hash_version in [1, 2] {
+            let stream = names_stream(hash_version, &strings);
+            let table = StringTable::load(&mut Cursor::new(&stream)).unwrap();
+            assert_eq!(table.hash_version(), hash_version);
+            assert_eq!(table.len(), strings.len());
+            for string in strings {
+                let offset = table.offset_of(string).unwrap();
+                assert_eq!(table.get(offset).unwrap(), string);
+            }
+            assert_eq!(table.offset_of("missing"), None);
+            let offsets: Vec<u32> = table.entries().iter().map(|(offset, _)| *offset).collect();
+            assert_eq!(offsets, [1, 16, 24, 26]);
+            assert_eq!(table.get(1000), None);
+        }
+    }
+
+    #[test]
+    fn reject_bad_tables() {
+        let stream = names_stream(1, &["a"]);
+        let mut bad = stream.clone();
+        bad[0] = 0;
+        assert!(matches!(
+            StringTable::load(&mut Cursor::new(&bad)),
+            Err(Error::InvalidSignature(0xEFFEEF00))
+        ));
+        let mut bad = stream.clone();
+        bad[4] = 3;
+        assert!(matches!(StringTable::load(&mut Cursor::new(&bad)), Err(Error::InvalidHashVersion(3))));
+        assert!(matches!(
+            StringTable::load(&mut Cursor::new(&stream[..stream.len() - 2])),
+            Err(Error::Field(_))
+        ));
+        let mut huge = stream[..8].to_vec();
+        huge.extend(u32::MAX.to_le_bytes());
+        assert!(matches!(StringTable::load(&mut Cursor::new(&huge)), Err(Error::LimitExceeded(_))));
+    }
+}
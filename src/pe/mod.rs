@@ -0,0 +1,498 @@
+//! Just enough of the PE/COFF format to find the PDB an image was linked against.
+//!
+//! The linker records the PDB's identity in a CodeView entry of the image's debug directory:
+//! an `RSDS` record (GUID, age and PDB path) for PDB 7.00 files, or an `NB10` record (signature,
+//! age and PDB path) for older ones. [PeImage::check_pdb] compares it with a PDB info stream.
+
+use crate::pdb::pdbstream::PDBStreamHeader;
+use crate::util;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Result type alias for this module
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+/// All of the errors that could possible be returned from this module
+pub enum Error {
+    /// Error consuming from the underlying reader.
+    Consume(std::io::Error),
+    /// A field of the image could not be read.
+    Field(util::FieldError),
+    /// The file does not start with the "MZ" signature.
+    NotDosImage,
+    /// The file has no "PE\0\0" signature where the DOS header points.
+    NotPeImage,
+    /// The optional header has a magic other than PE32 or PE32+.
+    UnknownOptionalHeader(u16),
+    /// The optional header is too small for the data directories it claims to have.
+    OptionalHeaderTooSmall,
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Consume(error)
+    }
+}
+
+impl From<util::FieldError> for Error {
+    fn from(error: util::FieldError) -> Self {
+        Error::Field(error)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Consume(e) => e.fmt(f),
+            Error::Field(e) => e.fmt(f),
+            Error::NotDosImage => write!(f, "not an executable image"),
+            Error::NotPeImage => write!(f, "not a PE image"),
+            Error::UnknownOptionalHeader(magic) => write!(f, "unknown optional header magic 0x{:x}", magic),
+            Error::OptionalHeaderTooSmall => {
+                write!(f, "the optional header is too small for its data directories")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Consume(e) => e.source(),
+            Error::Field(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+const DOS_SIGNATURE: &[u8; 2] = b"MZ";
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+/// File offset of `e_lfanew`, the file offset of the PE signature.
+const E_LFANEW_OFFSET: u64 = 0x3C;
+
+const PE32_MAGIC: u16 = 0x10B;
+const PE32_PLUS_MAGIC: u16 = 0x20B;
+/// Index of the debug directory among the optional header's data directories.
+const DEBUG_DIRECTORY_INDEX: u32 = 6;
+/// Size in bytes of a section table entry.
+const SECTION_ENTRY_SIZE: i64 = 40;
+/// Size in bytes of a debug directory entry.
+const DEBUG_ENTRY_SIZE: u32 = 28;
+const IMAGE_DEBUG_TYPE_CODEVIEW: u32 = 2;
+
+const RSDS_SIGNATURE: &[u8; 4] = b"RSDS";
+const NB10_SIGNATURE: &[u8; 4] = b"NB10";
+
+/// The PDB identity recorded in a CodeView debug directory entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodeView {
+    /// A PDB 7.00 reference.
+    Rsds { guid: u128, age: u32, path: String },
+    /// A reference to a PDB older than 7.00, identified by its signature instead of a GUID.
+    Nb10 { signature: u32, age: u32, path: String },
+}
+
+impl CodeView {
+    pub fn age(&self) -> u32 {
+        match self {
+            CodeView::Rsds { age, .. } | CodeView::Nb10 { age, .. } => *age,
+        }
+    }
+    /// The PDB path the image was linked against.
+    pub fn path(&self) -> &str {
+        match self {
+            CodeView::Rsds { path, .. } | CodeView::Nb10 { path, .. } => path,
+        }
+    }
+    /// The file name of the PDB, without the directories of [CodeView::path].
+    pub fn file_name(&self) -> &str {
+        file_name(self.path())
+    }
+    /// The key the image's PDB is stored under on a symbol server.
+    pub fn symbol_server_key(&self) -> String {
+        match self {
+            CodeView::Rsds { guid, age, .. } => crate::symstore::guid_key(*guid, *age),
+            CodeView::Nb10 { signature, age, .. } => crate::symstore::signature_key(*signature, *age),
+        }
+    }
+    /// Check that the PDB with info stream header `header` is the one this record refers to.
+    pub fn check(&self, header: &PDBStreamHeader) -> std::result::Result<(), Mismatch> {
+        match self {
+            CodeView::Rsds { guid, .. } => {
+                if !header.version().has_unique_id() {
+                    return Err(Mismatch::NoGuid);
+                }
+                if *guid != header.unique_id() {
+                    return Err(Mismatch::Guid {
+                        image: *guid,
+                        pdb: header.unique_id(),
+                    });
+                }
+            }
+            CodeView::Nb10 { signature, .. } => {
+                if *signature != header.signature() {
+                    return Err(Mismatch::Signature {
+                        image: *signature,
+                        pdb: header.signature(),
+                    });
+                }
+            }
+        }
+        if self.age() != header.age() {
+            return Err(Mismatch::Age {
+                image: self.age(),
+                pdb: header.age(),
+            });
+        }
+        Ok(())
+    }
+    fn load(reader: &mut (impl Read + Seek), offset: u64, size: u32) -> Result<Option<Self>> {
+        reader.seek(SeekFrom::Start(offset))?;
+        let signature = util::consume!(reader, 4, "CodeView Signature")?;
+        let ret = if &signature == RSDS_SIGNATURE {
+            let guid = util::consume!(reader, u128, "CodeView GUID")?;
+            let age = util::consume!(reader, u32, "CodeView Age")?;
+            let path = read_path(reader, size.saturating_sub(24))?;
+            CodeView::Rsds { guid, age, path }
+        } else if &signature == NB10_SIGNATURE {
+            let _offset = util::consume!(reader, u32, "CodeView Offset")?;
+            let signature = util::consume!(reader, u32, "CodeView PDB Signature")?;
+            let age = util::consume!(reader, u32, "CodeView Age")?;
+            let path = read_path(reader, size.saturating_sub(16))?;
+            CodeView::Nb10 { signature, age, path }
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(ret))
+    }
+}
+
+/// Read the NUL-terminated PDB path filling the rest of a CodeView record of `len` bytes.
+fn read_path(reader: &mut impl Read, len: u32) -> Result<String> {
+    let bytes = util::consume_bytes(reader, len as u64, "CodeView PDB Path")?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+/// Why an image and a PDB do not belong together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The image has no debug directory, so it names no PDB at all.
+    NoDebugDirectory,
+    /// The debug directory has no CodeView entry.
+    NoCodeView,
+    /// The image refers to a PDB 7.00 file, but the PDB has no GUID.
+    NoGuid,
+    Guid { image: u128, pdb: u128 },
+    Signature { image: u32, pdb: u32 },
+    Age { image: u32, pdb: u32 },
+    /// The PDB's file name differs from the one the image was linked against.
+    Name { image: String, pdb: String },
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Mismatch::NoDebugDirectory => write!(f, "the image has no debug directory"),
+            Mismatch::NoCodeView => write!(f, "the image has no CodeView debug information"),
+            Mismatch::NoGuid => write!(f, "the image expects a PDB 7.00 file, but the PDB has no GUID"),
+            Mismatch::Guid { image, pdb } => write!(
+                f,
+                "GUID differs: image has {}, PDB has {}",
+                crate::symstore::guid_string(*image),
+                crate::symstore::guid_string(*pdb)
+            ),
+            Mismatch::Signature { image, pdb } => {
+                write!(f, "signature differs: image has 0x{:08x}, PDB has 0x{:08x}", image, pdb)
+            }
+            Mismatch::Age { image, pdb } => write!(f, "age differs: image has {}, PDB has {}", image, pdb),
+            Mismatch::Name { image, pdb } => write!(f, "name differs: image has {}, PDB is {}", image, pdb),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// The debug information of a PE/COFF image.
+#[derive(Debug, Default)]
+pub struct PeImage {
+    has_debug_directory: bool,
+    codeview: Option<CodeView>,
+}
+
+impl PeImage {
+    /// Read the headers and debug directory of a `.dll` or `.exe`.
+    pub fn load(reader: &mut (impl Read + Seek)) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        if &util::consume!(reader, 2, "DOS Signature")? != DOS_SIGNATURE {
+            return Err(Error::NotDosImage);
+        }
+        reader.seek(SeekFrom::Start(E_LFANEW_OFFSET))?;
+        let pe_offset = util::consume!(reader, u32, "e_lfanew")?;
+        reader.seek(SeekFrom::Start(pe_offset as u64))?;
+        if &util::consume!(reader, 4, "PE Signature")? != PE_SIGNATURE {
+            return Err(Error::NotPeImage);
+        }
+        let _machine = util::consume!(reader, u16, "Machine")?;
+        let num_sections = util::consume!(reader, u16, "Number of Sections")?;
+        let _time_date_stamp = util::consume!(reader, u32, "Time Date Stamp")?;
+        let _symbol_table = util::consume!(reader, u32, "Pointer to Symbol Table")?;
+        let _num_symbols = util::consume!(reader, u32, "Number of Symbols")?;
+        let optional_header_size = util::consume!(reader, u16, "Size of Optional Header")?;
+        let _characteristics = util::consume!(reader, u16, "Characteristics")?;
+
+        // The data directories follow the fixed part of the optional header, whose size depends
+        // on whether addresses are 32 or 64 bits wide.
+        let optional_header = reader.stream_position()?;
+        let directories_offset = match util::consume!(reader, u16, "Optional Header Magic")? {
+            PE32_MAGIC => 96,
+            PE32_PLUS_MAGIC => 112,
+            magic => return Err(Error::UnknownOptionalHeader(magic)),
+        };
+        reader.seek(SeekFrom::Start(optional_header + directories_offset - 4))?;
+        let num_directories = util::consume!(reader, u32, "Number of RVA and Sizes")?;
+        let mut ret = PeImage::default();
+        if num_directories <= DEBUG_DIRECTORY_INDEX {
+            return Ok(ret);
+        }
+        if directories_offset + 8 * (DEBUG_DIRECTORY_INDEX as u64 + 1) > optional_header_size as u64 {
+            return Err(Error::OptionalHeaderTooSmall);
+        }
+        reader.seek(SeekFrom::Current(8 * DEBUG_DIRECTORY_INDEX as i64))?;
+        let debug_rva = util::consume!(reader, u32, "Debug Directory RVA")?;
+        let debug_size = util::consume!(reader, u32, "Debug Directory Size")?;
+        if debug_rva == 0 || debug_size == 0 {
+            return Ok(ret);
+        }
+
+        reader.seek(SeekFrom::Start(optional_header + optional_header_size as u64))?;
+        let mut debug_offset = None;
+        for _ in 0..num_sections {
+            let _name = util::consume!(reader, 8, "Section Name")?;
+            let virtual_size = util::consume!(reader, u32, "Section Virtual Size")?;
+            let virtual_address = util::consume!(reader, u32, "Section Virtual Address")?;
+            let raw_size = util::consume!(reader, u32, "Section Size of Raw Data")?;
+            let raw_offset = util::consume!(reader, u32, "Section Pointer to Raw Data")?;
+            reader.seek(SeekFrom::Current(SECTION_ENTRY_SIZE - 24))?;
+            let offset = debug_rva.wrapping_sub(virtual_address);
+            if debug_rva >= virtual_address && offset < virtual_size.max(raw_size) {
+                debug_offset = Some(raw_offset as u64 + offset as u64);
+                break;
+            }
+        }
+        // A debug directory outside of every section is as good as none.
+        let Some(debug_offset) = debug_offset else {
+            return Ok(ret);
+        };
+        ret.has_debug_directory = true;
+
+        let mut codeview_entries = Vec::new();
+        reader.seek(SeekFrom::Start(debug_offset))?;
+        for _ in 0..debug_size / DEBUG_ENTRY_SIZE {
+            let _characteristics = util::consume!(reader, u32, "Debug Characteristics")?;
+            let _time_date_stamp = util::consume!(reader, u32, "Debug Time Date Stamp")?;
+            let _version = util::consume!(reader, u32, "Debug Version")?;
+            let debug_type = util::consume!(reader, u32, "Debug Type")?;
+            let size = util::consume!(reader, u32, "Debug Size of Data")?;
+            let _address = util::consume!(reader, u32, "Debug Address of Raw Data")?;
+            let pointer = util::consume!(reader, u32, "Debug Pointer to Raw Data")?;
+            if debug_type == IMAGE_DEBUG_TYPE_CODEVIEW {
+                codeview_entries.push((pointer, size));
+            }
+        }
+        for (pointer, size) in codeview_entries {
+            if let Some(codeview) = CodeView::load(reader, pointer as u64, size)? {
+                ret.codeview = Some(codeview);
+                break;
+            }
+        }
+        Ok(ret)
+    }
+    /// Whether the image has a debug directory at all.
+    pub fn has_debug_directory(&self) -> bool {
+        self.has_debug_directory
+    }
+    /// The PDB the image was linked against, if it records one.
+    pub fn codeview(&self) -> Option<&CodeView> {
+        self.codeview.as_ref()
+    }
+    /// Check that the PDB with info stream header `header` belongs to this image. The PDB's
+    /// file name is compared too, case-insensitively, when `pdb_name` is given.
+    pub fn check_pdb(&self, header: &PDBStreamHeader, pdb_name: Option<&str>) -> std::result::Result<(), Mismatch> {
+        let codeview = match &self.codeview {
+            Some(codeview) => codeview,
+            None if !self.has_debug_directory => return Err(Mismatch::NoDebugDirectory),
+            None => return Err(Mismatch::NoCodeView),
+        };
+        codeview.check(header)?;
+        if let Some(pdb_name) = pdb_name {
+            let image_name = codeview.file_name();
+            if !image_name.eq_ignore_ascii_case(file_name(pdb_name)) {
+                return Err(Mismatch::Name {
+                    image: image_name.to_string(),
+                    pdb: file_name(pdb_name).to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The last component of a Windows or Unix path.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const GUID: u128 = 0x2c4bea12_424a1da5_2daa3740_96ef4ed5;
+    const SECTION_OFFSET: usize = 0x200;
+
+    /// A PE32+ image with one section, whose debug directory holds a single CodeView entry with
+    /// the contents `codeview`, or no debug directory if `codeview` is None.
+    fn image(codeview: Option<&[u8]>) -> Vec<u8> {
+        let mut file = vec![0; SECTION_OFFSET];
+        file[..2].copy_from_slice(DOS_SIGNATURE);
+        file[0x3C..0x40].copy_from_slice(&0x40_u32.to_le_bytes());
+        let mut pe = PE_SIGNATURE.to_vec();
+        pe.extend(0x8664_u16.to_le_bytes());
+        pe.extend(1_u16.to_le_bytes());
+        pe.extend([0; 12]);
+        pe.extend((112_u16 + 16 * 8).to_le_bytes());
+        pe.extend(0x22_u16.to_le_bytes());
+        pe.extend(PE32_PLUS_MAGIC.to_le_bytes());
+        pe.extend([0; 106]);
+        pe.extend(16_u32.to_le_bytes());
+        for directory in 0..16 {
+            let (rva, size) = match codeview {
+                Some(_) if directory == DEBUG_DIRECTORY_INDEX => (0x1000_u32, DEBUG_ENTRY_SIZE),
+                _ => (0, 0),
+            };
+            pe.extend(rva.to_le_bytes());
+            pe.extend(size.to_le_bytes());
+        }
+        pe.extend(b".rdata\0\0");
+        for field in [0x200_u32, 0x1000, 0x200, SECTION_OFFSET as u32] {
+            pe.extend(field.to_le_bytes());
+        }
+        pe.extend([0; 16]);
+        file[0x40..0x40 + pe.len()].copy_from_slice(&pe);
+
+        let codeview = codeview.unwrap_or_default();
+        let pointer = SECTION_OFFSET as u32 + DEBUG_ENTRY_SIZE;
+        for field in [0, 0, 0, IMAGE_DEBUG_TYPE_CODEVIEW, codeview.len() as u32, 0x101C] {
+            file.extend(field.to_le_bytes());
+        }
+        file.extend(pointer.to_le_bytes());
+        file.extend(codeview);
+        file.resize(SECTION_OFFSET + 0x200, 0);
+        file
+    }
+
+    fn rsds(guid: u128, age: u32, path: &str) -> Vec<u8> {
+        let mut record = RSDS_SIGNATURE.to_vec();
+        record.extend(guid.to_le_bytes());
+        record.extend(age.to_le_bytes());
+        record.extend(path.as_bytes());
+        record.push(0);
+        record
+    }
+
+    fn header(guid: u128, age: u32) -> PDBStreamHeader {
+        let mut stream = 20000404_u32.to_le_bytes().to_vec();
+        stream.extend(0xa280d1d6_u32.to_le_bytes());
+        stream.extend(age.to_le_bytes());
+        stream.extend(guid.to_le_bytes());
+        PDBStreamHeader::load(&mut Cursor::new(stream)).unwrap()
+    }
+
+    #[test]
+    fn rsds_record() {
+        let file = image(Some(&rsds(GUID, 3, "d:\\build\\ntdll.pdb")));
+        let pe = PeImage::load(&mut Cursor::new(&file)).unwrap();
+        assert!(pe.has_debug_directory());
+        let codeview = pe.codeview().unwrap();
+        assert_eq!(
+            *codeview,
+            CodeView::Rsds {
+                guid: GUID,
+                age: 3,
+                path: "d:\\build\\ntdll.pdb".to_string()
+            }
+        );
+        assert_eq!(codeview.file_name(), "ntdll.pdb");
+        assert_eq!(codeview.symbol_server_key(), "96EF4ED537402DAAA51D4A4212EA4B2C3");
+
+        assert_eq!(pe.check_pdb(&header(GUID, 3), None), Ok(()));
+        assert_eq!(pe.check_pdb(&header(GUID, 3), Some("/tmp/NTDLL.PDB")), Ok(()));
+        assert_eq!(
+            pe.check_pdb(&header(GUID, 3), Some("/tmp/ntdll.pd_")),
+            Err(Mismatch::Name {
+                image: "ntdll.pdb".to_string(),
+                pdb: "ntdll.pd_".to_string()
+            })
+        );
+        assert_eq!(
+            pe.check_pdb(&header(GUID, 4), None),
+            Err(Mismatch::Age { image: 3, pdb: 4 })
+        );
+        let mismatch = pe.check_pdb(&header(GUID + 1, 3), None).unwrap_err();
+        assert_eq!(
+            mismatch.to_string(),
+            "GUID differs: image has {96EF4ED5-3740-2DAA-A51D-4A4212EA4B2C}, \
+             PDB has {96EF4ED6-3740-2DAA-A51D-4A4212EA4B2C}"
+        );
+    }
+
+    #[test]
+    fn nb10_record() {
+        let mut record = NB10_SIGNATURE.to_vec();
+        for field in [0, 0xa280d1d6_u32, 3] {
+            record.extend(field.to_le_bytes());
+        }
+        record.extend(b"old.pdb\0");
+        let pe = PeImage::load(&mut Cursor::new(image(Some(&record)))).unwrap();
+        let codeview = pe.codeview().unwrap();
+        assert_eq!(codeview.path(), "old.pdb");
+        assert_eq!(codeview.symbol_server_key(), "A280D1D63");
+        assert_eq!(codeview.check(&header(0, 3)), Ok(()));
+        // A PDB 7.00 header is matched on its signature, which an RSDS record does not have.
+        let rsds_pe = PeImage::load(&mut Cursor::new(image(Some(&rsds(0, 3, "a.pdb"))))).unwrap();
+        let mut old = 19990604_u32.to_le_bytes().to_vec();
+        old.extend([0; 8]);
+        let old = PDBStreamHeader::load(&mut Cursor::new(old)).unwrap();
+        assert_eq!(rsds_pe.check_pdb(&old, None), Err(Mismatch::NoGuid));
+    }
+
+    #[test]
+    fn missing_debug_info() {
+        let pe = PeImage::load(&mut Cursor::new(image(None))).unwrap();
+        assert!(!pe.has_debug_directory());
+        assert_eq!(pe.check_pdb(&header(GUID, 3), None), Err(Mismatch::NoDebugDirectory));
+        let pe = PeImage::load(&mut Cursor::new(image(Some(b"XXXX0000")))).unwrap();
+        assert!(pe.has_debug_directory());
+        assert_eq!(pe.check_pdb(&header(GUID, 3), None), Err(Mismatch::NoCodeView));
+    }
+
+    #[test]
+    fn reject_bad_images() {
+        assert!(matches!(PeImage::load(&mut Cursor::new(b"ZM")), Err(Error::NotDosImage)));
+        let mut file = image(None);
+        file[0x40] = b'X';
+        assert!(matches!(PeImage::load(&mut Cursor::new(&file)), Err(Error::NotPeImage)));
+        let mut file = image(None);
+        file[0x58] = 0;
+        assert!(matches!(
+            PeImage::load(&mut Cursor::new(&file)),
+            Err(Error::UnknownOptionalHeader(0x200))
+        ));
+        let truncated = image(Some(&rsds(GUID, 3, "a.pdb")));
+        assert!(matches!(
+            PeImage::load(&mut Cursor::new(&truncated[..0x100])),
+            Err(Error::Field(_))
+        ));
+    }
+}
@@ -0,0 +1,916 @@
+//! Finding PDBs along an `_NT_SYMBOL_PATH`-style search path.
+//!
+//! A symbol path is a `;`-separated list of elements, searched in order:
+//!
+//! * `srv*<cache>*...*<upstream>` looks the file up in each symbol store from left to right. The
+//!   last one is the upstream, either an `http://` URL or a directory; the others are caches
+//!   that receive a copy of whatever is found further right. `https://` servers are not
+//!   supported and make the path invalid. An empty cache (`srv**<upstream>`)
+//!   means the resolver's default cache. `symsrv*<dll>*...` is read the same way, ignoring the
+//!   DLL name.
+//! * `cache*<dir>` caches whatever the elements after it find in `<dir>`, or in the default cache
+//!   when `<dir>` is left out.
+//! * Anything else is a plain directory holding `<name>` or `<ext>/<name>`.
+//!
+//! A `file.ptr` found in a local store is followed. One served by a symbol server is ignored
+//! unless [SymbolResolver::with_server_pointers] allows it: it may name any local or UNC path,
+//! and opening a UNC path hands the user's credentials to whatever host it names.
+//!
+//! Every candidate is opened and its PDB info stream checked against the GUID (or signature)
+//! and age being looked for before it is accepted or cached.
+
+use crate::cab;
+use crate::msf::MSF;
+use crate::pdb::PDB;
+use crate::pe::CodeView;
+use crate::symstore::{Error, Result, SymStore};
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Fetches files from upstream symbol servers.
+pub trait Transport {
+    /// Get the contents at `url`, or None if the server does not have it.
+    fn fetch(&self, url: &str) -> std::io::Result<Option<Vec<u8>>>;
+}
+
+/// A plain HTTP/1.1 client, enough for symbol servers that serve `http://` URLs.
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    /// Read and write timeout of the connection.
+    pub timeout: Option<Duration>,
+    /// Responses larger than this many bytes are refused.
+    pub max_size: u64,
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        HttpTransport {
+            timeout: Some(Duration::from_secs(60)),
+            max_size: 1 << 32,
+        }
+    }
+}
+
+/// Redirects followed before giving up.
+const MAX_REDIRECTS: usize = 5;
+
+impl Transport for HttpTransport {
+    fn fetch(&self, url: &str) -> std::io::Result<Option<Vec<u8>>> {
+        let mut url = url.to_string();
+        for _ in 0..=MAX_REDIRECTS {
+            let (host, port, path) = split_url(&url)?;
+            let mut stream = TcpStream::connect((host, port))?;
+            stream.set_read_timeout(self.timeout)?;
+            stream.set_write_timeout(self.timeout)?;
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: {}:{}\r\nUser-Agent: pdbparser\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+                path, host, port
+            )?;
+            let mut reader = BufReader::new(stream);
+            let status_line = read_line(&mut reader)?;
+            let status = status_line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| invalid_data(format!("malformed status line {:?}", status_line)))?;
+            let mut content_length = None;
+            let mut chunked = false;
+            let mut location = None;
+            loop {
+                let line = read_line(&mut reader)?;
+                if line.is_empty() {
+                    break;
+                }
+                let Some((name, value)) = line.split_once(':') else {
+                    continue;
+                };
+                let value = value.trim();
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "content-length" => {
+                        content_length = Some(
+                            value
+                                .parse::<u64>()
+                                .map_err(|_| invalid_data(format!("bad Content-Length {:?}", value)))?,
+                        )
+                    }
+                    "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                    "location" => location = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            match status {
+                200 => {}
+                404 | 410 => return Ok(None),
+                301 | 302 | 303 | 307 | 308 => {
+                    let location = location.ok_or_else(|| invalid_data("redirect without a Location".into()))?;
+                    url = resolve_location(&url, &location);
+                    continue;
+                }
+                status => {
+                    return Err(std::io::Error::other(format!("HTTP status {}", status)));
+                }
+            }
+            let body = if chunked {
+                self.read_chunked(&mut reader)?
+            } else {
+                let len = content_length.unwrap_or(self.max_size.saturating_add(1));
+                self.read_body(&mut reader, len, content_length.is_some())?
+            };
+            return Ok(Some(body));
+        }
+        Err(std::io::Error::other("too many redirects"))
+    }
+}
+
+impl HttpTransport {
+    /// Read `len` bytes of body, or everything until the connection closes when the length is
+    /// unknown.
+    fn read_body(&self, reader: &mut impl Read, len: u64, exact: bool) -> std::io::Result<Vec<u8>> {
+        if len > self.max_size && exact {
+            return Err(std::io::Error::other(format!("response of {} bytes is too large", len)));
+        }
+        let mut ret = Vec::new();
+        reader.take(len.min(self.max_size.saturating_add(1))).read_to_end(&mut ret)?;
+        if ret.len() as u64 > self.max_size {
+            return Err(std::io::Error::other("response is too large"));
+        }
+        if exact && (ret.len() as u64) < len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(ret)
+    }
+    fn read_chunked(&self, reader: &mut impl BufRead) -> std::io::Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        loop {
+            let line = read_line(reader)?;
+            let size = line.split(';').next().unwrap_or_default().trim();
+            let size = u64::from_str_radix(size, 16)
+                .map_err(|_| invalid_data(format!("bad chunk size {:?}", line)))?;
+            if size == 0 {
+                // Skip any trailers.
+                while !read_line(reader)?.is_empty() {}
+                return Ok(ret);
+            }
+            if ret.len() as u64 + size > self.max_size {
+                return Err(std::io::Error::other("response is too large"));
+            }
+            ret.extend(self.read_body(reader, size, true)?);
+            read_line(reader)?;
+        }
+    }
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Read a CRLF-terminated line, without the line ending.
+fn read_line(reader: &mut impl BufRead) -> std::io::Result<String> {
+    let mut line = Vec::new();
+    reader.take(64 * 1024).read_until(b'\n', &mut line)?;
+    if line.last() != Some(&b'\n') {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// The part of an `http://` URL after the scheme, which is matched case-insensitively.
+fn strip_http(url: &str) -> Option<&str> {
+    url.get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+        .map(|_| &url[7..])
+}
+
+/// Split an `http://host[:port]/path` URL.
+fn split_url(url: &str) -> std::io::Result<(&str, u16, &str)> {
+    let rest = strip_http(url).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("only http:// URLs are supported: {}", url),
+        )
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| invalid_data(format!("bad port in {}", url)))?,
+        ),
+        None => (authority, 80),
+    };
+    Ok((host, port, path))
+}
+
+/// Escape everything but unreserved characters in a URL path segment.
+fn percent_encode(segment: &str) -> String {
+    let mut ret = String::new();
+    for b in segment.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            ret.push(b as char);
+        } else {
+            ret.push_str(&format!("%{:02X}", b));
+        }
+    }
+    ret
+}
+
+/// Resolve the Location of a redirect from `url`.
+fn resolve_location(url: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let rest = strip_http(url).unwrap_or(url);
+    let origin_len = "http://".len() + rest.find('/').unwrap_or(rest.len());
+    if location.starts_with('/') {
+        return format!("{}{}", &url[..origin_len], location);
+    }
+    let dir_len = url.rfind('/').filter(|i| *i >= origin_len).map_or(url.len(), |i| i + 1);
+    let separator = if dir_len == url.len() { "/" } else { "" };
+    format!("{}{}{}", &url[..dir_len], separator, location)
+}
+
+/// Where a symbol path element looks for files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// A symbol store directory, or the default cache when None.
+    Store(Option<PathBuf>),
+    /// A symbol server URL.
+    Server(String),
+}
+
+/// One `;`-separated element of a symbol path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathElement {
+    /// `srv*...`: the stores and servers to try from left to right, caching into the ones
+    /// before the tier that has the file.
+    Server(Vec<Location>),
+    /// `cache*<dir>`: cache what the following elements find, or into the default cache when None.
+    Cache(Option<PathBuf>),
+    /// A plain directory.
+    Directory(PathBuf),
+}
+
+/// A parsed `_NT_SYMBOL_PATH`-style search path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolPath {
+    pub elements: Vec<PathElement>,
+}
+
+impl SymbolPath {
+    pub fn parse(path: &str) -> Result<Self> {
+        let mut elements = Vec::new();
+        for element in path.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let lower = element.to_ascii_lowercase();
+            let tiers = if lower.starts_with("srv*") {
+                Some(element.split('*').skip(1).collect::<Vec<_>>())
+            } else if lower.starts_with("symsrv*") {
+                Some(element.split('*').skip(2).collect())
+            } else {
+                None
+            };
+            if let Some(tiers) = tiers {
+                if tiers.iter().all(|tier| tier.is_empty()) {
+                    return Err(Error::InvalidSymbolPath(element.to_string()));
+                }
+                let tiers = tiers
+                    .into_iter()
+                    .map(|tier| location(tier).ok_or_else(|| Error::InvalidSymbolPath(element.to_string())))
+                    .collect::<Result<_>>()?;
+                elements.push(PathElement::Server(tiers));
+            } else if lower.starts_with("cache*") {
+                let dir = &element["cache*".len()..];
+                elements.push(PathElement::Cache((!dir.is_empty()).then(|| PathBuf::from(dir))));
+            } else {
+                elements.push(PathElement::Directory(PathBuf::from(element)));
+            }
+        }
+        Ok(SymbolPath { elements })
+    }
+    /// The search path in the `_NT_SYMBOL_PATH` environment variable, if set.
+    pub fn from_env() -> Result<Option<Self>> {
+        std::env::var("_NT_SYMBOL_PATH")
+            .ok()
+            .map(|path| Self::parse(&path))
+            .transpose()
+    }
+}
+
+/// Where a `srv*` tier looks, or None for an `https://` server, which [HttpTransport] cannot
+/// reach.
+fn location(tier: &str) -> Option<Location> {
+    let lower = tier.to_ascii_lowercase();
+    if lower.starts_with("https://") {
+        None
+    } else if lower.starts_with("http://") {
+        Some(Location::Server(tier.trim_end_matches('/').to_string()))
+    } else if tier.is_empty() {
+        Some(Location::Store(None))
+    } else {
+        Some(Location::Store(Some(PathBuf::from(tier))))
+    }
+}
+
+/// Finds PDBs along a [SymbolPath], downloading and caching them as needed.
+pub struct SymbolResolver {
+    path: SymbolPath,
+    transport: Box<dyn Transport>,
+    default_cache: PathBuf,
+    server_pointers: bool,
+}
+
+impl SymbolResolver {
+    /// Search `path`, downloading with an [HttpTransport] and using `<temp dir>/symbols` as the
+    /// default cache.
+    pub fn new(path: SymbolPath) -> Self {
+        SymbolResolver {
+            path,
+            transport: Box::new(HttpTransport::default()),
+            default_cache: std::env::temp_dir().join("symbols"),
+            server_pointers: false,
+        }
+    }
+    /// Download with `transport` instead.
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Box::new(transport);
+        self
+    }
+    /// Use `dir` for caches the symbol path leaves unnamed, and for downloads that nothing
+    /// else caches.
+    pub fn with_default_cache(mut self, dir: impl AsRef<Path>) -> Self {
+        self.default_cache = dir.as_ref().to_path_buf();
+        self
+    }
+    /// Follow the paths in `file.ptr` files served by symbol servers, which are ignored by
+    /// default. Only enable this for servers trusted to name local or UNC paths.
+    pub fn with_server_pointers(mut self, follow: bool) -> Self {
+        self.server_pointers = follow;
+        self
+    }
+    /// Find the PDB of the GUID (or signature) and age in `codeview`. The PDB is looked up
+    /// under the file name of [CodeView::path].
+    ///
+    /// Transport errors do not stop the search; the last one is returned only if the PDB is
+    /// not found anywhere.
+    pub fn find(&self, codeview: &CodeView) -> Result<Option<PathBuf>> {
+        let name = codeview.file_name();
+        let key = codeview.symbol_server_key();
+        let mut caches: Vec<PathBuf> = Vec::new();
+        let mut last_error = None;
+        for element in &self.path.elements {
+            let found = match element {
+                PathElement::Cache(dir) => {
+                    caches.push(self.store_dir(dir));
+                    continue;
+                }
+                PathElement::Directory(dir) => {
+                    let ext = Path::new(name)
+                        .extension()
+                        .map(|ext| ext.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                    [dir.join(name), dir.join(ext).join(name)]
+                        .into_iter()
+                        .find(|path| path.is_file() && verify(path, codeview))
+                }
+                PathElement::Server(tiers) => {
+                    match self.find_in_tiers(tiers, name, &key, codeview, &caches) {
+                        Ok(found) => {
+                            if found.is_some() {
+                                return Ok(found);
+                            }
+                            None
+                        }
+                        Err(e) => {
+                            last_error = Some(e);
+                            None
+                        }
+                    }
+                }
+            };
+            if let Some(found) = found {
+                return self.cache(&caches, name, &key, found).map(Some);
+            }
+        }
+        match last_error {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+    /// Find the PDB named `name` with GUID `guid` and age `age`.
+    pub fn find_by_guid(&self, name: &str, guid: u128, age: u32) -> Result<Option<PathBuf>> {
+        self.find(&CodeView::Rsds {
+            guid,
+            age,
+            path: name.to_string(),
+        })
+    }
+
+    fn store_dir(&self, dir: &Option<PathBuf>) -> PathBuf {
+        dir.clone().unwrap_or_else(|| self.default_cache.clone())
+    }
+    /// Search the tiers of a `srv*` element. What a tier finds is copied into the tiers before
+    /// it and into the caches of earlier `cache*` elements.
+    fn find_in_tiers(
+        &self,
+        tiers: &[Location],
+        name: &str,
+        key: &str,
+        codeview: &CodeView,
+        caches: &[PathBuf],
+    ) -> Result<Option<PathBuf>> {
+        let mut before = caches.to_vec();
+        for tier in tiers {
+            match tier {
+                Location::Store(dir) => {
+                    let dir = self.store_dir(dir);
+                    if let Some(found) = lookup_store(&dir, name, key)? {
+                        if verify(&found, codeview) {
+                            return self.cache(&before, name, key, found).map(Some);
+                        }
+                    }
+                    before.push(dir);
+                }
+                Location::Server(url) => {
+                    // Downloads have to land somewhere: the nearest cache, else the default one.
+                    let first = before.first().unwrap_or(&self.default_cache);
+                    if let Some(found) = self.download(url, name, key, codeview, first)? {
+                        return self.cache(&before, name, key, found).map(Some);
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+    /// Fetch `name` from the symbol server at `url` into the store at `cache`, trying the file
+    /// itself, its CAB-compressed `.pd_` form and, if allowed, a `file.ptr`.
+    fn download(
+        &self,
+        url: &str,
+        name: &str,
+        key: &str,
+        codeview: &CodeView,
+        cache: &Path,
+    ) -> Result<Option<PathBuf>> {
+        super::check_name(name)?;
+        let base = format!("{}/{}/{}", url, percent_encode(name), percent_encode(key));
+        let fetch = |url: &str| {
+            self.transport
+                .fetch(url)
+                .map_err(|e| Error::Download(url.to_string(), e))
+        };
+        if let Some(data) = fetch(&format!("{}/{}", base, percent_encode(name)))? {
+            return store_verified(cache, name, key, &data, codeview);
+        }
+        let compressed_url = format!("{}/{}", base, percent_encode(&super::compressed_name(name)));
+        if let Some(data) = fetch(&compressed_url)? {
+            let file = cab::extract(&mut std::io::Cursor::new(data))
+                .map_err(|e| Error::Expand(compressed_url, e))?
+                .into_iter()
+                .next();
+            return match file {
+                Some(file) => store_verified(cache, name, key, &file.data, codeview),
+                None => Ok(None),
+            };
+        }
+        if !self.server_pointers {
+            return Ok(None);
+        }
+        if let Some(data) = fetch(&format!("{}/{}", base, super::POINTER_FILE))? {
+            let target = super::read_pointer(&String::from_utf8_lossy(&data))?;
+            return Ok(Some(target).filter(|path| path.is_file() && verify(path, codeview)));
+        }
+        Ok(None)
+    }
+    /// Copy `found` into every store in `caches`, returning the copy in the first one, or
+    /// `found` itself if there are none. A compressed `found` stays compressed.
+    fn cache(&self, caches: &[PathBuf], name: &str, key: &str, found: PathBuf) -> Result<PathBuf> {
+        let mut ret = None;
+        for cache in caches {
+            let store = open_store(cache)?;
+            let target = store.key_dir(name, key)?.join(super::stored_name(name, &found));
+            if target != found && !target.is_file() {
+                let dir = store.key_dir(name, key)?;
+                fs::create_dir_all(&dir).map_err(|e| Error::Io(dir.clone(), e))?;
+                fs::copy(&found, &target).map_err(|e| Error::Io(target.clone(), e))?;
+            }
+            ret.get_or_insert(target);
+        }
+        Ok(ret.unwrap_or(found))
+    }
+}
+
+/// Open the store at `dir`, creating the directory of a new cache.
+fn open_store(dir: &Path) -> Result<SymStore> {
+    fs::create_dir_all(dir).map_err(|e| Error::Io(dir.to_path_buf(), e))?;
+    SymStore::open(dir)
+}
+
+fn lookup_store(dir: &Path, name: &str, key: &str) -> Result<Option<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    match SymStore::open(dir)?.lookup(name, key) {
+        // A store that says the file is gone just doesn't have it.
+        Err(Error::PointerMessage(_)) => Ok(None),
+        found => found,
+    }
+}
+
+/// Write downloaded `data` into the store at `cache` if it is the PDB `codeview` refers to.
+fn store_verified(cache: &Path, name: &str, key: &str, data: &[u8], codeview: &CodeView) -> Result<Option<PathBuf>> {
+    let dir = open_store(cache)?.key_dir(name, key)?;
+    fs::create_dir_all(&dir).map_err(|e| Error::Io(dir.clone(), e))?;
+    let partial = dir.join(format!("{}.partial", name));
+    fs::write(&partial, data).map_err(|e| Error::Io(partial.clone(), e))?;
+    if !verify(&partial, codeview) {
+        let _ = fs::remove_file(&partial);
+        return Ok(None);
+    }
+    let target = dir.join(name);
+    fs::rename(&partial, &target).map_err(|e| Error::Io(target.clone(), e))?;
+    Ok(Some(target))
+}
+
+/// Whether the file at `path` is the PDB `codeview` refers to.
+fn verify(path: &Path, codeview: &CodeView) -> bool {
+    let Ok((mut reader, msf)) = MSF::open(path) else {
+        return false;
+    };
+    PDB::pdb_stream(&mut reader, &msf).is_ok_and(|pdb| codeview.check(pdb.info().header()).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symstore::{Entry, Source};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::rc::Rc;
+
+    /// Serves fixed contents by URL and records every URL asked for.
+    #[derive(Default, Clone)]
+    struct MockTransport {
+        files: HashMap<String, Vec<u8>>,
+        requests: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Transport for MockTransport {
+        fn fetch(&self, url: &str) -> std::io::Result<Option<Vec<u8>>> {
+            self.requests.borrow_mut().push(url.to_string());
+            Ok(self.files.get(url).cloned())
+        }
+    }
+
+    /// An empty directory for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pdbparser-client-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `pdb/ntdll.pdb` and a CodeView record referring to it.
+    fn ntdll() -> (Vec<u8>, CodeView) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("pdb/ntdll.pdb");
+        let (mut reader, msf) = MSF::open(&path).unwrap();
+        let pdb = PDB::pdb_stream(&mut reader, &msf).unwrap();
+        let header = pdb.info().header();
+        let codeview = CodeView::Rsds {
+            guid: header.unique_id(),
+            age: header.age(),
+            path: "d:\\build\\ntdll.pdb".to_string(),
+        };
+        (fs::read(&path).unwrap(), codeview)
+    }
+
+    /// An uncompressed cabinet holding `data` as the file `name`.
+    fn cabinet(name: &str, data: &[u8]) -> Vec<u8> {
+        let blocks: Vec<&[u8]> = data.chunks(0x8000).collect();
+        let files_offset = 36 + 8;
+        let data_offset = files_offset + 16 + name.len() + 1;
+        let size = data_offset + blocks.len() * 8 + data.len();
+        let mut ret = b"MSCF".to_vec();
+        for field in [0, size as u32, 0, files_offset as u32, 0] {
+            ret.extend(field.to_le_bytes());
+        }
+        // Version 1.3, one folder, one file, no flags.
+        for field in [0x0103_u16, 1, 1, 0, 0, 0] {
+            ret.extend(field.to_le_bytes());
+        }
+        ret.extend((data_offset as u32).to_le_bytes());
+        ret.extend((blocks.len() as u16).to_le_bytes());
+        ret.extend(0_u16.to_le_bytes());
+        ret.extend((data.len() as u32).to_le_bytes());
+        ret.extend([0; 12]);
+        ret.extend(name.as_bytes());
+        ret.push(0);
+        for block in blocks {
+            // A zero checksum is not checked.
+            ret.extend(0_u32.to_le_bytes());
+            ret.extend((block.len() as u16).to_le_bytes());
+            ret.extend((block.len() as u16).to_le_bytes());
+            ret.extend(block);
+        }
+        ret
+    }
+
+    fn resolver(path: &str, transport: &MockTransport, default_cache: &Path) -> SymbolResolver {
+        SymbolResolver::new(SymbolPath::parse(path).unwrap())
+            .with_transport(transport.clone())
+            .with_default_cache(default_cache)
+    }
+
+    #[test]
+    fn parse_symbol_path() {
+        let path = SymbolPath::parse(" d:\\pdbs ; srv*c:\\symbols*HTTP://symbols.example.com/ ;cache*;symsrv*symsrv.dll**http://a;cache*e:\\cache").unwrap();
+        assert_eq!(
+            path.elements,
+            [
+                PathElement::Directory(PathBuf::from("d:\\pdbs")),
+                PathElement::Server(vec![
+                    Location::Store(Some(PathBuf::from("c:\\symbols"))),
+                    Location::Server("HTTP://symbols.example.com".to_string()),
+                ]),
+                PathElement::Cache(None),
+                PathElement::Server(vec![Location::Store(None), Location::Server("http://a".to_string())]),
+                PathElement::Cache(Some(PathBuf::from("e:\\cache"))),
+            ]
+        );
+        assert!(matches!(SymbolPath::parse("srv**"), Err(Error::InvalidSymbolPath(_))));
+        // HttpTransport cannot reach https:// servers, so they are refused up front.
+        assert!(matches!(
+            SymbolPath::parse("d:\\pdbs;srv*c:\\symbols*https://msdl.example.com/symbols"),
+            Err(Error::InvalidSymbolPath(element)) if element == "srv*c:\\symbols*https://msdl.example.com/symbols"
+        ));
+        assert!(SymbolPath::parse("").unwrap().elements.is_empty());
+    }
+
+    #[test]
+    fn download_and_cache() {
+        let dir = temp_dir("download");
+        let (data, codeview) = ntdll();
+        let key = codeview.symbol_server_key();
+        let mut transport = MockTransport::default();
+        let url = format!("http://sym/ntdll.pdb/{}/ntdll.pdb", key);
+        transport.files.insert(url.clone(), data.clone());
+        let path = format!("srv*{}*{}*http://sym", dir.join("near").display(), dir.join("far").display());
+        let found = resolver(&path, &transport, &dir.join("default")).find(&codeview).unwrap();
+        let near = dir.join("near").join("ntdll.pdb").join(&key).join("ntdll.pdb");
+        assert_eq!(found, Some(near.clone()));
+        assert_eq!(*transport.requests.borrow(), [url]);
+        // The download lands in the nearest cache and is copied into every other one.
+        assert_eq!(fs::read(&near).unwrap(), data);
+        assert_eq!(fs::read(dir.join("far").join("ntdll.pdb").join(&key).join("ntdll.pdb")).unwrap(), data);
+        assert!(!dir.join("default").exists());
+
+        // Now the caches have it, the server is not asked again.
+        let transport = MockTransport::default();
+        let found = resolver(&path, &transport, &dir.join("default")).find(&codeview).unwrap();
+        assert_eq!(found, Some(near));
+        assert!(transport.requests.borrow().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cache_element() {
+        let dir = temp_dir("cache");
+        let (data, codeview) = ntdll();
+        let key = codeview.symbol_server_key();
+        let mut transport = MockTransport::default();
+        transport
+            .files
+            .insert(format!("http://sym/ntdll.pdb/{}/ntdll.pdb", key), data);
+        // With no store named, downloads go to the default cache, which `cache*` also means.
+        let found = resolver("cache*;srv*http://sym", &transport, &dir)
+            .find(&codeview)
+            .unwrap();
+        assert_eq!(found, Some(dir.join("ntdll.pdb").join(&key).join("ntdll.pdb")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_fallback() {
+        let dir = temp_dir("compressed");
+        let (data, codeview) = ntdll();
+        let key = codeview.symbol_server_key();
+        let mut transport = MockTransport::default();
+        let base = format!("http://sym/ntdll.pdb/{}", key);
+        transport
+            .files
+            .insert(format!("{}/ntdll.pd_", base), cabinet("ntdll.pdb", &data));
+        let found = resolver("srv*http://sym", &transport, &dir).find(&codeview).unwrap();
+        let expanded = dir.join("ntdll.pdb").join(&key).join("ntdll.pdb");
+        assert_eq!(found, Some(expanded.clone()));
+        assert_eq!(fs::read(&expanded).unwrap(), data);
+        assert_eq!(
+            *transport.requests.borrow(),
+            [format!("{}/ntdll.pdb", base), format!("{}/ntdll.pd_", base)]
+        );
+
+        // A corrupt download is an error, not a miss.
+        let mut transport = MockTransport::default();
+        let mut corrupt = cabinet("ntdll.pdb", &data);
+        corrupt[0] = b'X';
+        transport.files.insert(format!("{}/ntdll.pd_", base), corrupt);
+        let cache = dir.join("corrupt");
+        match resolver("srv*http://sym", &transport, &cache).find(&codeview) {
+            Err(Error::Expand(url, crate::cab::Error::NotCabinet)) => assert_eq!(url, format!("{}/ntdll.pd_", base)),
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn server_pointers() {
+        let dir = temp_dir("pointer");
+        let (data, codeview) = ntdll();
+        let key = codeview.symbol_server_key();
+        let target = dir.join("elsewhere.pdb");
+        fs::write(&target, &data).unwrap();
+        let mut transport = MockTransport::default();
+        let pointer_url = format!("http://sym/ntdll.pdb/{}/file.ptr", key);
+        transport
+            .files
+            .insert(pointer_url.clone(), format!("PATH:{}", target.display()).into_bytes());
+        // A server's pointer is not even asked for by default.
+        let cache = dir.join("cache");
+        assert_eq!(resolver("srv*http://sym", &transport, &cache).find(&codeview).unwrap(), None);
+        assert!(!transport.requests.borrow().contains(&pointer_url));
+
+        let found = resolver("srv*http://sym", &transport, &cache)
+            .with_server_pointers(true)
+            .find(&codeview)
+            .unwrap();
+        assert_eq!(found, Some(target));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn local_store_pointers() {
+        let dir = temp_dir("local");
+        let (data, codeview) = ntdll();
+        let key = codeview.symbol_server_key();
+        let target = dir.join("elsewhere.pdb");
+        fs::write(&target, &data).unwrap();
+        let store = SymStore::create(dir.join("store"), false).unwrap();
+        let entry = Entry {
+            name: "ntdll.pdb".to_string(),
+            key: key.clone(),
+            source: Source::Pointer(target.display().to_string()),
+        };
+        store.add(&[entry], "", "", "").unwrap();
+        let transport = MockTransport::default();
+        let path = format!("srv*{}", store.root().display());
+        let found = resolver(&path, &transport, &dir.join("default")).find(&codeview).unwrap();
+        assert_eq!(found, Some(target));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_mismatch() {
+        let dir = temp_dir("mismatch");
+        let (data, codeview) = ntdll();
+        let CodeView::Rsds { guid, age, path } = codeview else {
+            unreachable!()
+        };
+        let mut transport = MockTransport::default();
+        let mut served = Vec::new();
+        for codeview in [
+            CodeView::Rsds { guid: guid ^ 1, age, path: path.clone() },
+            CodeView::Rsds { guid, age: age + 1, path: path.clone() },
+        ] {
+            let url = format!("http://sym/ntdll.pdb/{}/ntdll.pdb", codeview.symbol_server_key());
+            transport.files.insert(url, data.clone());
+            served.push(codeview);
+        }
+        for codeview in served {
+            let found = resolver("srv*http://sym", &transport, &dir).find(&codeview).unwrap();
+            assert_eq!(found, None);
+            let key_dir = dir.join("ntdll.pdb").join(codeview.symbol_server_key());
+            // Nothing is left behind, not even the partial download.
+            assert_eq!(fs::read_dir(&key_dir).unwrap().count(), 0);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn escape_names() {
+        let dir = temp_dir("names");
+        let transport = MockTransport::default();
+        let resolver = resolver("srv*http://sym", &transport, &dir);
+        for name in ["nt\u{1}dll.pdb", "ntdll\r\n.pdb", ".."] {
+            assert!(matches!(
+                resolver.find_by_guid(name, 1, 1),
+                Err(Error::InvalidName(_))
+            ));
+        }
+        assert!(transport.requests.borrow().is_empty());
+        assert_eq!(resolver.find_by_guid("d:\\x\\a b#?%.pdb", 1, 1).unwrap(), None);
+        let key = crate::symstore::guid_key(1, 1);
+        assert_eq!(
+            transport.requests.borrow()[0],
+            format!("http://sym/a%20b%23%3F%25.pdb/{}/a%20b%23%3F%25.pdb", key)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Answer one connection per response on a loopback port, returning the port and the
+    /// request lines received.
+    fn serve(responses: Vec<Vec<u8>>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                requests.push(read_line(&mut reader).unwrap());
+                while !read_line(&mut reader).unwrap().is_empty() {}
+                reader.get_mut().write_all(&response).unwrap();
+            }
+            requests
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn http_redirect() {
+        let (port, server) = serve(vec![
+            b"HTTP/1.1 302 Found\r\nLocation: /store/ntdll.pdb\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello".to_vec(),
+        ]);
+        let url = format!("http://127.0.0.1:{}/symbols/ntdll.pdb", port);
+        assert_eq!(HttpTransport::default().fetch(&url).unwrap(), Some(b"hello".to_vec()));
+        assert_eq!(
+            server.join().unwrap(),
+            ["GET /symbols/ntdll.pdb HTTP/1.1", "GET /store/ntdll.pdb HTTP/1.1"]
+        );
+    }
+
+    #[test]
+    fn http_chunked() {
+        let (port, server) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: x\r\n\r\n".to_vec(),
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n".to_vec(),
+        ]);
+        let transport = HttpTransport::default();
+        let url = format!("http://127.0.0.1:{}/a", port);
+        assert_eq!(transport.fetch(&url).unwrap(), Some(b"hello, world".to_vec()));
+        assert_eq!(transport.fetch(&url).unwrap(), None);
+        assert_eq!(
+            transport.fetch(&url).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_limits() {
+        let (port, server) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world".to_vec(),
+            b"HTTP/1.1 200 OK\r\n\r\nhello world".to_vec(),
+            b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello".to_vec(),
+            b"HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        ]);
+        let transport = HttpTransport {
+            max_size: 10,
+            ..Default::default()
+        };
+        let url = format!("http://127.0.0.1:{}/a", port);
+        // Too large, whether or not the length is given up front.
+        assert!(transport.fetch(&url).is_err());
+        assert!(transport.fetch(&url).is_err());
+        assert_eq!(
+            transport.fetch(&url).unwrap_err().kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+        assert!(transport.fetch(&url).unwrap_err().to_string().contains("500"));
+        server.join().unwrap();
+        assert_eq!(
+            transport.fetch("https://example.com/").unwrap_err().kind(),
+            std::io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(split_url("http://host").unwrap(), ("host", 80, "/"));
+        assert_eq!(split_url("HTTP://host/a").unwrap(), ("host", 80, "/a"));
+        assert_eq!(split_url("http://host:8080/a/b").unwrap(), ("host", 8080, "/a/b"));
+        assert!(split_url("http://host:port/").is_err());
+        assert_eq!(resolve_location("http://h/a/b", "http://o/c"), "http://o/c");
+        assert_eq!(resolve_location("http://h/a/b", "/c"), "http://h/c");
+        assert_eq!(resolve_location("http://h/a/b", "c"), "http://h/a/c");
+        assert_eq!(resolve_location("http://h", "c"), "http://h/c");
+        assert_eq!(resolve_location("HTTP://h/a/b", "/c"), "HTTP://h/c");
+        assert_eq!(percent_encode("a-Z_0.~ b/%"), "a-Z_0.~%20b%2F%25");
+    }
+}
@@ -0,0 +1,802 @@
+//! Symbol server index keys and SymStore-compatible directory trees.
+//!
+//! A symbol store keeps every file at `<root>/<name>/<key>/<name>`, where the key identifies one
+//! build of the file. When `<root>/index2.txt` exists the store is two-tier and the path gets an
+//! extra directory named after the first two characters of the file name:
+//! `<root>/<na>/<name>/<key>/<name>`. Instead of the file itself, the key directory may hold a
+//! `file.ptr` naming where the file really is. A file compressed into a cabinet keeps the
+//! directories of its real name but is stored with the last character of its name replaced by
+//! `_`, as in `ntdll.pdb/<key>/ntdll.pd_`.
+//!
+//! Every change to the store is a transaction recorded in `<root>/000Admin`:
+//!
+//! | File          | Contents                                                              |
+//! |---------------|-----------------------------------------------------------------------|
+//! | `lastid.txt`  | the last transaction id, as 10 decimal digits                         |
+//! | `server.txt`  | one line per live "add" transaction                                   |
+//! | `history.txt` | one line per transaction, "add" and "del" alike                       |
+//! | `<id>`        | one `"<name>\<key>","<source path>"` line per file the add stored     |
+//!
+//! An "add" line reads `id,add,file|ptr,MM/DD/YYYY,HH:MM:SS,"product","version","comment",` and a
+//! "del" line `id,del,deleted id`. Times are written in UTC.
+
+mod client;
+
+use crate::pdb::pdbstream::PDBStreamHeader;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+pub use client::{HttpTransport, Location, PathElement, SymbolPath, SymbolResolver, Transport};
+
+/// Result type alias for this module
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+/// All of the errors that could possible be returned from this module
+pub enum Error {
+    /// Error reading or writing a file of the store.
+    Io(PathBuf, std::io::Error),
+    /// A file name is empty or contains path separators.
+    InvalidName(String),
+    /// A key is empty or contains characters other than ASCII letters and digits.
+    InvalidKey(String),
+    /// The `file.ptr` of a file holds a message instead of a path, usually saying why the
+    /// file is unavailable.
+    PointerMessage(String),
+    /// No live transaction has this id.
+    UnknownTransaction(u32),
+    /// A line of the 000Admin files could not be parsed.
+    InvalidHistory(String),
+    /// Every transaction id has been used up.
+    TransactionIdOverflow,
+    /// A `srv*` symbol path element names no store or server.
+    InvalidSymbolPath(String),
+    /// Downloading from a symbol server failed.
+    Download(String, std::io::Error),
+    /// A compressed file downloaded from this URL could not be expanded.
+    Expand(String, crate::cab::Error),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(path, _) => write!(f, "could not access {}", path.display()),
+            Error::InvalidName(name) => write!(f, "invalid file name {:?}", name),
+            Error::InvalidKey(key) => write!(f, "invalid key {:?}", key),
+            Error::PointerMessage(message) => write!(f, "file is unavailable: {}", message),
+            Error::UnknownTransaction(id) => write!(f, "no transaction {:010}", id),
+            Error::InvalidHistory(line) => write!(f, "malformed transaction record {:?}", line),
+            Error::TransactionIdOverflow => write!(f, "no transaction ids left"),
+            Error::InvalidSymbolPath(element) => write!(f, "invalid symbol path element {:?}", element),
+            Error::Download(url, _) => write!(f, "could not download {}", url),
+            Error::Expand(url, _) => write!(f, "could not expand {}", url),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(_, e) | Error::Download(_, e) => Some(e),
+            Error::Expand(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Attach `path` to an io error.
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> Error + '_ {
+    move |e| Error::Io(path.to_path_buf(), e)
+}
+
+/// Index key of a PDB 7.00 file: its GUID as 32 upper-case hex digits, in the usual
+/// `{Data1}{Data2}{Data3}{Data4}` order, followed by its age in hex.
+pub fn guid_key(guid: u128, age: u32) -> String {
+    let data4 = ((guid >> 64) as u64).to_le_bytes();
+    let mut ret = format!(
+        "{:08X}{:04X}{:04X}",
+        guid as u32,
+        (guid >> 32) as u16,
+        (guid >> 48) as u16
+    );
+    for b in data4 {
+        ret.push_str(&format!("{:02X}", b));
+    }
+    ret.push_str(&format!("{:X}", age));
+    ret
+}
+
+/// `guid` as it is usually written, `{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}`.
+pub fn guid_string(guid: u128) -> String {
+    let data4 = ((guid >> 64) as u64).to_le_bytes();
+    let mut ret = format!(
+        "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-",
+        guid as u32,
+        (guid >> 32) as u16,
+        (guid >> 48) as u16,
+        data4[0],
+        data4[1]
+    );
+    for b in &data4[2..] {
+        ret.push_str(&format!("{:02X}", b));
+    }
+    ret.push('}');
+    ret
+}
+
+/// Index key of a PDB older than 7.00: its signature as 8 upper-case hex digits, followed by
+/// its age in hex.
+pub fn signature_key(signature: u32, age: u32) -> String {
+    format!("{:08X}{:X}", signature, age)
+}
+
+/// Where a file added to the store comes from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// Copy this file into the store.
+    File(PathBuf),
+    /// Only record a `file.ptr` pointing at this path, which is usually a network share.
+    Pointer(String),
+}
+
+/// A file to add to the store.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// File name, such as `ntdll.pdb`.
+    pub name: String,
+    pub key: String,
+    pub source: Source,
+}
+
+impl Entry {
+    /// Add the PDB at `path`, whose info stream has header `header`, under its own file name.
+    /// A compressed `.pd_` is added under the name of the `.pdb` it expands to.
+    pub fn pdb(path: impl AsRef<Path>, header: &PDBStreamHeader) -> Self {
+        let path = path.as_ref();
+        let mut name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if name.to_ascii_lowercase().ends_with(".pd_") {
+            name.pop();
+            name.push('b');
+        }
+        Entry {
+            name,
+            key: header.symbol_server_key(),
+            source: Source::File(path.to_path_buf()),
+        }
+    }
+}
+
+/// One line of `history.txt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transaction {
+    Add {
+        id: u32,
+        /// Whether the files were stored as `file.ptr` pointers.
+        pointer: bool,
+        /// `MM/DD/YYYY`.
+        date: String,
+        /// `HH:MM:SS`.
+        time: String,
+        product: String,
+        version: String,
+        comment: String,
+    },
+    Delete {
+        id: u32,
+        /// The "add" transaction whose files were deleted.
+        deleted: u32,
+    },
+}
+
+impl Transaction {
+    pub fn id(&self) -> u32 {
+        match self {
+            Transaction::Add { id, .. } | Transaction::Delete { id, .. } => *id,
+        }
+    }
+    fn parse(line: &str) -> Result<Self> {
+        let invalid = || Error::InvalidHistory(line.to_string());
+        let fields = split_fields(line);
+        let id = fields.first().and_then(|id| id.parse().ok()).ok_or_else(invalid)?;
+        let field = |i: usize| fields.get(i).cloned().ok_or_else(invalid);
+        match fields.get(1).map(String::as_str) {
+            Some("add") => Ok(Transaction::Add {
+                id,
+                pointer: field(2)? == "ptr",
+                date: field(3)?,
+                time: field(4)?,
+                product: field(5)?,
+                version: field(6)?,
+                comment: field(7)?,
+            }),
+            Some("del") => Ok(Transaction::Delete {
+                id,
+                deleted: field(2)?.parse().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for Transaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Transaction::Add {
+                id,
+                pointer,
+                date,
+                time,
+                product,
+                version,
+                comment,
+            } => write!(
+                f,
+                "{:010},add,{},{},{},\"{}\",\"{}\",\"{}\",",
+                id,
+                if *pointer { "ptr" } else { "file" },
+                date,
+                time,
+                product,
+                version,
+                comment
+            ),
+            Transaction::Delete { id, deleted } => write!(f, "{:010},del,{:010}", id, deleted),
+        }
+    }
+}
+
+/// Split a comma-separated line, removing the quotes around quoted fields.
+fn split_fields(line: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => ret.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    ret.push(field);
+    ret
+}
+
+/// A SymStore-compatible symbol store on disk.
+#[derive(Debug, Clone)]
+pub struct SymStore {
+    root: PathBuf,
+    two_tier: bool,
+}
+
+const ADMIN_DIR: &str = "000Admin";
+const INDEX2_FILE: &str = "index2.txt";
+const POINTER_FILE: &str = "file.ptr";
+
+impl SymStore {
+    /// Open an existing store, detecting a two-tier layout from `index2.txt`.
+    pub fn open(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        fs::metadata(&root).map_err(io_error(&root))?;
+        let two_tier = root.join(INDEX2_FILE).exists();
+        Ok(SymStore { root, two_tier })
+    }
+    /// Create a store at `root`, or open it if it already exists. A new store is two-tier
+    /// when `two_tier` is set; an existing one keeps its layout.
+    pub fn create(root: impl AsRef<Path>, two_tier: bool) -> Result<Self> {
+        let root = root.as_ref();
+        if root.join(ADMIN_DIR).exists() {
+            return Self::open(root);
+        }
+        let admin = root.join(ADMIN_DIR);
+        fs::create_dir_all(&admin).map_err(io_error(&admin))?;
+        if two_tier {
+            let index2 = root.join(INDEX2_FILE);
+            fs::write(&index2, b"").map_err(io_error(&index2))?;
+        }
+        Self::open(root)
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// Whether files live under an extra directory named after their first two characters.
+    pub fn is_two_tier(&self) -> bool {
+        self.two_tier
+    }
+    /// The directory holding the file `name` with key `key`.
+    pub fn key_dir(&self, name: &str, key: &str) -> Result<PathBuf> {
+        check_name(name)?;
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(Error::InvalidKey(key.to_string()));
+        }
+        let mut ret = self.root.clone();
+        if self.two_tier {
+            ret.push(name.get(..2).unwrap_or(name));
+        }
+        ret.push(name);
+        ret.push(key);
+        Ok(ret)
+    }
+    /// Find the file `name` with key `key`, or its compressed form, following a `file.ptr` if
+    /// there is one. Names are tried as given and then in lower case, since stores are often populated on
+    /// case-insensitive file systems.
+    pub fn lookup(&self, name: &str, key: &str) -> Result<Option<PathBuf>> {
+        let lower = name.to_lowercase();
+        for (name, key) in [(name, key.to_string()), (lower.as_str(), key.to_uppercase())] {
+            let dir = self.key_dir(name, &key)?;
+            for file in [dir.join(name), dir.join(compressed_name(name))] {
+                if file.is_file() {
+                    return Ok(Some(file));
+                }
+            }
+            let pointer = dir.join(POINTER_FILE);
+            if pointer.is_file() {
+                let target = fs::read_to_string(&pointer).map_err(io_error(&pointer))?;
+                return read_pointer(&target).map(Some);
+            }
+        }
+        Ok(None)
+    }
+    /// Add `entries` to the store as one transaction, returning its id.
+    pub fn add(&self, entries: &[Entry], product: &str, version: &str, comment: &str) -> Result<u32> {
+        for entry in entries {
+            self.key_dir(&entry.name, &entry.key)?;
+        }
+        let id = self.next_id()?;
+        let mut index = String::new();
+        for entry in entries {
+            let dir = self.key_dir(&entry.name, &entry.key)?;
+            fs::create_dir_all(&dir).map_err(io_error(&dir))?;
+            let source = match &entry.source {
+                Source::File(path) => {
+                    let target = dir.join(stored_name(&entry.name, path));
+                    fs::copy(path, &target).map_err(io_error(path))?;
+                    path.display().to_string()
+                }
+                Source::Pointer(path) => {
+                    let target = dir.join(POINTER_FILE);
+                    fs::write(&target, format!("PATH:{}", path)).map_err(io_error(&target))?;
+                    path.clone()
+                }
+            };
+            index.push_str(&format!("\"{}\\{}\",\"{}\"\r\n", entry.name, entry.key, source));
+        }
+        let pointer = entries
+            .iter()
+            .any(|entry| matches!(entry.source, Source::Pointer(_)));
+        let (date, time) = utc_now();
+        let transaction = Transaction::Add {
+            id,
+            pointer,
+            date,
+            time,
+            product: product.to_string(),
+            version: version.to_string(),
+            comment: comment.to_string(),
+        };
+        let transaction_file = self.admin_file(&format!("{:010}", id));
+        fs::write(&transaction_file, index).map_err(io_error(&transaction_file))?;
+        self.append_line("server.txt", &transaction)?;
+        self.append_line("history.txt", &transaction)?;
+        self.write_last_id(id)?;
+        Ok(id)
+    }
+    /// Delete the files added by transaction `id`, except those another live transaction
+    /// also added, and record the deletion.
+    pub fn delete(&self, id: u32) -> Result<u32> {
+        let live = self.transactions()?;
+        if !live.iter().any(|t| t.id() == id) {
+            return Err(Error::UnknownTransaction(id));
+        }
+        let mut still_used = Vec::new();
+        for other in live.iter().filter(|t| t.id() != id) {
+            still_used.extend(self.files_of(other.id())?);
+        }
+        for (name, key) in self.files_of(id)? {
+            if still_used.contains(&(name.clone(), key.clone())) {
+                continue;
+            }
+            let dir = self.key_dir(&name, &key)?;
+            if dir.exists() {
+                fs::remove_dir_all(&dir).map_err(io_error(&dir))?;
+            }
+            // Drop the name directories too once they are empty; failing that is harmless.
+            if let Some(name_dir) = dir.parent() {
+                let _ = fs::remove_dir(name_dir);
+                if self.two_tier {
+                    if let Some(prefix_dir) = name_dir.parent() {
+                        let _ = fs::remove_dir(prefix_dir);
+                    }
+                }
+            }
+        }
+        let server = self.admin_file("server.txt");
+        let remaining: String = live
+            .iter()
+            .filter(|t| t.id() != id)
+            .map(|t| format!("{}\r\n", t))
+            .collect();
+        fs::write(&server, remaining).map_err(io_error(&server))?;
+        let delete_id = self.next_id()?;
+        self.append_line("history.txt", &Transaction::Delete {
+            id: delete_id,
+            deleted: id,
+        })?;
+        self.write_last_id(delete_id)?;
+        Ok(delete_id)
+    }
+    /// The live "add" transactions, from `server.txt`.
+    pub fn transactions(&self) -> Result<Vec<Transaction>> {
+        self.read_transactions("server.txt")
+    }
+    /// Every transaction ever made, from `history.txt`.
+    pub fn history(&self) -> Result<Vec<Transaction>> {
+        self.read_transactions("history.txt")
+    }
+    /// The `(name, key)` of every file added by transaction `id`.
+    pub fn files_of(&self, id: u32) -> Result<Vec<(String, String)>> {
+        let path = self.admin_file(&format!("{:010}", id));
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(Error::UnknownTransaction(id)),
+            Err(e) => return Err(Error::Io(path, e)),
+        };
+        contents
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let fields = split_fields(line);
+                fields
+                    .first()
+                    .and_then(|file| file.split_once('\\'))
+                    .map(|(name, key)| (name.to_string(), key.to_string()))
+                    .ok_or_else(|| Error::InvalidHistory(line.to_string()))
+            })
+            .collect()
+    }
+
+    fn admin_file(&self, name: &str) -> PathBuf {
+        self.root.join(ADMIN_DIR).join(name)
+    }
+    fn read_transactions(&self, file: &str) -> Result<Vec<Transaction>> {
+        let path = self.admin_file(file);
+        match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(Transaction::parse)
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(Error::Io(path, e)),
+        }
+    }
+    fn append_line(&self, file: &str, transaction: &Transaction) -> Result<()> {
+        let path = self.admin_file(file);
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| write!(f, "{}\r\n", transaction))
+            .map_err(io_error(&path))
+    }
+    fn next_id(&self) -> Result<u32> {
+        let path = self.admin_file("lastid.txt");
+        let last = match fs::read_to_string(&path) {
+            Ok(contents) => contents
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| Error::InvalidHistory(contents.clone()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(Error::Io(path, e)),
+        };
+        last.checked_add(1).ok_or(Error::TransactionIdOverflow)
+    }
+    fn write_last_id(&self, id: u32) -> Result<()> {
+        let path = self.admin_file("lastid.txt");
+        fs::write(&path, format!("{:010}\r\n", id)).map_err(io_error(&path))
+    }
+}
+
+/// A file name must be a single path component, without control characters.
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\\'])
+        || name.chars().any(char::is_control)
+    {
+        return Err(Error::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// The name a file `name` has once compressed into a cabinet, such as `ntdll.pd_` for
+/// `ntdll.pdb`.
+fn compressed_name(name: &str) -> String {
+    let mut ret = name.to_string();
+    ret.pop();
+    ret.push('_');
+    ret
+}
+
+/// The name to store the file at `path` under in the directory of `name`: its compressed
+/// name if `path` is the compressed file, otherwise `name` itself.
+fn stored_name(name: &str, path: &Path) -> String {
+    let compressed = compressed_name(name);
+    match path.file_name() {
+        Some(file) if file.to_string_lossy().eq_ignore_ascii_case(&compressed) => compressed,
+        _ => name.to_string(),
+    }
+}
+
+/// Resolve the contents of a `file.ptr`: `PATH:<path>`, `MSG:<message>`, or a bare path.
+fn read_pointer(contents: &str) -> Result<PathBuf> {
+    let contents = contents.trim();
+    if let Some(message) = contents.strip_prefix("MSG:") {
+        return Err(Error::PointerMessage(message.trim().to_string()));
+    }
+    Ok(PathBuf::from(contents.strip_prefix("PATH:").unwrap_or(contents)))
+}
+
+/// The current UTC date and time as `MM/DD/YYYY` and `HH:MM:SS`.
+fn utc_now() -> (String, String) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // Civil date from days since 1970-01-01, counting in 400-year eras starting in March.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (
+        format!("{:02}/{:02}/{:04}", month, day, year),
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// The info stream header of `pdb/ntdll.pdb`.
+    const NTDLL_HEADER: &[u8] = &[
+        0x94, 0x2e, 0x31, 0x01, 0xd6, 0xd1, 0x80, 0xa2, 0x03, 0x00, 0x00, 0x00, 0xd5, 0x4e, 0xef, 0x96,
+        0x40, 0x37, 0xaa, 0x2d, 0xa5, 0x1d, 0x4a, 0x42, 0x12, 0xea, 0x4b, 0x2c,
+    ];
+    const NTDLL_KEY: &str = "96EF4ED537402DAAA51D4A4212EA4B2C3";
+
+    /// An empty directory for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pdbparser-symstore-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ntdll_header() -> PDBStreamHeader {
+        PDBStreamHeader::load(&mut Cursor::new(NTDLL_HEADER)).unwrap()
+    }
+
+    #[test]
+    fn keys() {
+        let header = ntdll_header();
+        assert_eq!(guid_key(header.unique_id(), header.age()), NTDLL_KEY);
+        assert_eq!(header.symbol_server_key(), NTDLL_KEY);
+        assert_eq!(guid_string(header.unique_id()), "{96EF4ED5-3740-2DAA-A51D-4A4212EA4B2C}");
+        assert_eq!(signature_key(0x3a5f0b21, 0x1c), "3A5F0B211C");
+    }
+
+    #[test]
+    fn add_lookup_delete() {
+        let dir = temp_dir("add");
+        let pdb = dir.join("ntdll.pdb");
+        fs::write(&pdb, b"pdb").unwrap();
+        let store = SymStore::create(dir.join("store"), false).unwrap();
+        assert!(!store.is_two_tier());
+        let entries = [
+            Entry::pdb(&pdb, &ntdll_header()),
+            Entry {
+                name: "kernel32.pdb".to_string(),
+                key: "0123ABCD1".to_string(),
+                source: Source::Pointer("\\\\server\\share\\kernel32.pdb".to_string()),
+            },
+        ];
+        assert_eq!(store.add(&entries, "product", "1.0", "first").unwrap(), 1);
+        let stored = store.root().join("ntdll.pdb").join(NTDLL_KEY).join("ntdll.pdb");
+        assert_eq!(store.lookup("ntdll.pdb", NTDLL_KEY).unwrap(), Some(stored.clone()));
+        assert_eq!(fs::read(&stored).unwrap(), b"pdb");
+        // Stores filled on Windows are matched whatever the case of the name and key.
+        assert_eq!(
+            store.lookup("NTDLL.PDB", &NTDLL_KEY.to_lowercase()).unwrap(),
+            Some(stored)
+        );
+        assert_eq!(
+            store.lookup("kernel32.pdb", "0123ABCD1").unwrap(),
+            Some(PathBuf::from("\\\\server\\share\\kernel32.pdb"))
+        );
+        assert_eq!(store.lookup("kernel32.pdb", "0123ABCD2").unwrap(), None);
+        assert_eq!(
+            store.files_of(1).unwrap(),
+            [
+                ("ntdll.pdb".to_string(), NTDLL_KEY.to_string()),
+                ("kernel32.pdb".to_string(), "0123ABCD1".to_string())
+            ]
+        );
+        let transactions = store.transactions().unwrap();
+        assert!(matches!(
+            &transactions[..],
+            [Transaction::Add { id: 1, pointer: true, product, comment, .. }]
+                if product == "product" && comment == "first"
+        ));
+
+        assert_eq!(store.delete(1).unwrap(), 2);
+        assert_eq!(store.lookup("ntdll.pdb", NTDLL_KEY).unwrap(), None);
+        assert!(!store.root().join("ntdll.pdb").exists());
+        assert!(store.transactions().unwrap().is_empty());
+        let history = store.history().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1], Transaction::Delete { id: 2, deleted: 1 });
+        assert!(matches!(store.delete(1), Err(Error::UnknownTransaction(1))));
+        assert_eq!(
+            fs::read_to_string(store.admin_file("lastid.txt")).unwrap(),
+            "0000000002\r\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn delete_keeps_shared_files() {
+        let dir = temp_dir("shared");
+        let pdb = dir.join("ntdll.pdb");
+        fs::write(&pdb, b"pdb").unwrap();
+        let store = SymStore::create(dir.join("store"), false).unwrap();
+        let entries = [Entry::pdb(&pdb, &ntdll_header())];
+        let first = store.add(&entries, "", "", "").unwrap();
+        let second = store.add(&entries, "", "", "").unwrap();
+        store.delete(first).unwrap();
+        assert!(store.lookup("ntdll.pdb", NTDLL_KEY).unwrap().is_some());
+        store.delete(second).unwrap();
+        assert!(store.lookup("ntdll.pdb", NTDLL_KEY).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn two_tier() {
+        let dir = temp_dir("two-tier");
+        let store = SymStore::create(&dir, true).unwrap();
+        assert!(store.is_two_tier());
+        assert!(SymStore::open(&dir).unwrap().is_two_tier());
+        // Creating an existing store keeps its layout.
+        assert!(SymStore::create(&dir, false).unwrap().is_two_tier());
+        assert_eq!(
+            store.key_dir("ntdll.pdb", NTDLL_KEY).unwrap(),
+            dir.join("nt").join("ntdll.pdb").join(NTDLL_KEY)
+        );
+        let pdb = dir.join("ntdll.pdb.in");
+        fs::write(&pdb, b"pdb").unwrap();
+        let entry = Entry {
+            name: "ntdll.pdb".to_string(),
+            key: NTDLL_KEY.to_string(),
+            source: Source::File(pdb),
+        };
+        store.add(&[entry], "", "", "").unwrap();
+        assert_eq!(
+            store.lookup("ntdll.pdb", NTDLL_KEY).unwrap(),
+            Some(dir.join("nt").join("ntdll.pdb").join(NTDLL_KEY).join("ntdll.pdb"))
+        );
+        store.delete(1).unwrap();
+        assert!(!dir.join("nt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_pdb() {
+        let dir = temp_dir("compressed");
+        let compressed = dir.join("ntdll.pd_");
+        fs::write(&compressed, b"cab").unwrap();
+        let entry = Entry::pdb(&compressed, &ntdll_header());
+        assert_eq!(entry.name, "ntdll.pdb");
+        let store = SymStore::create(dir.join("store"), false).unwrap();
+        store.add(&[entry], "", "", "").unwrap();
+        let stored = store.root().join("ntdll.pdb").join(NTDLL_KEY).join("ntdll.pd_");
+        assert_eq!(fs::read(&stored).unwrap(), b"cab");
+        assert_eq!(store.lookup("ntdll.pdb", NTDLL_KEY).unwrap(), Some(stored));
+        assert_eq!(
+            store.files_of(1).unwrap(),
+            [("ntdll.pdb".to_string(), NTDLL_KEY.to_string())]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pointer_messages() {
+        let dir = temp_dir("pointer");
+        let store = SymStore::create(&dir, false).unwrap();
+        let key_dir = store.key_dir("ntdll.pdb", NTDLL_KEY).unwrap();
+        fs::create_dir_all(&key_dir).unwrap();
+        fs::write(key_dir.join(POINTER_FILE), "MSG: withdrawn").unwrap();
+        match store.lookup("ntdll.pdb", NTDLL_KEY) {
+            Err(Error::PointerMessage(message)) => assert_eq!(message, "withdrawn"),
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::write(key_dir.join(POINTER_FILE), "d:\\symbols\\ntdll.pdb\r\n").unwrap();
+        assert_eq!(
+            store.lookup("ntdll.pdb", NTDLL_KEY).unwrap(),
+            Some(PathBuf::from("d:\\symbols\\ntdll.pdb"))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reject_bad_names() {
+        let dir = temp_dir("names");
+        let store = SymStore::create(&dir, false).unwrap();
+        for name in ["", ".", "..", "../ntdll.pdb", "sub\\ntdll.pdb", "ntdll\r\n.pdb", "nt\0dll.pdb"] {
+            assert!(matches!(store.key_dir(name, NTDLL_KEY), Err(Error::InvalidName(_))), "{:?}", name);
+        }
+        for key in ["", "..", "ABC/DEF", "ABC DEF"] {
+            assert!(matches!(store.key_dir("ntdll.pdb", key), Err(Error::InvalidKey(_))), "{:?}", key);
+        }
+        let entry = Entry {
+            name: "..".to_string(),
+            key: NTDLL_KEY.to_string(),
+            source: Source::Pointer("x".to_string()),
+        };
+        assert!(matches!(store.add(&[entry], "", "", ""), Err(Error::InvalidName(_))));
+        // Nothing was recorded for the rejected transaction.
+        assert!(store.history().unwrap().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transaction_id_overflow() {
+        let dir = temp_dir("overflow");
+        let store = SymStore::create(&dir, false).unwrap();
+        store.write_last_id(u32::MAX).unwrap();
+        let entry = Entry {
+            name: "ntdll.pdb".to_string(),
+            key: NTDLL_KEY.to_string(),
+            source: Source::Pointer("x".to_string()),
+        };
+        assert!(matches!(store.add(&[entry], "", "", ""), Err(Error::TransactionIdOverflow)));
+        assert!(!dir.join("ntdll.pdb").exists());
+        fs::write(store.admin_file("lastid.txt"), "garbage").unwrap();
+        assert!(matches!(store.next_id(), Err(Error::InvalidHistory(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_history() {
+        let line = "0000000003,add,file,10/17/2026,19:05:02,\"pdbparser\",\"1.0\",\"a, b\",";
+        let transaction = Transaction::parse(line).unwrap();
+        assert_eq!(
+            transaction,
+            Transaction::Add {
+                id: 3,
+                pointer: false,
+                date: "10/17/2026".to_string(),
+                time: "19:05:02".to_string(),
+                product: "pdbparser".to_string(),
+                version: "1.0".to_string(),
+                comment: "a, b".to_string(),
+            }
+        );
+        assert_eq!(transaction.to_string(), line);
+        let delete = Transaction::parse("0000000004,del,0000000003").unwrap();
+        assert_eq!(delete, Transaction::Delete { id: 4, deleted: 3 });
+        assert_eq!(delete.to_string(), "0000000004,del,0000000003");
+        for line in ["", "x,add", "0000000001,mod,1", "0000000001,del,x", "0000000001,add,file"] {
+            assert!(matches!(Transaction::parse(line), Err(Error::InvalidHistory(_))), "{:?}", line);
+        }
+    }
+}
@@ -1,19 +1,158 @@
 
 macro_rules! consume {
     ($reader:expr, $ty:ty, $field:expr) => {{
+        use $crate::util::ReadField as _;
         let mut tmp = [0_u8; std::mem::size_of::<$ty>()];
-        $reader
-            .read_exact(&mut tmp)
-            .map(|_| <$ty>::from_le_bytes(tmp))
-            .map_err(|x| std::io::Error::new(std::io::ErrorKind::Other, "Consumption Error"))
+        $reader.read_field(&mut tmp, $field).map(|_| <$ty>::from_le_bytes(tmp))
     }};
     ($reader:expr, $size:expr, $field:expr) => {{
+        use $crate::util::ReadField as _;
         let mut tmp = [0_u8; $size];
-        $reader
-            .read_exact(&mut tmp)
-            .map(|_| tmp)
-            .map_err(|x| std::io::Error::new(std::io::ErrorKind::Other, "Consumption Error"))
+        $reader.read_field(&mut tmp, $field).map(|_| tmp)
     }};
 }
 
-pub(crate) use consume;
\ No newline at end of file
+pub(crate) use consume;
+
+/// A field could not be read in full, either because the data ended or because the reader
+/// failed.
+#[derive(Debug)]
+pub struct FieldError {
+    field: &'static str,
+    bytes_read: usize,
+    source: Option<std::io::Error>,
+}
+
+impl FieldError {
+    /// Name of the field being decoded.
+    pub fn field(&self) -> &'static str {
+        self.field
+    }
+    /// Bytes of the field that were read before the failure; the field starts this many bytes
+    /// before the reader's current position.
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.source {
+            None => write!(f, "truncated while reading {}", self.field),
+            Some(_) => write!(f, "could not read {}", self.field),
+        }
+    }
+}
+
+impl std::error::Error for FieldError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source.as_ref().map(|e| e as _)
+    }
+}
+
+/// Read a whole field, remembering its name for the error.
+pub(crate) trait ReadField: std::io::Read {
+    /// Fill `buf`, naming `field` if it cannot be filled.
+    fn read_field(&mut self, buf: &mut [u8], field: &'static str) -> Result<(), FieldError> {
+        let mut bytes_read = 0;
+        while bytes_read < buf.len() {
+            match self.read(&mut buf[bytes_read..]) {
+                Ok(0) => break,
+                Ok(n) => bytes_read += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    return Err(FieldError {
+                        field,
+                        bytes_read,
+                        source: Some(e),
+                    })
+                }
+            }
+        }
+        if bytes_read < buf.len() {
+            return Err(FieldError {
+                field,
+                bytes_read,
+                source: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl<R: std::io::Read + ?Sized> ReadField for R {}
+
+/// Read exactly `len` bytes of `field`. `len` usually comes from the file itself, so the buffer
+/// grows as data actually arrives instead of being allocated up front.
+pub(crate) fn consume_bytes(
+    reader: &mut impl std::io::Read,
+    len: u64,
+    field: &'static str,
+) -> Result<Vec<u8>, FieldError> {
+    use std::io::Read;
+    let mut ret = Vec::new();
+    let result = reader.take(len).read_to_end(&mut ret);
+    if result.is_err() || (ret.len() as u64) < len {
+        return Err(FieldError {
+            field,
+            bytes_read: ret.len(),
+            source: result.err(),
+        });
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    /// Hands out `data`, then fails.
+    struct Failing<'a>(&'a [u8]);
+
+    impl Read for Failing<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::Error::other("device gone"));
+            }
+            let len = self.0.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn consume_fields() {
+        let mut reader = Cursor::new([1, 0, 0, 0, 2, 0]);
+        assert_eq!(consume!(reader, u32, "first").unwrap(), 1);
+        let e = consume!(reader, u32, "second").unwrap_err();
+        assert_eq!(e.field(), "second");
+        assert_eq!(e.bytes_read(), 2);
+        assert_eq!(e.to_string(), "truncated while reading second");
+        assert!(std::error::Error::source(&e).is_none());
+    }
+
+    #[test]
+    fn reader_errors() {
+        let e = consume!(Failing(&[1, 2, 3]), 8, "signature").unwrap_err();
+        assert_eq!(e.field(), "signature");
+        assert_eq!(e.bytes_read(), 3);
+        assert_eq!(e.to_string(), "could not read signature");
+        let source = std::error::Error::source(&e).unwrap();
+        assert_eq!(source.to_string(), "device gone");
+        assert!(source.source().is_none());
+    }
+
+    #[test]
+    fn consume_byte_runs() {
+        let mut reader = Cursor::new(b"hello world");
+        assert_eq!(consume_bytes(&mut reader, 5, "greeting").unwrap(), b"hello");
+        let e = consume_bytes(&mut reader, 100, "rest").unwrap_err();
+        assert_eq!((e.field(), e.bytes_read()), ("rest", 6));
+        assert_eq!(e.to_string(), "truncated while reading rest");
+        let e = consume_bytes(&mut Failing(b"ab"), 4, "tail").unwrap_err();
+        assert_eq!(e.to_string(), "could not read tail");
+        assert_eq!(std::error::Error::source(&e).unwrap().to_string(), "device gone");
+    }
+}
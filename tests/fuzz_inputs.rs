@@ -0,0 +1,62 @@
+//! Replay the fuzzing seeds and past crash inputs in `fuzz/` through the fuzz targets' code
+//! paths, so a fixed crash cannot come back unnoticed without cargo-fuzz.
+
+use pdbparser::msf::{self, ParseLimits};
+use pdbparser::pdb;
+use std::io::Cursor;
+use std::path::Path;
+
+/// What `fuzz_targets/load.rs` and `fuzz_targets/load_unlimited.rs` do with an input.
+fn load(data: &[u8], limits: ParseLimits) {
+    let _ = msf::check(&mut Cursor::new(data), &limits);
+    let mut reader = Cursor::new(data);
+    let Ok(msf) = msf::MSF::load_with_limits(&mut reader, limits) else {
+        return;
+    };
+    if let Ok(pdb) = pdb::PDB::pdb_stream(&mut reader, &msf) {
+        let _ = pdb.string_table(&mut reader, &msf);
+        let _ = pdb.link_info(&mut reader, &msf);
+        for source in pdb.injected_sources(&mut reader, &msf).unwrap_or_default() {
+            let _ = pdb.read_injected_source(&mut reader, &msf, &source);
+        }
+    }
+    for stream in 0..msf.num_streams().min(64) {
+        let _ = msf.read_stream(&mut reader, stream);
+    }
+}
+
+/// Every file in `fuzz/<dir>`.
+fn inputs(dir: &str) -> Vec<(String, Vec<u8>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz").join(dir);
+    let mut ret: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, std::fs::read(&path).unwrap())
+        })
+        .collect();
+    ret.sort();
+    assert!(!ret.is_empty(), "no inputs in {}", dir.display());
+    ret
+}
+
+#[test]
+fn regressions() {
+    for (name, data) in inputs("regressions") {
+        eprintln!("regressions/{}", name);
+        load(&data, ParseLimits::default());
+        load(&data, ParseLimits::unlimited());
+    }
+}
+
+#[test]
+fn seeds() {
+    for (name, data) in inputs("seeds") {
+        eprintln!("seeds/{}", name);
+        load(&data, ParseLimits::default());
+        load(&data, ParseLimits::unlimited());
+        // The seeds are well-formed containers, or they would not get the fuzzer far.
+        msf::MSF::load(&mut Cursor::new(&data[..])).unwrap();
+    }
+}